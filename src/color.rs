@@ -1,140 +1,162 @@
 use std::env;
 use term::terminfo::TermInfo;
 
-use crate::regent_color; 
 
 //base on preference
 
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum TerminalColor {
     Colors256,
     Colors16,
     TrueColors,
 }
 
-//color enum -> description
-pub enum Color {
-    Green,
-    Gray,
-    Orange,
-    Invert,
-    Red,
-    Blue,
-    Purple,
-    Cyan,
-    Yellow,
-    YellowBg,
-    RedBg,
-    OrangeBg,
-    NonText,
-    Reset,
-}
 
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Rgb(pub u8, pub u8, pub u8);
 
 
+//the colors xterm uses for the 16 ANSI colors, terminals differ but they are close enough to find
+//the nearest one
+const XTERM_16: [Rgb; 16] = [
+    Rgb(0x00, 0x00, 0x00), Rgb(0xcd, 0x00, 0x00), Rgb(0x00, 0xcd, 0x00), Rgb(0xcd, 0xcd, 0x00),
+    Rgb(0x00, 0x00, 0xee), Rgb(0xcd, 0x00, 0xcd), Rgb(0x00, 0xcd, 0xcd), Rgb(0xe5, 0xe5, 0xe5),
+    Rgb(0x7f, 0x7f, 0x7f), Rgb(0xff, 0x00, 0x00), Rgb(0x00, 0xff, 0x00), Rgb(0xff, 0xff, 0x00),
+    Rgb(0x5c, 0x5c, 0xff), Rgb(0xff, 0x00, 0xff), Rgb(0x00, 0xff, 0xff), Rgb(0xff, 0xff, 0xff),
+];
 
+//the levels of each channel in the 6x6x6 color cube at 16..=231
+const CUBE_LEVELS: [u8; 6] = [0x00, 0x5f, 0x87, 0xaf, 0xd7, 0xff];
 
 
-impl Color {
-    pub fn has_bg_color(self) -> bool {
-        use Color::*;
-        matches!(self, YellowBg | RedBg | OrangeBg)
+impl Rgb {
+    //"#rrggbb"
+    pub fn parse(s: &str) -> Option<Rgb> {
+        let hex = s.strip_prefix('#').filter(|h| h.len() == 6 && h.is_ascii())?;
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        Some(Rgb(channel(0)?, channel(2)?, channel(4)?))
+    }
+
+    //the color of an index of the xterm 256 color palette
+    pub fn from_256(c: u8) -> Rgb {
+        match c {
+            0..=15 => XTERM_16[c as usize],
+            16..=231 => {
+                let c = c - 16;
+                Rgb(CUBE_LEVELS[(c / 36) as usize], CUBE_LEVELS[(c / 6 % 6) as usize], CUBE_LEVELS[(c % 6) as usize])
+            }
+            _ => {
+                let level = 8 + (c - 232) * 10;
+                Rgb(level, level, level)
+            }
+        }
     }
-}
 
+    fn distance(self, other: Rgb) -> u32 {
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+        d(self.0, other.0) + d(self.1, other.1) + d(self.2, other.2)
+    }
 
-fn sequence_for_color_16(color: Color, ) -> &'static [u8] {
-    use Color::*; 
-    match color {
-        Reset => b"\x1b[39;0m",
-        Red => b"\x1b[91m",
-        Green => b"\x1b[32m",
-        Gray => b"\x1b[90m",
-        Yellow => b"\x1b[93m",
-        Orange => b"\x1b[33m", // No orange color in 16 colors. Use darker yellow instead
-        Blue => b"\x1b[94m",
-        Purple => b"\x1b[95m",
-        Cyan => b"\x1b[96m",
-        RedBg => b"\x1b[97m\x1b[41m",
-        YellowBg => b"\x1b[103m\x1b[30m",
-        OrangeBg => b"\x1b[107m\x1b[30m", // White BG color is used instead of orange
-        NonText => b"\x1b[37m",
-        Invert => b"\x1b[7m",
+    //the nearest color of the cube or the gray ramp. 0..=15 are left out since terminals let
+    //users change them
+    pub fn nearest_256(self) -> u8 {
+        let level = |v: u8| match v {
+            0..=47 => 0,
+            48..=114 => 1,
+            v => (v - 35) / 40,
+        };
+        let cube = 16 + 36 * level(self.0) + 6 * level(self.1) + level(self.2);
+
+        let avg = (self.0 as u32 + self.1 as u32 + self.2 as u32) / 3;
+        let gray = 232 + (avg.saturating_sub(3) / 10).min(23) as u8;
+
+        if self.distance(Rgb::from_256(gray)) < self.distance(Rgb::from_256(cube)) {
+            gray
+        } else {
+            cube
+        }
     }
-}
 
-fn sequence_for_color_256(color: Color) -> &'static [u8] {
-    use Color::*; 
-
-    match color {
-        Reset => b"\x1b[39;0m",
-        Red => b"\x1b[91m",
-        Green => b"\x1b[32m",
-        Gray => b"\x1b[90m",
-        Yellow => b"\x1b[93m",
-        Orange => b"\x1b[33m", // No orange color in 16 colors. Use darker yellow instead
-        Blue => b"\x1b[94m",
-        Purple => b"\x1b[95m",
-        Cyan => b"\x1b[96m",
-        RedBg => b"\x1b[97m\x1b[41m",
-        YellowBg => b"\x1b[103m\x1b[30m",
-        OrangeBg => b"\x1b[107m\x1b[30m", // White BG color is used instead of orange
-        NonText => b"\x1b[37m",
-        Invert => b"\x1b[7m", 
+    pub fn nearest_16(self) -> u8 {
+        (0..16u8).min_by_key(|c| self.distance(XTERM_16[*c as usize])).unwrap_or(0)
     }
 }
 
 
-fn true_colors_sequence(color: Color) -> &'static[u8] {
-    use Color::*; 
-
-    match color {
-        Invert => b"\x1b[7m",
-        Reset => concat!(
-            "\x1b[39;0m",
-            regent_color!(fg, 0xfb, 0xf1, 0xc7), 
-            regent_color!(bg, 0x28, 0x28, 0x28),
-        ).as_bytes(),
-        Red => regent_color!(fg, 0xfb, 0x49, 0x34).as_bytes(),
-        Green => regent_color!(fg, 0xb8, 0xbb, 0x26).as_bytes(),
-        Gray => regent_color!(fg, 0xa8, 0x99, 0x84).as_bytes(),
-        Yellow => regent_color!(fg, 0xfa, 0xbd, 0x2f).as_bytes(),
-        Orange => regent_color!(fg, 0xfe, 0x80, 0x19).as_bytes(),
-        Blue => regent_color!(fg, 0x83, 0xa5, 0x98).as_bytes(),
-        Purple => regent_color!(fg, 0xd3, 0x86, 0x9b).as_bytes(),
-        Cyan => regent_color!(fg, 0x8e, 0xc0, 0x7c).as_bytes(),
-        RedBg => concat!(
-            regent_color!(fg, 0xfb, 0xf1, 0xc7),
-            regent_color!(bg, 0xcc, 0x24, 0x1d),
-        ).as_bytes(), 
-        YellowBg => concat!(
-            regent_color!(fg, 0x28, 0x28, 0x28),
-            regent_color!(bg, 0xd7, 0x99, 0x21),
-        )
-        .as_bytes(),
-        OrangeBg => concat!(
-            regent_color!(fg, 0x28, 0x28, 0x28),
-            regent_color!(bg, 0xd6, 0x5d, 0x0e),
-        )
-        .as_bytes(),
-        NonText => regent_color!(fg, 0x66, 0x5c, 0x54).as_bytes(),
-    }
+const COLOR_NAMES: [&str; 16] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+    "bright-black", "bright-red", "bright-green", "bright-yellow", "bright-blue", "bright-magenta", "bright-cyan", "bright-white",
+];
+
+
+//the index of one of the 16 ANSI colors from its name, like "bright-red"
+pub fn color_16_from_name(name: &str) -> Option<u8> {
+    COLOR_NAMES.iter().position(|n| *n == name).map(|i| i as u8)
 }
 
 
+//the color used instead of the RGB value on terminals with less colors
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub enum Fallback {
+    #[default]
+    Nearest,        //computed from the RGB value
+    TerminalDefault,
+    Index(u8),
+}
 
 
-impl TerminalColor {
-    //the seuence when change the terminal color background
-    pub fn sequence(self, color: Color) -> &'static[u8] {
-        match self {
-            TerminalColor::TrueColors => true_colors_sequence(color),
-            TerminalColor::Colors16 => sequence_for_color_16(color),
-            TerminalColor::Colors256 => sequence_for_color_256(color),
+//a color of a theme: the RGB value for true color terminals and the colors used instead when the
+//terminal has less of them
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct Color {
+    pub rgb: Option<Rgb>,
+    pub c256: Fallback,
+    pub c16: Fallback,
+}
+
+
+impl Color {
+    //the SGR parameters selecting this color as foreground or background, None when the terminal
+    //keeps its default color
+    pub fn sgr(&self, term: TerminalColor, bg: bool) -> Option<String> {
+        let base = if bg { 48 } else { 38 };
+        let c256 = |c: u8| format!("{};5;{}", base, c);
+
+        match term {
+            TerminalColor::TrueColors => match (self.rgb, self.c256, self.c16) {
+                (Some(Rgb(r, g, b)), ..) => Some(format!("{};2;{};{};{}", base, r, g, b)),
+                (None, Fallback::Index(c), _) => Some(c256(c)),
+                (None, _, Fallback::Index(c)) => Some(sgr_16(base, c)),
+                _ => None,
+            },
+            TerminalColor::Colors256 => match (self.c256, self.rgb, self.c16) {
+                (Fallback::Index(c), ..) => Some(c256(c)),
+                (Fallback::TerminalDefault, ..) => None,
+                (Fallback::Nearest, Some(rgb), _) => Some(c256(rgb.nearest_256())),
+                (Fallback::Nearest, None, Fallback::Index(c)) => Some(sgr_16(base, c)),
+                _ => None,
+            },
+            TerminalColor::Colors16 => match (self.c16, self.rgb, self.c256) {
+                (Fallback::Index(c), ..) => Some(sgr_16(base, c)),
+                (Fallback::TerminalDefault, ..) => None,
+                (Fallback::Nearest, Some(rgb), _) => Some(sgr_16(base, rgb.nearest_16())),
+                (Fallback::Nearest, None, Fallback::Index(c)) => Some(sgr_16(base, Rgb::from_256(c).nearest_16())),
+                _ => None,
+            },
         }
     }
+}
+
 
+//30-37 and 90-97 for the foreground, 40-47 and 100-107 for the background
+fn sgr_16(base: u8, c: u8) -> String {
+    let (first, c) = if c < 8 { (base - 8, c) } else { (base + 52, c - 8) };
+    format!("{}", first + c)
+}
+
+
+impl TerminalColor {
     pub fn getting_from_env() -> TerminalColor {
         env::var("COLORTERM")
             .ok()
@@ -168,3 +190,62 @@ impl TerminalColor {
 //         Color::Blue
 //     }
 // }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cube_levels_split_halfway() {
+        //0x00|0x5f at 47/48, 0x5f|0x87 at 114/115, 0x87|0xaf at 154/155
+        assert_eq!(Rgb(47, 0, 255).nearest_256(), 16 + 5);
+        assert_eq!(Rgb(48, 0, 255).nearest_256(), 16 + 36 + 5);
+        assert_eq!(Rgb(114, 0, 255).nearest_256(), 16 + 36 + 5);
+        assert_eq!(Rgb(115, 0, 255).nearest_256(), 16 + 72 + 5);
+        assert_eq!(Rgb(154, 0, 255).nearest_256(), 16 + 72 + 5);
+        assert_eq!(Rgb(155, 0, 255).nearest_256(), 16 + 108 + 5);
+    }
+
+    #[test]
+    fn grays_go_to_the_ramp_unless_the_cube_is_nearer() {
+        assert_eq!(Rgb(0x80, 0x80, 0x80).nearest_256(), 244);
+        assert_eq!(Rgb(0x81, 0x80, 0x7f).nearest_256(), 244);
+        assert_eq!(Rgb(0x87, 0x87, 0x87).nearest_256(), 16 + 36 * 2 + 6 * 2 + 2);
+        assert_eq!(Rgb(0, 0, 0).nearest_256(), 16);
+        assert_eq!(Rgb(0xff, 0xff, 0xff).nearest_256(), 231);
+        assert_eq!(Rgb(0xee, 0xee, 0xee).nearest_256(), 255);
+    }
+
+    #[test]
+    fn palette_colors_map_back_to_their_index() {
+        for c in 16..=255u8 {
+            assert_eq!(Rgb::from_256(c).nearest_256(), c, "{:?}", Rgb::from_256(c));
+        }
+        for c in 0..16u8 {
+            assert_eq!(Rgb::from_256(c).nearest_16(), c);
+        }
+        assert_eq!(Rgb::from_256(232), Rgb(8, 8, 8));
+    }
+
+    #[test]
+    fn sixteen_colors_use_the_normal_and_bright_bases() {
+        assert_eq!(sgr_16(38, 1), "31");
+        assert_eq!(sgr_16(38, 9), "91");
+        assert_eq!(sgr_16(48, 1), "41");
+        assert_eq!(sgr_16(48, 9), "101");
+        assert_eq!(Rgb(0xf0, 0x10, 0x10).nearest_16(), 9);
+    }
+
+    #[test]
+    fn sgr_falls_back_to_the_colors_the_terminal_has() {
+        let color = Color { rgb: Some(Rgb(0xff, 0, 0)), ..Color::default() };
+        assert_eq!(color.sgr(TerminalColor::TrueColors, false).as_deref(), Some("38;2;255;0;0"));
+        assert_eq!(color.sgr(TerminalColor::Colors256, true).as_deref(), Some("48;5;196"));
+        assert_eq!(color.sgr(TerminalColor::Colors16, true).as_deref(), Some("101"));
+
+        let color = Color { rgb: Some(Rgb(0xff, 0, 0)), c256: Fallback::Index(1), c16: Fallback::TerminalDefault };
+        assert_eq!(color.sgr(TerminalColor::Colors256, false).as_deref(), Some("38;5;1"));
+        assert_eq!(color.sgr(TerminalColor::Colors16, false), None);
+    }
+}
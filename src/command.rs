@@ -0,0 +1,578 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use crate::error::Result;
+use crate::fuzzy;
+use crate::input::{InputSeq, KeySeq};
+use crate::prompt::{Action, Prompt, PromptResult};
+
+
+//every action of the editor which can be bound to a key or run from the command palette
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Command {
+    Quit,
+    Save,
+    OpenBuffer,
+    NextBuffer,
+    PreviousBuffer,
+    SwitchBuffer,
+    ListBuffers,
+    KillBuffer,
+    FindFile,
+    CursorUp,
+    CursorDown,
+    CursorRight,
+    CursorLeft,
+    LineHead,
+    LineEnd,
+    NextPage,
+    PreviousPage,
+    NextWord,
+    PreviousWord,
+    NextParagraph,
+    PreviousParagraph,
+    BufferTop,
+    BufferBottom,
+    DeleteChar,
+    DeleteNextChar,
+    DeleteWord,
+    DeleteToLineHead,
+    DeleteToLineEnd,
+    InsertTab,
+    NewLine,
+    Search,
+    Replace,
+    Undo,
+    Redo,
+    Refresh,
+    Suspend,
+    SplitHorizontally,
+    SplitVertically,
+    CloseWindow,
+    OnlyWindow,
+    NextWindow,
+    WindowLeft,
+    WindowDown,
+    WindowUp,
+    WindowRight,
+    EnlargeHeight,
+    ShrinkHeight,
+    EnlargeWidth,
+    ShrinkWidth,
+    Palette,
+    SwitchTheme,
+    Help,
+}
+
+
+pub struct CommandDef {
+    pub cmd: Command,
+    pub name: &'static str,
+    pub description: &'static str,
+    pub keys: &'static [&'static str], //default bindings, in the syntax of InputSeq::from_str
+}
+
+
+macro_rules! commands {
+    ($($cmd:ident, $name:expr, $desc:expr, [$($key:expr),*];)*) => {
+        pub const COMMANDS: &[CommandDef] = &[
+            $(CommandDef { cmd: Command::$cmd, name: $name, description: $desc, keys: &[$($key),*] },)*
+        ];
+    };
+}
+
+
+commands! {
+    Quit, "quit", "Quit", ["C-q"];
+    Save, "save", "Save to file", ["C-s"];
+    OpenBuffer, "open-buffer", "Open text buffer", ["C-o"];
+    NextBuffer, "next-buffer", "Next text buffer", ["C-x"];
+    PreviousBuffer, "previous-buffer", "Previous text buffer", ["M-X"];
+    SwitchBuffer, "switch-buffer", "Switch buffer by name", ["M-s"];
+    ListBuffers, "list-buffers", "List and close buffers", ["M-a"];
+    KillBuffer, "kill-buffer", "Close text buffer", ["M-c"];
+    FindFile, "find-file", "Find file in working directory", ["M-g"];
+    CursorUp, "cursor-up", "Move cursor up", ["C-p", "UP"];
+    CursorDown, "cursor-down", "Move cursor down", ["C-n", "DOWN"];
+    CursorRight, "cursor-right", "Move cursor right", ["C-f", "RIGHT"];
+    CursorLeft, "cursor-left", "Move cursor left", ["C-b", "LEFT"];
+    LineHead, "line-head", "Move cursor to head of line", ["C-a", "M-LEFT", "HOME"];
+    LineEnd, "line-end", "Move cursor to end of line", ["C-e", "M-RIGHT", "END"];
+    NextPage, "next-page", "Next page", ["C-v", "PAGEDOWN"];
+    PreviousPage, "previous-page", "Previous page", ["C-]", "M-v", "PAGEUP"];
+    NextWord, "next-word", "Move cursor to next word", ["M-f", "C-RIGHT"];
+    PreviousWord, "previous-word", "Move cursor to previous word", ["M-b", "C-LEFT"];
+    NextParagraph, "next-paragraph", "Move cursor to next paragraph", ["M-n", "C-DOWN"];
+    PreviousParagraph, "previous-paragraph", "Move cursor to previous paragraph", ["M-p", "C-UP"];
+    BufferTop, "buffer-top", "Move cursor to top of file", ["M-<"];
+    BufferBottom, "buffer-bottom", "Move cursor to bottom of file", ["M->"];
+    DeleteChar, "delete-char", "Delete character", ["C-h", "BACKSPACE"];
+    DeleteNextChar, "delete-next-char", "Delete next character", ["C-d", "DELETE"];
+    DeleteWord, "delete-word", "Delete a word", ["C-w"];
+    DeleteToLineHead, "delete-to-line-head", "Delete until head of line", ["C-j"];
+    DeleteToLineEnd, "delete-to-line-end", "Delete until end of line", ["C-k"];
+    InsertTab, "insert-tab", "Insert tab", ["TAB"];
+    NewLine, "new-line", "New line", ["ENTER"];
+    Search, "search", "Search text", ["C-g"];
+    Replace, "replace", "Replace text in the whole buffer", ["M-%"];
+    Undo, "undo", "Undo last change", ["C-u"];
+    Redo, "redo", "Redo last change", ["C-r"];
+    Refresh, "refresh", "Refresh screen", ["C-l"];
+    Suspend, "suspend", "Suspend to the shell", ["C-z"];
+    SplitHorizontally, "split-horizontally", "Split window horizontally", ["M-2"];
+    SplitVertically, "split-vertically", "Split window vertically", ["M-3"];
+    CloseWindow, "close-window", "Close window", ["M-0"];
+    OnlyWindow, "only-window", "Close other windows", ["M-1"];
+    NextWindow, "next-window", "Next window", ["M-o"];
+    WindowLeft, "window-left", "Move to window on the left", ["M-h"];
+    WindowDown, "window-down", "Move to window below", ["M-j"];
+    WindowUp, "window-up", "Move to window above", ["M-k"];
+    WindowRight, "window-right", "Move to window on the right", ["M-l"];
+    EnlargeHeight, "enlarge-height", "Enlarge window height", ["M-="];
+    ShrinkHeight, "shrink-height", "Shrink window height", ["M--"];
+    EnlargeWidth, "enlarge-width", "Enlarge window width", ["M-}"];
+    ShrinkWidth, "shrink-width", "Shrink window width", ["M-{"];
+    Palette, "command-palette", "Run a command by name", ["M-x"];
+    SwitchTheme, "switch-theme", "Switch the color theme", [];
+    Help, "help", "Show this help", ["C-?", "F1"];
+}
+
+
+//keys which only mean something inside prompts, listed after the commands in the help
+const PROMPT_HELP: &[(&str, &str)] = &[
+    ("UP/DOWN or M-p/M-n in prompt", "Browse prompt history"),
+    ("C-r in prompt", "Search prompt history"),
+];
+
+
+impl Command {
+    pub fn def(self) -> &'static CommandDef {
+        COMMANDS.iter().find(|d| d.cmd == self).unwrap()
+    }
+
+    pub fn from_name(name: &str) -> Option<Command> {
+        COMMANDS.iter().find(|d| d.name == name).map(|d| d.cmd)
+    }
+}
+
+
+//a key sequence such as "C-x C-s", keys separated by spaces
+pub fn parse_keys(keys: &str) -> std::result::Result<Vec<InputSeq>, String> {
+    let seqs = keys.split_whitespace().map(str::parse).collect::<std::result::Result<Vec<InputSeq>, _>>()?;
+    if seqs.is_empty() {
+        return Err("Empty key sequence".to_string());
+    }
+    Ok(seqs)
+}
+
+
+fn keys_to_string(keys: &[InputSeq]) -> String {
+    keys.iter().map(|k| k.to_string()).collect::<Vec<_>>().join(" ")
+}
+
+
+//a node of the keymap trie, either bound to a command or the prefix of longer key sequences
+enum Binding {
+    Command(Command),
+    Prefix(HashMap<InputSeq, Binding>),
+}
+
+
+pub enum KeyResult {
+    Run(Command),
+    Pending,             //the keys so far are the prefix of a binding
+    Unbound,             //a single key with no binding
+    UnboundChord(String),
+    Canceled,            //C-g after a prefix
+}
+
+
+//key bindings of the commands, as a trie so that several keys in a row can make one binding
+pub struct Keymap {
+    root: HashMap<InputSeq, Binding>,
+    pending: Vec<InputSeq>,
+}
+
+
+impl Keymap {
+    pub fn new() -> Self {
+        let mut keymap = Self {
+            root: HashMap::new(),
+            pending: vec![],
+        };
+
+        for def in COMMANDS {
+            for key in def.keys {
+                let keys = parse_keys(key).expect("default key bindings are valid");
+                let shadowed = keymap.bind(&keys, Some(def.cmd));
+                debug_assert!(shadowed.is_empty(), "default key binding {} is taken twice", key);
+            }
+        }
+
+        keymap
+    }
+
+    //the default bindings changed by the [keys] section of the config file. A binding to "none"
+    //removes it. Returns the keymap and the problems found in the user bindings, including the
+    //default and earlier bindings they take the keys of
+    pub fn with_bindings(bindings: &[(String, String)]) -> (Self, Vec<String>) {
+        let mut keymap = Self::new();
+        let mut warnings = vec![];
+
+        for (keys, name) in bindings {
+            let keys = match parse_keys(keys) {
+                Ok(keys) => keys,
+                Err(err) => {
+                    warnings.push(format!("Invalid key binding '{}': {}", keys, err));
+                    continue;
+                }
+            };
+
+            let cmd = match (name.as_str(), Command::from_name(name)) {
+                ("none", _) => None,
+                (_, Some(cmd)) => Some(cmd),
+                (_, None) => {
+                    warnings.push(format!("Unknown command '{}' bound to {}", name, keys_to_string(&keys)));
+                    continue;
+                }
+            };
+
+            //a key can end a binding or continue a longer one, but not both
+            for (other, old) in keymap.bind(&keys, cmd) {
+                warnings.push(format!(
+                    "Key binding {} ({}) shadows {} ({})",
+                    keys_to_string(&keys),
+                    name,
+                    keys_to_string(&other),
+                    old.def().name,
+                ));
+            }
+        }
+
+        (keymap, warnings)
+    }
+
+    //bind keys to cmd, None removes the binding. Returns the bindings which lost their keys: a
+    //command bound to the same keys, a command turned into a prefix or the bindings under a prefix
+    fn bind(&mut self, keys: &[InputSeq], cmd: Option<Command>) -> Vec<(Vec<InputSeq>, Command)> {
+        let mut shadowed = vec![];
+        let cmd = match cmd {
+            Some(cmd) => cmd,
+            None => {
+                Self::unbind(&mut self.root, keys);
+                return shadowed;
+            }
+        };
+        let (last, prefix) = match keys.split_last() {
+            Some(split) => split,
+            None => return shadowed,
+        };
+
+        let mut map = &mut self.root;
+        for (i, key) in prefix.iter().enumerate() {
+            let node = map.entry(key.clone()).or_insert_with(|| Binding::Prefix(HashMap::new()));
+            if let Binding::Command(old) = *node {
+                shadowed.push((keys[..=i].to_vec(), old));
+                *node = Binding::Prefix(HashMap::new());
+            }
+            map = match node {
+                Binding::Prefix(map) => map,
+                Binding::Command(_) => unreachable!(),
+            };
+        }
+
+        match map.insert(last.clone(), Binding::Command(cmd)) {
+            Some(Binding::Command(old)) if old != cmd => shadowed.push((keys.to_vec(), old)),
+            Some(Binding::Prefix(old)) => Self::collect_bindings(&old, &mut keys.to_vec(), &mut shadowed),
+            _ => {}
+        }
+        shadowed
+    }
+
+    //remove the binding of keys without creating or replacing nodes on the way, prefixes left
+    //empty are removed too
+    fn unbind(map: &mut HashMap<InputSeq, Binding>, keys: &[InputSeq]) {
+        let (first, rest) = match keys.split_first() {
+            Some(split) => split,
+            None => return,
+        };
+        if rest.is_empty() {
+            map.remove(first);
+            return;
+        }
+        if let Some(Binding::Prefix(next)) = map.get_mut(first) {
+            Self::unbind(next, rest);
+            if next.is_empty() {
+                map.remove(first);
+            }
+        }
+    }
+
+    fn collect_bindings(map: &HashMap<InputSeq, Binding>, prefix: &mut Vec<InputSeq>, found: &mut Vec<(Vec<InputSeq>, Command)>) {
+        for (key, node) in map {
+            prefix.push(key.clone());
+            match node {
+                Binding::Command(cmd) => found.push((prefix.clone(), *cmd)),
+                Binding::Prefix(next) => Self::collect_bindings(next, prefix, found),
+            }
+            prefix.pop();
+        }
+    }
+
+    fn lookup(&self, keys: &[InputSeq]) -> Option<&Binding> {
+        let (last, prefix) = keys.split_last()?;
+
+        let mut map = &self.root;
+        for key in prefix {
+            match map.get(key)? {
+                Binding::Prefix(next) => map = next,
+                Binding::Command(_) => return None,
+            }
+        }
+
+        map.get(last)
+    }
+
+    //feed one key, the result says whether a binding is complete
+    pub fn feed(&mut self, seq: InputSeq) -> KeyResult {
+        let is_cancel = seq.ctrl && seq.key == KeySeq::Key(b'g');
+        if !self.pending.is_empty() && is_cancel {
+            self.pending.clear();
+            return KeyResult::Canceled;
+        }
+
+        self.pending.push(seq);
+
+        //a key with shift acts like the key alone unless shift has a binding of its own
+        if self.lookup(&self.pending).is_none() {
+            if let Some(last) = self.pending.last_mut().filter(|s| s.shift) {
+                last.shift = false;
+            }
+        }
+
+        let result = match self.lookup(&self.pending) {
+            Some(Binding::Prefix(_)) => return KeyResult::Pending,
+            Some(Binding::Command(cmd)) => KeyResult::Run(*cmd),
+            None if self.pending.len() == 1 => KeyResult::Unbound,
+            None => KeyResult::UnboundChord(keys_to_string(&self.pending)),
+        };
+
+        self.pending.clear();
+        result
+    }
+
+    //the prefix typed so far, such as "C-x"
+    pub fn pending_keys(&self) -> String {
+        keys_to_string(&self.pending)
+    }
+
+    fn collect_keys(&self, map: &HashMap<InputSeq, Binding>, prefix: &mut Vec<InputSeq>, cmd: Command, found: &mut Vec<String>) {
+        for (key, node) in map {
+            prefix.push(key.clone());
+            match node {
+                Binding::Command(c) if *c == cmd => found.push(keys_to_string(prefix)),
+                Binding::Command(_) => {}
+                Binding::Prefix(next) => self.collect_keys(next, prefix, cmd, found),
+            }
+            prefix.pop();
+        }
+    }
+
+    //key sequences bound to cmd, the default ones first in the order of the registry
+    pub fn keys_of(&self, cmd: Command) -> Vec<String> {
+        let mut found = vec![];
+        self.collect_keys(&self.root, &mut vec![], cmd, &mut found);
+
+        let defaults = cmd.def().keys;
+        let default_pos = |k: &String| {
+            defaults
+                .iter()
+                .position(|d| parse_keys(d).map(|d| keys_to_string(&d)).as_ref() == Ok(k))
+                .unwrap_or(defaults.len())
+        };
+        found.sort_by_key(|k| (default_pos(k), k.clone()));
+        found
+    }
+
+    //one "keys : description" line for each command, then the prompt keys
+    pub fn help(&self) -> String {
+        let palette = self.keys_of(Command::Palette);
+        let mut lines: Vec<String> = COMMANDS
+            .iter()
+            .map(|def| {
+                let keys = self.keys_of(def.cmd);
+                let keys = match (keys.is_empty(), palette.first()) {
+                    (false, _) => keys.join(" or "),
+                    (true, Some(palette)) => format!("{} {}", palette, def.name),
+                    (true, None) => def.name.to_string(),
+                };
+                format!("{:<30}: {}", keys, def.description)
+            })
+            .collect();
+
+        lines.extend(PROMPT_HELP.iter().map(|(keys, desc)| format!("{:<30}: {}", keys, desc)));
+        lines.join("\n")
+    }
+}
+
+
+//choose a command by fuzzy matching its name, the prompt result is the name of the chosen command
+pub struct RunCommand {
+    input: String,
+    matches: Vec<Command>,
+    selected: usize,
+}
+
+
+impl RunCommand {
+    fn update<W: Write>(&mut self, prompt: &mut Prompt<'_, W>, input: &str) {
+        self.matches = fuzzy::rank(input, COMMANDS.iter().map(|d| (d.cmd, d.name)));
+        self.input = input.to_string();
+        self.selected = 0;
+        self.update_hint(prompt);
+    }
+
+    fn update_hint<W: Write>(&self, prompt: &mut Prompt<'_, W>) {
+        let len = self.matches.len();
+        if len == 0 {
+            prompt.set_hint(" [No match]");
+            return;
+        }
+
+        let labels: Vec<&str> = (0..len.min(8))
+            .map(|i| self.matches[(self.selected + i) % len].def().name)
+            .collect();
+        let selected = self.matches[self.selected].def();
+
+        prompt.set_hint(format!(" {{{}}} {}", labels.join(" | "), selected.description));
+    }
+}
+
+
+impl Action for RunCommand {
+    fn new<W: Write>(prompt: &mut Prompt<'_, W>) -> Self {
+        let mut action = Self {
+            input: String::new(),
+            matches: vec![],
+            selected: 0,
+        };
+        action.update(prompt, "");
+        action
+    }
+
+    fn on_seq<W: Write>(
+        &mut self,
+        prompt: &mut Prompt<'_, W>,
+        input: &str,
+        seq: InputSeq,
+    ) -> Result<bool> {
+        use KeySeq::*;
+
+        let len = self.matches.len();
+        match seq {
+            InputSeq { key: TabKey, .. } | InputSeq { key: Key(b'n'), ctrl: true, .. } if len > 0 => {
+                self.selected = (self.selected + 1) % len;
+                self.update_hint(prompt);
+            }
+            InputSeq { key: Key(b'p'), ctrl: true, .. } if len > 0 => {
+                self.selected = (self.selected + len - 1) % len;
+                self.update_hint(prompt);
+            }
+            _ if input != self.input => self.update(prompt, input),
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    fn on_end<W: Write>(
+        self,
+        prompt: &mut Prompt<'_, W>,
+        result: PromptResult,
+    ) -> Result<PromptResult> {
+        prompt.set_hint("");
+
+        match result {
+            PromptResult::Input(_) => match self.matches.get(self.selected) {
+                Some(cmd) => Ok(PromptResult::Input(cmd.def().name.to_string())),
+                None => {
+                    prompt.set_error_message("No command matches");
+                    Ok(PromptResult::Canceled)
+                }
+            },
+            PromptResult::Canceled => Ok(result),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bindings(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(k, c)| (k.to_string(), c.to_string())).collect()
+    }
+
+    #[test]
+    fn defaults_have_no_conflicts() {
+        let (_, warnings) = Keymap::with_bindings(&[]);
+        assert!(warnings.is_empty(), "{:?}", warnings);
+    }
+
+    #[test]
+    fn user_binding_shadows_default() {
+        let (mut keymap, warnings) = Keymap::with_bindings(&bindings(&[("C-s", "quit")]));
+        assert_eq!(warnings, vec!["Key binding C-s (quit) shadows C-s (save)"]);
+        assert!(matches!(keymap.feed("C-s".parse().unwrap()), KeyResult::Run(Command::Quit)));
+    }
+
+    #[test]
+    fn rebinding_the_same_command_is_quiet() {
+        let (_, warnings) = Keymap::with_bindings(&bindings(&[("C-s", "save")]));
+        assert!(warnings.is_empty(), "{:?}", warnings);
+    }
+
+    #[test]
+    fn default_turned_into_prefix() {
+        let (mut keymap, warnings) = Keymap::with_bindings(&bindings(&[("C-x C-s", "save")]));
+        assert_eq!(warnings, vec!["Key binding C-x C-s (save) shadows C-x (next-buffer)"]);
+        assert!(matches!(keymap.feed("C-x".parse().unwrap()), KeyResult::Pending));
+        assert!(matches!(keymap.feed("C-s".parse().unwrap()), KeyResult::Run(Command::Save)));
+    }
+
+    #[test]
+    fn prefix_replaced_by_command() {
+        let (_, warnings) = Keymap::with_bindings(&bindings(&[("C-t C-s", "save"), ("C-t", "quit")]));
+        assert_eq!(warnings, vec!["Key binding C-t (quit) shadows C-t C-s (save)"]);
+    }
+
+    #[test]
+    fn removing_a_binding_is_quiet() {
+        let (mut keymap, warnings) = Keymap::with_bindings(&bindings(&[("C-s", "none")]));
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert!(matches!(keymap.feed("C-s".parse().unwrap()), KeyResult::Unbound));
+    }
+
+    #[test]
+    fn removing_a_missing_binding_keeps_its_prefix() {
+        let (mut keymap, warnings) = Keymap::with_bindings(&bindings(&[("C-x C-s", "none"), ("C-t C-s", "none")]));
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert!(matches!(keymap.feed("C-x".parse().unwrap()), KeyResult::Run(Command::NextBuffer)));
+        assert!(!keymap.root.contains_key(&"C-t".parse().unwrap()));
+    }
+
+    #[test]
+    fn removing_the_last_binding_of_a_prefix() {
+        let (keymap, _) = Keymap::with_bindings(&bindings(&[("C-t C-s", "save"), ("C-t C-s", "none")]));
+        assert!(!keymap.root.contains_key(&"C-t".parse().unwrap()));
+    }
+
+    #[test]
+    fn invalid_bindings() {
+        let (_, warnings) = Keymap::with_bindings(&bindings(&[("C-s", "no-such-command"), ("", "save")]));
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].starts_with("Unknown command 'no-such-command'"));
+        assert!(warnings[1].starts_with("Invalid key binding ''"));
+    }
+}
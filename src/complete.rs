@@ -0,0 +1,286 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::error::Result;
+use crate::input::{InputSeq, KeySeq};
+use crate::prompt::{Action, Prompt, PromptResult};
+
+
+fn home_dir() -> Option<PathBuf> {
+    env::var_os("HOME").map(PathBuf::from)
+}
+
+
+//"~" and "~/..." are taken from the home directory
+fn expand_tilde(input: &str) -> PathBuf {
+    match (input.strip_prefix('~'), home_dir()) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+            home.join(rest.trim_start_matches('/'))
+        }
+        _ => PathBuf::from(input),
+    }
+}
+
+
+fn longest_common_prefix(names: &[String]) -> &str {
+    let first = names[0].as_str();
+    let end = names[1..].iter().fold(first.len(), |end, name| {
+        first[..end]
+            .char_indices()
+            .zip(name.chars())
+            .find(|((_, a), b)| a != b)
+            .map(|((i, _), _)| i)
+            .unwrap_or_else(|| end.min(name.len()))
+    });
+
+    &first[..end]
+}
+
+
+//complete file and directory names with Tab in path prompts such as "Open:" and "Save as:".
+//Relative paths are resolved against the directory of the current buffer.
+pub struct CompletePath {
+    base: PathBuf,
+    candidates: Vec<String>,
+    dir_part: String,
+    selected: Option<usize>,
+    completed: Option<String>, //input as last set by a completion, to detect repeated Tab
+}
+
+
+impl CompletePath {
+    fn resolve(&self, input: &str) -> PathBuf {
+        let path = expand_tilde(input);
+        if path.is_absolute() {
+            path
+        } else {
+            self.base.join(path)
+        }
+    }
+
+    //names in the directory part of input starting with its last component
+    fn list_candidates(&self, input: &str) -> (String, Vec<String>) {
+        //a bare "~" is the home directory, not a name starting with "~"
+        let input = if input == "~" && home_dir().is_some() { "~/" } else { input };
+        let (dir_part, prefix) = match input.rfind('/') {
+            Some(i) => (&input[..=i], &input[i + 1..]),
+            None => ("", input),
+        };
+
+        let dir = self.resolve(if dir_part.is_empty() { "." } else { dir_part });
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .filter_map(|e| e.ok())
+                    .filter_map(|e| {
+                        let mut name = e.file_name().into_string().ok()?;
+                        if !name.starts_with(prefix) || name.starts_with('.') && !prefix.starts_with('.') {
+                            return None;
+                        }
+                        if e.path().is_dir() {
+                            name.push('/');
+                        }
+                        Some(name)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        names.sort();
+        (dir_part.to_string(), names)
+    }
+
+    fn complete<W: Write>(&mut self, prompt: &mut Prompt<'_, W>, input: &str) {
+        //Tab again right after a completion cycles through the candidates
+        if self.completed.as_deref() == Some(input) && self.candidates.len() > 1 {
+            let next = self.selected.map(|s| (s + 1) % self.candidates.len()).unwrap_or(0);
+            self.select(prompt, next);
+            return;
+        }
+
+        let (dir_part, candidates) = self.list_candidates(input);
+        self.dir_part = dir_part;
+        self.candidates = candidates;
+        self.selected = None;
+
+        match self.candidates.len() {
+            0 => {
+                self.completed = None;
+                prompt.set_hint(" [No match]");
+            }
+            1 => {
+                let completion = format!("{}{}", self.dir_part, self.candidates[0]);
+                self.candidates.clear();
+                self.set_completion(prompt, completion);
+            }
+            _ => {
+                let common = format!("{}{}", self.dir_part, longest_common_prefix(&self.candidates));
+                if common.len() > input.len() {
+                    //complete up to where the candidates differ before cycling
+                    self.set_completion(prompt, common);
+                } else {
+                    self.select(prompt, 0);
+                }
+            }
+        }
+    }
+
+    fn select<W: Write>(&mut self, prompt: &mut Prompt<'_, W>, idx: usize) {
+        self.selected = Some(idx);
+        let completion = format!("{}{}", self.dir_part, self.candidates[idx]);
+        self.set_completion(prompt, completion);
+    }
+
+    fn set_completion<W: Write>(&mut self, prompt: &mut Prompt<'_, W>, completion: String) {
+        self.update_hint(prompt);
+        prompt.set_input(completion.clone());
+        self.completed = Some(completion);
+    }
+
+    fn update_hint<W: Write>(&self, prompt: &mut Prompt<'_, W>) {
+        if self.candidates.len() < 2 {
+            prompt.set_hint("");
+            return;
+        }
+
+        let labels: Vec<String> = self
+            .candidates
+            .iter()
+            .enumerate()
+            .map(|(i, c)| if Some(i) == self.selected { format!("[{}]", c) } else { c.clone() })
+            .collect();
+
+        prompt.set_hint(format!(" {}", labels.join(" ")));
+    }
+}
+
+
+impl Action for CompletePath {
+    fn new<W: Write>(prompt: &mut Prompt<'_, W>) -> Self {
+        let base = prompt
+            .buf()
+            .path()
+            .and_then(Path::parent)
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+
+        Self {
+            base,
+            candidates: vec![],
+            dir_part: String::new(),
+            selected: None,
+            completed: None,
+        }
+    }
+
+    fn on_seq<W: Write>(
+        &mut self,
+        prompt: &mut Prompt<'_, W>,
+        input: &str,
+        seq: InputSeq,
+    ) -> Result<bool> {
+        match seq {
+            InputSeq { key: KeySeq::TabKey, .. } => self.complete(prompt, input),
+            _ => {
+                //typing anything else ends the completion
+                self.completed = None;
+                self.candidates.clear();
+                prompt.set_hint("");
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn on_end<W: Write>(
+        self,
+        prompt: &mut Prompt<'_, W>,
+        result: PromptResult,
+    ) -> Result<PromptResult> {
+        prompt.set_hint("");
+
+        match result {
+            PromptResult::Input(input) if !input.is_empty() => {
+                let path = self.resolve(&input);
+                let path = env::current_dir()
+                    .ok()
+                    .and_then(|cwd| path.strip_prefix(cwd).ok().map(Path::to_path_buf))
+                    .unwrap_or(path);
+                Ok(PromptResult::Input(path.to_string_lossy().into_owned()))
+            }
+            result => Ok(result),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    fn complete_in(base: &Path) -> CompletePath {
+        CompletePath {
+            base: base.to_path_buf(),
+            candidates: vec![],
+            dir_part: String::new(),
+            selected: None,
+            completed: None,
+        }
+    }
+
+    #[test]
+    fn common_prefix() {
+        assert_eq!(longest_common_prefix(&names(&["main.rs"])), "main.rs");
+        assert_eq!(longest_common_prefix(&names(&["main.rs", "make/", "man"])), "ma");
+        assert_eq!(longest_common_prefix(&names(&["src/", "lib.rs"])), "");
+        assert_eq!(longest_common_prefix(&names(&["abc", "ab"])), "ab");
+        assert_eq!(longest_common_prefix(&names(&["日本語", "日本人", "日本"])), "日本");
+        //é and è share their first byte
+        assert_eq!(longest_common_prefix(&names(&["café", "cafè"])), "caf");
+    }
+
+    #[test]
+    fn tilde() {
+        let home = home_dir().unwrap();
+        assert_eq!(expand_tilde("~"), home);
+        assert_eq!(expand_tilde("~/x"), home.join("x"));
+        assert_eq!(expand_tilde("~user/x"), PathBuf::from("~user/x"));
+        assert_eq!(expand_tilde("a/~/x"), PathBuf::from("a/~/x"));
+    }
+
+    #[test]
+    fn candidates() {
+        let top = env::temp_dir().join(format!("detty-complete-{}", std::process::id()));
+        fs::create_dir_all(top.join("src")).unwrap();
+        for file in ["src/main.rs", "src/mod.rs", "Makefile", ".hidden"] {
+            fs::write(top.join(file), "").unwrap();
+        }
+
+        let complete = complete_in(&top);
+        assert_eq!(complete.list_candidates(""), (String::new(), names(&["Makefile", "src/"])));
+        assert_eq!(complete.list_candidates("s"), (String::new(), names(&["src/"])));
+        assert_eq!(complete.list_candidates("src/m"), ("src/".to_string(), names(&["main.rs", "mod.rs"])));
+        assert_eq!(complete.list_candidates("src/"), ("src/".to_string(), names(&["main.rs", "mod.rs"])));
+        assert_eq!(complete.list_candidates(".h"), (String::new(), names(&[".hidden"])));
+        assert_eq!(complete.list_candidates("nothing/x"), ("nothing/".to_string(), vec![]));
+
+        let absolute = format!("{}/M", top.display());
+        assert_eq!(complete.list_candidates(&absolute), (format!("{}/", top.display()), names(&["Makefile"])));
+
+        fs::remove_dir_all(&top).unwrap();
+    }
+
+    #[test]
+    fn bare_tilde_lists_the_home_directory() {
+        let complete = complete_in(Path::new(""));
+        let (dir_part, candidates) = complete.list_candidates("~");
+        assert_eq!(dir_part, "~/");
+        assert_eq!(candidates, complete.list_candidates("~/").1);
+    }
+}
@@ -0,0 +1,338 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use toml::{Table, Value};
+
+use crate::language::{Indent, Language};
+use crate::project::Project;
+use crate::row::TAB_STOP;
+
+
+//$XDG_CONFIG_HOME/detty, falling back to ~/.config/detty
+pub fn config_dir() -> Option<PathBuf> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))?;
+
+    Some(config_dir.join("detty"))
+}
+
+
+fn default_path() -> Option<PathBuf> {
+    config_dir().map(|d| d.join("config.toml"))
+}
+
+
+//a TOML value converted to the type of a setting
+trait FromValue: Sized {
+    fn from_value(value: &Value) -> Option<Self>;
+    const EXPECTED: &'static str;
+}
+
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Option<Self> {
+        value.as_bool()
+    }
+    const EXPECTED: &'static str = "true or false";
+}
+
+
+impl FromValue for usize {
+    fn from_value(value: &Value) -> Option<Self> {
+        value.as_integer().and_then(|i| usize::try_from(i).ok())
+    }
+    const EXPECTED: &'static str = "a positive number";
+}
+
+
+impl FromValue for u64 {
+    fn from_value(value: &Value) -> Option<Self> {
+        value.as_integer().and_then(|i| u64::try_from(i).ok())
+    }
+    const EXPECTED: &'static str = "a positive number";
+}
+
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Option<Self> {
+        value.as_str().map(str::to_string)
+    }
+    const EXPECTED: &'static str = "a string";
+}
+
+
+//Settings with the value of each setting, and SettingsLayer with the ones a config file set
+macro_rules! settings {
+    ($($field:ident: $t:ty = $default:expr,)*) => {
+        #[derive(Clone, PartialEq, Debug)]
+        pub struct Settings {
+            $(pub $field: $t,)*
+        }
+
+        impl Default for Settings {
+            fn default() -> Self {
+                Self { $($field: $default,)* }
+            }
+        }
+
+        #[derive(Clone, Default, Debug)]
+        pub struct SettingsLayer {
+            $(pub $field: Option<$t>,)*
+        }
+
+        impl SettingsLayer {
+            pub fn apply_to(&self, settings: &mut Settings) {
+                $(if let Some(v) = &self.$field {
+                    settings.$field = v.clone();
+                })*
+            }
+
+            //Ok(false) when there is no setting called key
+            fn set(&mut self, key: &str, value: &Value) -> std::result::Result<bool, String> {
+                match key {
+                    $(stringify!($field) => match <$t as FromValue>::from_value(value) {
+                        Some(v) => self.$field = Some(v),
+                        None => return Err(format!("'{}' must be {}", key, <$t as FromValue>::EXPECTED)),
+                    },)*
+                    _ => return Ok(false),
+                }
+                Ok(true)
+            }
+        }
+    };
+}
+
+
+settings! {
+    tab_width: usize = TAB_STOP,
+    expand_tabs: bool = false,  //insert indent_width spaces instead of a tab
+    indent_width: usize = 4,
+    soft_wrap: bool = false,
+    line_numbers: bool = false,
+    theme: String = "auto".to_string(),   //"auto" picks dark_theme or light_theme for the background
+    dark_theme: String = "gruvbox-dark".to_string(),
+    light_theme: String = "gruvbox-light".to_string(),
+    background: String = "auto".to_string(),  //"auto" asks the terminal, "dark" or "light"
+    message_timeout: u64 = 5,   //seconds a message stays in the message bar
+    escape_timeout: u64 = 25,   //milliseconds to wait after ESC before it is a key of its own
+    keymap: String = "default".to_string(),   //"default" or "vi"
+    scroll_margin: usize = 0,   //lines kept visible above and below the cursor
+    end_of_line: String = "lf".to_string(),   //"lf", "crlf" or "cr"
+    charset: String = "utf-8".to_string(),    //"utf-8", "utf-8-bom", "latin1", "utf-16be" or "utf-16le"
+    trim_trailing_whitespace: bool = false,
+    insert_final_newline: bool = true,
+}
+
+
+//user settings read from the config file. Problems in the file never stop the editor,
+//they are collected in warnings and shown once it started
+pub struct Config {
+    pub keys: Vec<(String, String)>, //key sequence and command name, in the order of the file
+    pub global: SettingsLayer,
+    pub langs: HashMap<&'static str, SettingsLayer>, //[lang.<name>] sections
+    pub warnings: Vec<String>,
+}
+
+
+impl Config {
+    pub fn new() -> Self {
+        Self {
+            keys: vec![],
+            global: SettingsLayer::default(),
+            langs: HashMap::new(),
+            warnings: vec![],
+        }
+    }
+
+    //read the config file, a missing file means the defaults
+    pub fn load() -> Self {
+        match default_path() {
+            Some(path) if path.exists() => Self::from_file(&path),
+            _ => Self::new(),
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Self {
+        let mut config = Self::new();
+
+        match fs::read_to_string(path).map_err(|e| e.to_string()).and_then(|s| s.parse::<Table>().map_err(|e| e.to_string())) {
+            Ok(table) => config.apply(&table),
+            Err(err) => config.warn(format!("{}: {}", path.display(), err.trim())),
+        }
+
+        config
+    }
+
+    fn warn<S: Into<String>>(&mut self, warning: S) {
+        self.warnings.push(warning.into());
+    }
+
+    fn apply(&mut self, table: &Table) {
+        for (name, value) in table {
+            match (name.as_str(), value) {
+                ("keys", Value::Table(keys)) => self.apply_keys(keys),
+                ("lang", Value::Table(langs)) => self.apply_langs(langs),
+                (name, value) => {
+                    let mut global = std::mem::take(&mut self.global);
+                    self.set(&mut global, name, name, value);
+                    self.global = global;
+                }
+            }
+        }
+    }
+
+    fn set(&mut self, layer: &mut SettingsLayer, section: &str, key: &str, value: &Value) {
+        match layer.set(key, value) {
+            Ok(true) => {}
+            Ok(false) => self.warn(format!("Unknown config key '{}'", section)),
+            Err(err) => self.warn(err),
+        }
+    }
+
+    fn apply_keys(&mut self, keys: &Table) {
+        for (key, value) in keys {
+            match value {
+                Value::String(cmd) => self.keys.push((key.clone(), cmd.clone())),
+                _ => self.warn(format!("Binding of '{}' must be a command name", key)),
+            }
+        }
+    }
+
+    fn apply_langs(&mut self, langs: &Table) {
+        for (name, value) in langs {
+            let lang = match Language::from_name(name) {
+                Some(lang) => lang,
+                None => {
+                    self.warn(format!("Unknown language 'lang.{}'", name));
+                    continue;
+                }
+            };
+
+            let table = match value {
+                Value::Table(table) => table,
+                _ => {
+                    self.warn(format!("'lang.{}' must be a section", name));
+                    continue;
+                }
+            };
+
+            let mut layer = self.langs.remove(lang.name()).unwrap_or_default();
+            for (key, value) in table {
+                self.set(&mut layer, &format!("lang.{}.{}", name, key), key, value);
+            }
+            self.langs.insert(lang.name(), layer);
+        }
+    }
+
+    //settings which do not depend on a buffer, such as the message timeout and the theme
+    pub fn settings(&self) -> Settings {
+        let mut settings = Settings::default();
+        self.global.apply_to(&mut settings);
+        settings
+    }
+
+    //the global settings, then the [lang.<name>] section
+    fn apply_to(&self, settings: &mut Settings, lang: Language) {
+        self.global.apply_to(settings);
+        if let Some(layer) = self.langs.get(lang.name()) {
+            layer.apply_to(settings);
+        }
+    }
+
+    //the defaults of the language, changed by this config, changed by the project of the file
+    pub fn settings_for(&self, lang: Language, project: Option<&Project>) -> Settings {
+        let mut settings = Settings::default();
+        if let Indent::Fixed(indent) = lang.indent() {
+            settings.expand_tabs = true;
+            settings.indent_width = indent.len();
+        }
+
+        self.apply_to(&mut settings, lang);
+        if let Some(project) = project {
+            project.editorconfig.apply_to(&mut settings);
+            if let Some(config) = &project.config {
+                config.apply_to(&mut settings, lang);
+            }
+        }
+
+        settings.tab_width = settings.tab_width.max(1);
+        settings
+    }
+
+    //the settings of the file at path, before it is opened
+    pub fn settings_for_file(&self, path: &Path) -> Settings {
+        self.settings_for(Language::detect(path), Some(&Project::load(path)))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(toml: &str) -> Config {
+        let mut config = Config::new();
+        config.apply(&toml.parse::<Table>().unwrap());
+        config
+    }
+
+    #[test]
+    fn warnings() {
+        let cases: &[(&str, &[&str])] = &[
+            ("tab_width = 2", &[]),
+            ("no_such_key = 1", &["Unknown config key 'no_such_key'"]),
+            ("tab_width = \"x\"", &["'tab_width' must be a positive number"]),
+            ("tab_width = -1", &["'tab_width' must be a positive number"]),
+            ("soft_wrap = 1", &["'soft_wrap' must be true or false"]),
+            ("[lang.rust]\nno_such_key = 1", &["Unknown config key 'lang.rust.no_such_key'"]),
+            ("[lang.cobol]\ntab_width = 2", &["Unknown language 'lang.cobol'"]),
+            ("lang = { rust = 1 }", &["'lang.rust' must be a section"]),
+            ("[keys]\nC-s = 1", &["Binding of 'C-s' must be a command name"]),
+        ];
+
+        for (toml, warnings) in cases {
+            assert_eq!(config(toml).warnings, *warnings, "{}", toml);
+        }
+    }
+
+    #[test]
+    fn values() {
+        let cases: &[(&str, Language, usize, bool)] = &[
+            ("", Language::Plain, TAB_STOP, false),
+            ("", Language::Rust, TAB_STOP, true),
+            ("tab_width = 2", Language::Plain, 2, false),
+            ("tab_width = 0", Language::Plain, 1, false),
+            ("tab_width = 2\n[lang.rust]\ntab_width = 3", Language::Rust, 3, true),
+            ("tab_width = 2\n[lang.rust]\ntab_width = 3", Language::Plain, 2, false),
+            ("[lang.rust]\nexpand_tabs = false", Language::Rust, TAB_STOP, false),
+        ];
+
+        for (toml, lang, tab_width, expand_tabs) in cases {
+            let settings = config(toml).settings_for(*lang, None);
+            assert_eq!((settings.tab_width, settings.expand_tabs), (*tab_width, *expand_tabs), "{} ({:?})", toml, lang);
+        }
+    }
+
+    #[test]
+    fn layers() {
+        let global = config("tab_width = 2\nindent_width = 2\nsoft_wrap = true\n[lang.rust]\ntab_width = 3\nindent_width = 3");
+        let project = Project {
+            editorconfig: SettingsLayer { tab_width: Some(5), indent_width: Some(5), ..Default::default() },
+            config: Some(config("[lang.rust]\ntab_width = 6")),
+            warnings: vec![],
+        };
+
+        let settings = global.settings_for(Language::Rust, Some(&project));
+        assert_eq!(settings.tab_width, 6);
+        assert_eq!(settings.indent_width, 5);
+        assert!(settings.soft_wrap);
+        assert!(settings.expand_tabs);
+
+        let settings = global.settings_for(Language::Plain, Some(&project));
+        assert_eq!((settings.tab_width, settings.indent_width), (5, 5));
+    }
+}
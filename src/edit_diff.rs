@@ -1,4 +1,7 @@
-use crate::row::{Row, self};
+use std::cmp;
+use std::mem;
+
+use crate::row::Row;
 
 
 #[derive(Copy, Clone)]
@@ -15,6 +18,8 @@ pub enum  EditDiff {
     InsertChar(usize, usize, char), 
     DeleteChar(usize, usize, char),
     Insert(usize, usize, String), 
+    InsertText(usize, usize, String), //text spanning lines, such as a paste
+    RemoveText(usize, usize, String), //text spanning lines from x, y, such as a vi operator removes
     Append(usize, String),
     Truncate(usize, String),
     Remove(usize, usize, String),
@@ -27,7 +32,24 @@ pub enum  EditDiff {
 
 
 impl EditDiff {
-    pub fn apply(&self, rows: &mut Vec<Row>, which: UndoRedo) -> (usize, usize){
+    //the first line the diff changes, None for Newline which adds a line at the end
+    pub fn first_line(&self) -> Option<usize> {
+        match *self {
+            EditDiff::InsertChar(_, y, _) 
+            | EditDiff::DeleteChar(_, y, _) 
+            | EditDiff::Insert(_, y, _) 
+            | EditDiff::InsertText(_, y, _) 
+            | EditDiff::RemoveText(_, y, _) 
+            | EditDiff::Remove(_, y, _) 
+            | EditDiff::Append(y, _) 
+            | EditDiff::Truncate(y, _) 
+            | EditDiff::InsertLine(y, _) 
+            | EditDiff::DeleteLine(y, _) => Some(y), 
+            EditDiff::Newline => None, 
+        }
+    }
+
+    pub fn apply(&self, rows: &mut Vec<Row>, which: UndoRedo, tab_width: usize) -> (usize, usize){
         use UndoRedo::*; 
 
         match *self {
@@ -45,13 +67,13 @@ impl EditDiff {
 
             EditDiff::DeleteChar(x, y, c) => match which {
                 Redo => {
-                    rows[y].remove_char(x - y); 
+                    rows[y].remove_char(x - 1); 
                     (x - 1, y)
                 }
 
                 Undo => {
                     rows[y].insert_char(x - 1, c); 
-                    (x - 1, y)
+                    (x, y)
                 }
             },
 
@@ -74,6 +96,7 @@ impl EditDiff {
                 Redo => {
                     let count = s.chars().count(); 
                     let len = rows[y].len(); 
+                    rows[y].truncate(len - count); 
                     (len - count, y)
                 }
 
@@ -91,7 +114,20 @@ impl EditDiff {
                 }
 
                 Undo => {
-                    rows[y].remove(x, c.chars().count()); 
+                    rows[y].remove(x, x + c.chars().count()); 
+                    (x, y)
+                }
+            },
+
+            EditDiff::InsertText(x, y, ref text) => match which {
+                Redo => insert_text(rows, x, y, text, tab_width), 
+                Undo => remove_text(rows, x, y, text), 
+            },
+
+            EditDiff::RemoveText(x, y, ref text) => match which {
+                Redo => remove_text(rows, x, y, text), 
+                Undo => {
+                    insert_text(rows, x, y, text, tab_width); 
                     (x, y)
                 }
             },
@@ -114,7 +150,7 @@ impl EditDiff {
 
             EditDiff::Newline => match which {
                 Redo => {
-                    rows.push(Row::empty()); 
+                    rows.push(Row::empty(tab_width)); 
                     (0, rows.len() - 1)
                 }
 
@@ -129,12 +165,15 @@ impl EditDiff {
 
             EditDiff::InsertLine(y, ref c) => match  which {
                 Redo => {
-                    rows.insert(y, Row::new(c).unwrap()); 
+                    rows.insert(y, Row::new(c, tab_width).unwrap()); 
                     (0, y)
                 }
 
                 Undo => {
                     rows.remove(y); 
+                    if y == 0 {
+                        return (0, 0); 
+                    }
                     (rows[y - 1].len(), y -1)
                 }
             },
@@ -148,22 +187,208 @@ impl EditDiff {
                         rows.remove(y);
                     }
 
+                    if y == 0 {
+                        return (0, 0); 
+                    }
                     (rows[y - 1].len(), y - 1)
                 }
 
                 Undo => {
                     if y == rows.len() {
-                        rows.push(Row::new(c).unwrap()); 
+                        rows.push(Row::new(c, tab_width).unwrap()); 
                     
                     }else {
                         //rows.remove(y); 
-                        rows.insert(y, Row::new(c).unwrap())
+                        rows.insert(y, Row::new(c, tab_width).unwrap())
                     }
 
-                    (0, 1)
+                    (0, y)
                 }
             },
 
         }
     }    
-}
\ No newline at end of file
+}
+
+
+//most undo points kept, the oldest ones are dropped
+const MAX_ENTRIES: usize = 1000; 
+
+
+//the edits of a buffer grouped by undo points, what undo and redo walk through
+#[derive(Default)]
+pub struct EditHistory {
+    index: usize, //entries before it are done, the ones after it were undone
+    ongoing: Vec<EditDiff>, 
+    entries: Vec<Vec<EditDiff>>,
+}
+
+
+impl EditHistory {
+    pub fn push(&mut self, diff: EditDiff) {
+        self.ongoing.push(diff); 
+    }
+
+    //close the edits since the last undo point, false when there were none
+    pub fn finish_ongoing_edit(&mut self) -> bool {
+        if self.ongoing.is_empty() {
+            return false; 
+        }
+
+        //a new edit forgets what was undone
+        self.entries.truncate(self.index); 
+        self.entries.push(mem::take(&mut self.ongoing)); 
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.remove(0); 
+        }
+        self.index = self.entries.len(); 
+        true
+    }
+
+    //cursor and first changed line after undoing the last entry, None when there is nothing to undo
+    pub fn undo(&mut self, rows: &mut Vec<Row>, tab_width: usize) -> Option<(usize, usize, usize)> {
+        if self.index == 0 {
+            return None; 
+        }
+
+        self.index -= 1; 
+        Some(apply_all(self.entries[self.index].iter().rev(), rows, UndoRedo::Undo, tab_width))
+    }
+
+    pub fn redo(&mut self, rows: &mut Vec<Row>, tab_width: usize) -> Option<(usize, usize, usize)> {
+        if self.index == self.entries.len() {
+            return None; 
+        }
+
+        self.index += 1; 
+        Some(apply_all(self.entries[self.index - 1].iter(), rows, UndoRedo::Redo, tab_width))
+    }
+}
+
+
+//the cursor after the last diff and the first line any of them touched
+fn apply_all<'a>(diffs: impl Iterator<Item = &'a EditDiff>, rows: &mut Vec<Row>, which: UndoRedo, tab_width: usize) -> (usize, usize, usize) {
+    let mut state = (0, 0, usize::MAX); 
+    for diff in diffs {
+        let (x, y) = diff.apply(rows, which, tab_width); 
+        state = (x, y, cmp::min(state.2, diff.first_line().map_or(y, |l| cmp::min(l, y)))); 
+    }
+    state
+}
+
+
+//insert text at x, y, the cursor goes after it
+fn insert_text(rows: &mut Vec<Row>, x: usize, y: usize, text: &str, tab_width: usize) -> (usize, usize) {
+    let mut lines = text.split('\n'); 
+    let first = lines.next().unwrap_or(""); 
+    let rest: Vec<&str> = lines.collect(); 
+
+    let tail = rows[y][x..].to_string(); 
+    rows[y].truncate(x); 
+    rows[y].append(first); 
+    for (i, line) in rest.iter().enumerate() {
+        rows.insert(y + 1 + i, Row::new(*line, tab_width).unwrap()); 
+    }
+
+    let last = y + rest.len(); 
+    let x = if rest.is_empty() { x + first.chars().count() } else { rows[last].len() }; 
+    rows[last].append(tail); 
+    (x, last)
+}
+
+
+//remove the text which starts at x, y
+fn remove_text(rows: &mut Vec<Row>, x: usize, y: usize, text: &str) -> (usize, usize) {
+    let last = y + text.matches('\n').count(); 
+    let end = match text.rfind('\n') {
+        Some(i) => text[i + 1..].chars().count(), 
+        None => x + text.chars().count(), 
+    }; 
+
+    let tail = rows[last][end..].to_string(); 
+    rows.drain(y + 1..=last); 
+    rows[y].truncate(x); 
+    rows[y].append(tail); 
+    (x, y)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(lines: &[&str]) -> Vec<Row> {
+        lines.iter().map(|l| Row::new(*l, 4).unwrap()).collect()
+    }
+
+    fn lines(rows: &[Row]) -> Vec<&str> {
+        rows.iter().map(Row::buffer).collect()
+    }
+
+    //redo the diff, then undo it, and expect the rows we started with
+    fn round_trip(start: &[&str], diff: EditDiff, done: &[&str]) {
+        let mut r = rows(start);
+        diff.apply(&mut r, UndoRedo::Redo, 4);
+        assert_eq!(lines(&r), done, "{:?}", diff);
+        diff.apply(&mut r, UndoRedo::Undo, 4);
+        assert_eq!(lines(&r), start, "{:?}", diff);
+    }
+
+    #[test]
+    fn insert_is_undone() {
+        round_trip(&["abc"], EditDiff::Insert(1, 0, "xy".to_string()), &["axybc"]);
+        round_trip(&["abc"], EditDiff::InsertChar(3, 0, 'd'), &["abcd"]);
+    }
+
+    #[test]
+    fn insert_text_is_undone() {
+        round_trip(&["abc"], EditDiff::InsertText(1, 0, "x".to_string()), &["axbc"]);
+        round_trip(&["abc", "def"], EditDiff::InsertText(2, 0, "x\ny\nz".to_string()), &["abx", "y", "zc", "def"]);
+    }
+
+    #[test]
+    fn remove_is_undone() {
+        //x is where the removed text ends
+        round_trip(&["abcdef"], EditDiff::Remove(4, 0, "cd".to_string()), &["abef"]);
+        round_trip(&["abc"], EditDiff::DeleteChar(2, 0, 'b'), &["ac"]);
+    }
+
+    #[test]
+    fn remove_text_is_undone() {
+        round_trip(&["abc"], EditDiff::RemoveText(1, 0, "b".to_string()), &["ac"]);
+        round_trip(&["abc", "def", "ghi"], EditDiff::RemoveText(1, 0, "bc\ndef\ng".to_string()), &["ahi"]);
+    }
+
+    #[test]
+    fn truncate_and_append_are_undone() {
+        round_trip(&["abcdef"], EditDiff::Truncate(0, "def".to_string()), &["abc"]);
+        round_trip(&["abc", "x"], EditDiff::Append(0, "def".to_string()), &["abcdef", "x"]);
+    }
+
+    #[test]
+    fn newline_is_undone() {
+        round_trip(&["abc"], EditDiff::Newline, &["abc", ""]);
+        round_trip(&["abc", "def"], EditDiff::InsertLine(1, "xyz".to_string()), &["abc", "xyz", "def"]);
+        round_trip(&["abc", "def"], EditDiff::DeleteLine(0, "abc".to_string()), &["def"]);
+    }
+
+    #[test]
+    fn history_undoes_and_redoes_whole_entries() {
+        let mut r = rows(&["abc"]);
+        let mut history = EditHistory::default();
+        for diff in [EditDiff::InsertChar(3, 0, 'd'), EditDiff::InsertChar(4, 0, 'e')] {
+            diff.apply(&mut r, UndoRedo::Redo, 4);
+            history.push(diff);
+        }
+        assert!(history.finish_ongoing_edit());
+        assert!(!history.finish_ongoing_edit());
+
+        assert_eq!(history.undo(&mut r, 4), Some((3, 0, 0)));
+        assert_eq!(lines(&r), ["abc"]);
+        assert_eq!(history.undo(&mut r, 4), None);
+
+        assert_eq!(history.redo(&mut r, 4), Some((5, 0, 0)));
+        assert_eq!(lines(&r), ["abcde"]);
+        assert_eq!(history.redo(&mut r, 4), None);
+    }
+}
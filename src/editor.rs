@@ -3,14 +3,27 @@
 
 // use std::fmt::Write;
 
+use std::cmp;
+use std::fs; 
 use std::io::Write; 
-use std::path::Path; 
+use std::env; 
+use std::path::{Path, PathBuf}; 
+use std::time::{Duration, Instant}; 
 use crate::error::Result;
-use crate::language::Language;
 use crate::prompt::{self, PromptResult, Prompt};
-use crate::status::{TextBuffer, Status, CursorDir};
-use crate::screen::Screen;  
-use crate::input::{InputSeq, KeySeq};
+use crate::status::{TextBuffer, CursorDir, SelectionKind};
+use crate::screen::{CursorShape, Screen};  
+use crate::window::{SplitDir, Window, Windows};
+use crate::finder::FindFile;
+use crate::complete::CompletePath;
+use crate::history::{History, HistoryKind};
+use crate::command::{Command, KeyResult, Keymap, RunCommand};
+use crate::config::Config;
+use crate::project::Project;
+use crate::theme::{Background, ChooseTheme, Theme};
+use crate::input::{InputSeq, KeySeq, Mouse, MouseKind, RawMode};
+use crate::event::{Event, EventInput, Events, Timer};
+use crate::vi::{self, InsertAt, Mode, Motion, MotionKind, Operator, Parse, Register, Span, Target, TextObject, Vi};
 /*
  * This is the Editor file for the Terminal Editor
  * Let's write some code here
@@ -19,7 +32,7 @@ use crate::input::{InputSeq, KeySeq};
 
 pub struct Edit<'a, I, W>
 where 
-    I: Iterator<Item = Result<InputSeq>>, 
+    I: EventInput, 
     W: Write,
 {
     editor: &'a mut Editor<I, W>,
@@ -27,26 +40,16 @@ where
 
 
 
-impl<'a, I, W> Edit<'a, I, W>
-where
-    I: Iterator<Item = Result<InputSeq>>,
-    W: Write,
-{
-    pub fn editor(&self) -> &'_ Editor<I, W>{
-        self.editor
-    }
-}
-
 impl<'a, I, W>Iterator for Edit<'a, I, W>
 where 
-    I: Iterator<Item = Result<InputSeq>>, 
+    I: EventInput, 
     W: Write
 {
-    type Item = Result<InputSeq>;
+    type Item = Result<()>;
 
     fn next(&mut self) -> Option<Self::Item> {
         match self.editor.step() {
-            Ok(EditStep::Continue(seq)) => Some(Ok(seq)),
+            Ok(EditStep::Continue) => Some(Ok(())),
             Ok(EditStep::Quit) => None, 
             Err(error) => Some(Err(error)) //since we dont know the error yet
         }
@@ -57,7 +60,7 @@ where
 
 
 enum EditStep {
-    Continue(InputSeq),
+    Continue,
     Quit, 
 }
 
@@ -65,7 +68,7 @@ enum EditStep {
 impl EditStep {
     fn continues(&self) -> bool {
         match self {
-            EditStep::Continue(_) => true, //default input from the user 
+            EditStep::Continue => true, //default input from the user 
             EditStep::Quit => false, 
         }
     }
@@ -73,19 +76,44 @@ impl EditStep {
 
 
 
-pub struct Editor<I: Iterator<Item = Result<InputSeq>>, W: Write>{
+//two presses at the same cell within this time select a word
+const DOUBLE_CLICK: Duration = Duration::from_millis(500); 
+//lines scrolled by one step of the mouse wheel
+const WHEEL_LINES: usize = 3; 
+//how often the files of the buffers are checked for changes by other programs
+const CHECK_FILES_INTERVAL: Duration = Duration::from_secs(2); 
+
+
+//one path per file however it was typed, so the file is not opened twice. files which
+//don't exist yet can't be canonicalized and are joined to the working directory instead
+fn file_key(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| {
+        let path = env::current_dir().map(|cwd| cwd.join(path)).unwrap_or_else(|_| path.to_path_buf()); 
+        path.components().collect()
+    })
+}
+
+
+pub struct Editor<I: EventInput, W: Write>{
     input: I, 
     qutting: bool, 
     screen: Screen<W>,
     bufs: Vec<TextBuffer>, 
-    buf_idx: usize,
-    status_bar: Status, 
+    wins: Windows, 
+    history: History, 
+    keymap: Keymap, 
+    config: Config, 
+    last_click: Option<(Instant, usize, usize)>, //time and screen cell of the last press
+    dragging: bool, 
+    raw_mode: Option<RawMode>, //to give the terminal back while suspended
+    events: Events, 
+    vi: Option<Vi>, //set when the keymap setting is "vi"
 }
 
 
 impl<I, W> Editor<I, W>
     where
-        I: Iterator<Item = Result<InputSeq>>, 
+        I: EventInput, 
         W: Write,
 {
     fn with_buf(
@@ -93,33 +121,169 @@ impl<I, W> Editor<I, W>
         mut input: I, 
         output: W,
         window_size: Option<(usize, usize)>,
+        config: Config, 
     ) -> Result<Editor<I, W>> {
 
         let screen = Screen::new(window_size, &mut input, output)?;
-        let status_bar = Status::from_buffer(&buf, (1, 1));
+        let wins = Windows::new(Window::new(&buf, 0, (1, 1)));
         
-        Ok(Editor {
+        let mut editor = Editor {
             input, 
             qutting: false, 
             bufs: vec![buf], 
-            buf_idx: 0,
+            wins,
             screen, 
-            status_bar
-        })
+            history: History::load(), 
+            keymap: Keymap::new(), 
+            config: Config::new(), 
+            last_click: None, 
+            dragging: false, 
+            raw_mode: None, 
+            events: Events::new()?, 
+            vi: None, 
+        }; 
+        editor.configure(config); 
+        Ok(editor)
+    }
+
+
+    //apply the user configuration, its problems are shown in the message bar
+    fn configure(&mut self, config: Config) {
+        let (keymap, key_warnings) = Keymap::with_bindings(&config.keys); 
+        self.keymap = keymap; 
+
+        let mut warnings: Vec<String> = config.warnings.iter().cloned().chain(key_warnings).collect(); 
+        self.config = config; 
+        warnings.extend(self.load_theme()); 
+
+        self.vi = match self.config.settings().keymap.as_str() {
+            "vi" => Some(self.vi.take().unwrap_or_else(Vi::new)), 
+            "default" => None, 
+            other => {
+                warnings.push(format!("Unknown keymap '{}', it must be \"default\" or \"vi\"", other)); 
+                None
+            }
+        }; 
+
+        match warnings.len() {
+            0 => {}
+            1 => self.screen.set_error_message(&warnings[0]), 
+            n => self.screen.set_error_message(format!("{} (and {} more config warnings)", warnings[0], n - 1)), 
+        }
+
+        self.screen.set_message_timeout(self.config.settings().message_timeout); 
+
+        for idx in 0..self.bufs.len() {
+            self.configure_buf(idx); 
+        }
+    }
+
+
+    //the theme of the config, "auto" picks the one matching the background of the terminal.
+    //returns the problems of the theme
+    fn load_theme(&mut self) -> Vec<String> {
+        let settings = self.config.settings(); 
+        let background = Background::from_name(&settings.background).unwrap_or_else(|| self.screen.background()); 
+        let name = match (settings.theme.as_str(), background) {
+            ("auto", Background::Dark) => settings.dark_theme, 
+            ("auto", Background::Light) => settings.light_theme, 
+            _ => settings.theme, 
+        }; 
+
+        match Theme::load(&name, self.screen.terminal_color()) {
+            Ok(theme) => {
+                let warnings = theme.warnings.iter().map(|w| format!("Theme {}: {}", theme.name, w)).collect(); 
+                self.screen.set_theme(theme); 
+                warnings
+            }
+            Err(err) => vec![err], 
+        }
+    }
+
+
+    //background reported by the terminal after the editor started, the theme was picked with a guess
+    pub fn set_background(&mut self, background: Background) {
+        if background != self.screen.background() {
+            self.screen.set_background(background); 
+            //problems of the theme were already shown by configure
+            self.load_theme(); 
+        }
+    }
+
+
+    //report keys with the kitty keyboard protocol, which tells C-i from TAB and ESC from M-
+    pub fn enable_kitty_keyboard(&mut self) -> Result<()> {
+        self.screen.enable_kitty_keyboard()
+    }
+
+    pub fn set_raw_mode(&mut self, raw_mode: RawMode) {
+        self.raw_mode = Some(raw_mode); 
+    }
+
+
+    //stop like a shell job with Ctrl-Z, the raw mode is off while we are stopped
+    fn suspend(&mut self) -> Result<()> {
+        let raw_mode = match self.raw_mode {
+            Some(raw_mode) => raw_mode, 
+            None => {
+                self.screen.set_error_message("Cannot suspend without a terminal"); 
+                return Ok(()); 
+            }
+        }; 
+
+        self.screen.suspend()?; 
+        raw_mode.leave()?; 
+        //returns once the shell sends SIGCONT
+        signal_hook::low_level::raise(signal_hook::consts::SIGTSTP)?; 
+        raw_mode.enter()?; 
+        self.screen.resume()?; 
+
+        //the terminal may have been resized meanwhile
+        self.screen.maybe_resize(&mut self.input)?; 
+        self.will_reset_screen(); 
+        Ok(())
+    }
+
+
+    //settings of the buffer from the user config and from the project its file is in
+    fn configure_buf(&mut self, idx: usize) {
+        let project = self.bufs[idx].path().map(Project::load); 
+        if let Some(warning) = project.as_ref().and_then(|p| p.warnings.first()) {
+            self.screen.set_error_message(warning.as_str()); 
+        }
+
+        let settings = self.config.settings_for(self.bufs[idx].lang(), project.as_ref()); 
+        self.bufs[idx].set_settings(settings); 
+    }
+
+
+    //add a buffer with the settings of its language, returns its index
+    fn push_buf(&mut self, buf: TextBuffer) -> usize {
+        self.bufs.push(buf); 
+        let idx = self.bufs.len() - 1; 
+        self.configure_buf(idx); 
+        idx
     }
 
 
     fn refresh_statusbar(&mut self) {
-        self.status_bar.set_buf_pos((self.buf_idx + 1, self.bufs.len())); 
-        self.status_bar.update_from_but(&self.bufs[self.buf_idx]); 
+        let focus = self.wins.focus(); 
+        let len = self.bufs.len(); 
+        let mode = self.vi.as_ref().map(|vi| vi.mode.name()); 
+
+        for (idx, win) in self.wins.iter_mut().enumerate() {
+            let buf = &self.bufs[win.buf_idx]; 
+            let cursor = if idx == focus { buf.cursor() } else { win.cursor() }; 
+            win.status_bar.set_buf_pos((win.buf_idx + 1, len)); 
+            win.status_bar.set_mode(if idx == focus { mode } else { None }); 
+            win.status_bar.update_from_view(buf, cursor); 
+        }
     }
 
 
     fn render_screen(&mut self) -> Result<()> {
         self.refresh_statusbar(); 
-        self.screen.render(&self.bufs[self.buf_idx], &self.status_bar)?;
-        self.status_bar.redraw  = false; 
-
+        self.screen.render(&mut self.wins, &self.bufs)?;
 
         Ok(())
         //continue here
@@ -127,37 +291,144 @@ impl<I, W> Editor<I, W>
 
 
     fn will_reset_scroll(&mut self){
-        self.screen.set_dirty_start(0); 
-        self.screen.row_off = 0; 
-        self.screen.col_off = 0; 
+        self.wins.focused_mut().reset_scroll(); 
     }
 
     fn will_reset_screen(&mut self) {
-        self.screen.set_dirty_start(self.screen.row_off); 
+        self.screen.redraw_all(); 
         self.screen.unset_message(); 
-        self.status_bar.redraw = true; 
+    }
+
+
+    //move the focus to window idx, each window keeps its own cursor of the buffer
+    fn focus_window(&mut self, idx: usize) {
+        if idx == self.wins.focus() {
+            return; 
+        }
+
+        let cursor = self.buf().cursor(); 
+        self.wins.focused_mut().set_cursor(cursor); 
+        self.wins.set_focus(idx); 
+
+        let cursor = self.wins.focused().cursor(); 
+        self.buf_mut().restore_cursor(cursor); 
+        self.screen.cursor_moved = true; 
+    }
+
+
+    fn split_window(&mut self, dir: SplitDir) {
+        let buf_idx = self.buf_idx(); 
+        let buf_pos = (buf_idx + 1, self.bufs.len()); 
+
+        if !self.wins.split(dir, &self.bufs[buf_idx], buf_pos) {
+            self.screen.set_error_message("Window is too small to split"); 
+        }
+    }
+
+
+    fn close_window(&mut self) {
+        let cursor = self.buf().cursor(); 
+        self.wins.focused_mut().set_cursor(cursor); 
+
+        if !self.wins.close() {
+            self.screen.set_error_message("Cannot close the only window"); 
+            return; 
+        }
+
+        let cursor = self.wins.focused().cursor(); 
+        self.buf_mut().restore_cursor(cursor); 
+        self.screen.redraw_all(); 
+    }
+
+
+    fn only_window(&mut self) {
+        if self.wins.len() > 1 {
+            self.wins.only(); 
+            self.screen.redraw_all(); 
+        }
+    }
+
+
+    fn next_window(&mut self) {
+        if self.wins.len() == 1 {
+            self.screen.set_info_message("No other window"); 
+            return; 
+        }
+
+        let idx = self.wins.next_idx(); 
+        self.focus_window(idx); 
+    }
+
+
+    fn move_to_window(&mut self, dir: CursorDir) {
+        let win = self.wins.focused(); 
+        let (y, x) = win.screen_cursor(); 
+        let (row, col) = (win.rect.top + y, win.rect.left + x); 
+
+        match self.wins.neighbor(dir, (row, col)) {
+            Some(idx) => self.focus_window(idx), 
+            None => self.screen.set_info_message("No window in that direction"), 
+        }
+    }
+
+
+    fn resize_window(&mut self, dir: SplitDir, delta: isize) {
+        if !self.wins.resize(dir, delta) {
+            self.screen.set_info_message("No window to resize in that direction"); 
+        }
     }
 
     fn open_buffer(&mut self) -> Result<()>{
-       if let PromptResult::Input(input) = self.prompt::<prompt::NoAction>(
-        "Open: {} (Empty name for new text buffer", 
+       if let PromptResult::Input(input) = self.prompt::<CompletePath>(
+        "Open: {} (Empty name for new text buffer, Tab to complete)", 
+        Some(HistoryKind::Open),
         false,
        )? {
 
-        let buf = if input.is_empty() {
-            TextBuffer::empty() //when the buffer is empty 
+        if input.is_empty() {
+            let idx = self.push_buf(TextBuffer::empty()); //when the buffer is empty 
+            self.wins.focused_mut().buf_idx = idx; 
+            self.will_reset_scroll(); 
         }else {
-            TextBuffer::open(input)?
-        }; 
-
-        self.bufs.push(buf); 
-        self.buf_idx = self.bufs.len() - 1; 
+            self.open_file(&input)?; 
+        }
        }
 
        Ok(())
     }
 
 
+    //open path in the focused window, reusing its buffer when the file is already open
+    fn open_file(&mut self, path: &str) -> Result<()> {
+        let key = file_key(Path::new(path)); 
+        let existing = self.bufs.iter().position(|b| b.path().map(file_key).as_ref() == Some(&key)); 
+
+        let idx = match existing {
+            Some(idx) => idx, 
+            None => {
+                let charset = self.config.settings_for_file(Path::new(path)).charset; 
+                self.push_buf(TextBuffer::open(path, &charset)?)
+            }
+        }; 
+
+        if idx != self.buf_idx() {
+            self.wins.focused_mut().buf_idx = idx; 
+            self.will_reset_scroll(); 
+        }
+
+        Ok(())
+    }
+
+
+    fn find_file(&mut self) -> Result<()> {
+        if let PromptResult::Input(path) = self.prompt::<FindFile>("Find file: {}", None, false)? {
+            self.open_file(&path)?; 
+        }
+
+        Ok(())
+    }
+
+
     fn switch_buffer(&mut self, idx: usize){
         let len = self.bufs.len(); 
 
@@ -168,8 +439,7 @@ impl<I, W> Editor<I, W>
 
 
 
-        self.buf_idx = idx; 
-        let buf = self.buf(); 
+        self.wins.focused_mut().buf_idx = idx; 
 
 
         //then reset scroll 
@@ -178,24 +448,255 @@ impl<I, W> Editor<I, W>
 
 
     fn next_buffer(&mut self){
-        self.switch_buffer(if self.buf_idx == self.bufs.len() - 1 {
+        let idx = self.buf_idx(); 
+        self.switch_buffer(if idx == self.bufs.len() - 1 {
             0
 
         }else {
-            self.buf_idx + 1
+            idx + 1
         })
     }
 
     fn previous_buffer(&mut self){
-        self.switch_buffer(if self.buf_idx == 0 {
+        let idx = self.buf_idx(); 
+        self.switch_buffer(if idx == 0 {
             self.bufs.len() - 1
         
         }else {
-            self.buf_idx - 1
+            idx - 1
         })
     }
 
 
+    fn select_buffer(&mut self) -> Result<()> {
+        if self.bufs.len() == 1 {
+            self.screen.set_info_message("No other buffer"); 
+            return Ok(()); 
+        }
+
+        self.prompt::<prompt::SwitchBuffer>("Switch to buffer: {}", None, false)?; 
+        Ok(())
+    }
+
+
+    //remove the buffer at idx, windows showing it move on to a neighbor buffer
+    fn remove_buffer(&mut self, idx: usize) {
+        self.bufs.remove(idx); 
+        if self.bufs.is_empty() {
+            self.push_buf(TextBuffer::empty()); 
+        }
+
+        let len = self.bufs.len(); 
+        for win in self.wins.iter_mut() {
+            if win.buf_idx == idx {
+                win.buf_idx = cmp::min(idx.saturating_sub(1), len - 1); 
+                win.set_cursor(self.bufs[win.buf_idx].cursor()); 
+                win.reset_scroll(); 
+            } else if win.buf_idx > idx {
+                win.buf_idx -= 1; 
+            }
+        }
+
+        self.screen.redraw_all(); 
+    }
+
+
+    //ask a question in the message bar and wait for one of the choice keys, None when canceled
+    fn ask_choice(&mut self, question: &str, choices: &[u8]) -> Result<Option<u8>> {
+        self.screen.set_info_message(question); 
+        self.render_screen()?; 
+
+        let mut choice = None; 
+        loop {
+            let seq = match self.events.next(&mut self.input)? {
+                Event::Key(seq) => seq, 
+                Event::Resize => {
+                    if self.screen.maybe_resize(&mut self.input)? {
+                        self.will_reset_screen(); 
+                        self.screen.set_info_message(question); 
+                        self.render_screen()?; 
+                    }
+                    continue; 
+                }
+                Event::Mouse(_) | Event::Paste(_) => continue, 
+                //the question stays on the message bar until it is answered
+                event => {
+                    self.events.defer(event); 
+                    continue; 
+                }
+            }; 
+
+            match seq {
+                InputSeq { key: KeySeq::Key(b), ctrl: false, alt: false, .. } if choices.contains(&b.to_ascii_lowercase()) => {
+                    choice = Some(b.to_ascii_lowercase()); 
+                    break; 
+                }
+                InputSeq { key: KeySeq::Key(b'g'), ctrl: true, .. } | InputSeq { key: KeySeq::Key(0x1b), .. } => break, 
+                _ => {}
+            }
+        }
+
+        self.events.resume(); 
+        self.screen.unset_message(); 
+        Ok(choice)
+    }
+
+
+    //ask to save or discard the unsaved changes of the current buffer, false when it must be kept
+    fn confirm_close(&mut self) -> Result<bool> {
+        if !self.buf().modified() {
+            return Ok(true); 
+        }
+
+        let question = format!(
+            "{} has unsaved changes: (s)ave, (d)iscard or (c)ancel?", 
+            self.buf().filename(),
+        ); 
+
+        match self.ask_choice(&question, b"sdc")? {
+            Some(b's') => {
+                self.save()?; 
+                //saving failed or Save-as was canceled, keep the buffer
+                Ok(!self.buf().modified())
+            }
+            Some(b'd') => Ok(true), 
+            _ => {
+                self.screen.set_info_message("Canceled"); 
+                Ok(false)
+            }
+        }
+    }
+
+
+    //close the current buffer, unsaved changes are saved or dropped only when the user says so
+    fn kill_buffer(&mut self) -> Result<()> {
+        if !self.confirm_close()? {
+            return Ok(()); 
+        }
+
+        let name = self.buf().filename().to_string(); 
+        let idx = self.buf_idx(); 
+        self.remove_buffer(idx); 
+
+        let cursor = self.wins.focused().cursor(); 
+        self.buf_mut().restore_cursor(cursor); 
+        self.screen.set_info_message(format!("Closed {}", name)); 
+        Ok(())
+    }
+
+
+    //full screen list of the open buffers, several of them can be marked and closed at once
+    fn list_buffers(&mut self) -> Result<()> {
+        use KeySeq::*; 
+
+        let mut selected = self.buf_idx(); 
+        let mut marked = vec![false; self.bufs.len()]; 
+
+        loop {
+            let lines: Vec<String> = self.bufs
+                .iter()
+                .enumerate()
+                .map(|(i, b)| format!(
+                    "{} {} {:>3}  {}", 
+                    if marked[i] { 'D' } else { ' ' }, 
+                    if b.modified() { '*' } else { ' ' }, 
+                    i + 1, 
+                    b.filename(),
+                ))
+                .collect(); 
+            self.screen.render_list(
+                "Buffers -- d: mark to close, u: unmark, x: close marked, Enter: switch, q: quit", 
+                &lines, 
+                selected,
+            )?; 
+
+            let seq = match self.events.next(&mut self.input)? {
+                Event::Key(seq) => seq, 
+                //the list is rendered again for the new size at the top of the loop
+                Event::Resize => {
+                    if self.screen.maybe_resize(&mut self.input)? {
+                        self.screen.redraw_all(); 
+                    }
+                    continue; 
+                }
+                Event::Mouse(_) | Event::Paste(_) => continue, 
+                event => {
+                    self.events.defer(event); 
+                    continue; 
+                }
+            }; 
+
+            let last = self.bufs.len() - 1; 
+            match seq {
+                InputSeq { key: UpKey, .. } | InputSeq { key: Key(b'p'), ctrl: true, .. } | InputSeq { key: Key(b'k'), ctrl: false, .. } => {
+                    selected = selected.saturating_sub(1); 
+                }
+                InputSeq { key: DownKey, .. } | InputSeq { key: Key(b'n'), ctrl: true, .. } | InputSeq { key: Key(b'j'), ctrl: false, .. } => {
+                    selected = cmp::min(selected + 1, last); 
+                }
+                InputSeq { key: Key(b'd'), ctrl: false, .. } | InputSeq { key: Key(b'u'), ctrl: false, .. } => {
+                    marked[selected] = seq.key == Key(b'd'); 
+                    selected = cmp::min(selected + 1, last); 
+                }
+                InputSeq { key: Key(b'x'), ctrl: false, .. } => {
+                    let (closed, kept) = self.close_marked_buffers(&marked)?; 
+                    self.screen.set_info_message(if kept > 0 {
+                        format!("Closed {} buffer(s), kept {} modified buffer(s)", closed, kept)
+                    } else {
+                        format!("Closed {} buffer(s)", closed)
+                    }); 
+                    marked = vec![false; self.bufs.len()]; 
+                    selected = cmp::min(selected, self.bufs.len() - 1); 
+                }
+                InputSeq { key: EnterKey, .. } => {
+                    self.wins.focused_mut().buf_idx = selected; 
+                    self.will_reset_scroll(); 
+                    break; 
+                }
+                InputSeq { key: Key(b'q'), ctrl: false, .. } | InputSeq { key: Key(b'g'), ctrl: true, .. } | InputSeq { key: Key(0x1b), .. } => break, 
+                _ => {}
+            }
+        }
+
+        self.events.resume(); 
+        self.screen.redraw_all(); 
+        Ok(())
+    }
+
+
+    //close the marked buffers, the ones with unsaved changes after asking as kill_buffer does.
+    //returns how many were closed and kept
+    fn close_marked_buffers(&mut self, marked: &[bool]) -> Result<(usize, usize)> {
+        let (mut closed, mut kept) = (0, 0); 
+        let mut current = self.buf_idx(); 
+
+        for idx in (0..marked.len()).rev().filter(|i| marked[*i]) {
+            if self.bufs[idx].modified() {
+                //the question and Save-as are about the buffer of the focused window
+                self.wins.focused_mut().buf_idx = idx; 
+                self.will_reset_scroll(); 
+                self.will_reset_screen(); 
+                if !self.confirm_close()? {
+                    kept += 1; 
+                    continue; 
+                }
+            }
+
+            self.remove_buffer(idx); 
+            closed += 1; 
+            if current >= idx {
+                current = current.saturating_sub(1); 
+            }
+        }
+
+        //back to the buffer shown before, or the one before it when it was closed
+        self.wins.focused_mut().buf_idx = cmp::min(current, self.bufs.len() - 1); 
+        self.will_reset_scroll(); 
+
+        Ok((closed, kept))
+    }
+
+
     fn handle_not_mapped(&mut self, seq: &InputSeq) {
         self.screen.set_error_message(format!("Key ''{} not mapped", seq))
     }
@@ -212,37 +713,48 @@ impl<I, W> Editor<I, W>
     fn prompt<A: prompt::Action>(
         &mut self, 
         prompt: &str, 
+        history: Option<HistoryKind>, 
         empty_is_cancel: bool
     ) -> Result<PromptResult>{
 
-        Prompt::new(
+        let result = Prompt::new(
             &mut self.screen, 
-            &mut self.bufs[self.buf_idx], 
-            &mut self.status_bar, 
+            &mut self.wins, 
+            &mut self.bufs, 
+            &mut self.history, 
+            &mut self.events, 
+            history, 
             empty_is_cancel,
         )
-        .run::<A, _, _>(prompt, &mut self.input)
+        .run::<A, _, _>(prompt, &mut self.input); 
+
+        self.events.resume(); 
+        result
     }
 
     
 
-    pub fn new(input: I, output: W, window_size: Option<(usize, usize)>) -> Result<Editor<I, W> >{
+    pub fn new(input: I, output: W, window_size: Option<(usize, usize)>, config: Config) -> Result<Editor<I, W> >{
         //return the buffer here
-        Self::with_buf(TextBuffer::empty(), input, output, window_size)
+        Self::with_buf(TextBuffer::empty(), input, output, window_size, config)
     }
 
     pub fn buf(&self) -> &TextBuffer  {
-        &self.bufs[self.buf_idx]
+        &self.bufs[self.buf_idx()]
         //self.bufs[self.buf_idx]
     }
 
+    fn buf_idx(&self) -> usize {
+        self.wins.focused().buf_idx
+    }
+
     pub fn first_paint(&mut self) -> Result<Edit<'_, I, W>>{
-        if self.buf().from_sctrach() {
-            self.screen.render_welcome(&self.status_bar)?; 
-            self.status_bar.redraw = false; 
+        if self.buf().is_scratch() {
+            self.refresh_statusbar(); 
+            self.screen.render_welcome(&mut self.wins)?; 
             
         }else {
-           self.render_screen(); 
+           self.render_screen()?; 
         }
 
 
@@ -254,16 +766,53 @@ impl<I, W> Editor<I, W>
     pub fn edit(&mut self) -> Result<()>{
        // self.first_paint(); 
 
-       self.first_paint()?.try_for_each(|x| x.map(|_| ()))
+       self.events.schedule(Timer::CheckFiles, CHECK_FILES_INTERVAL); 
+       self.schedule_message_expiry(); 
+       self.update_cursor_shape()?; 
+       self.first_paint()?.try_for_each(|x| x)
     }
 
 
+    //move to the next match of the text asked for, wrapping around the end of the buffer. Its matches
+    //stay highlighted until the search is canceled or finds nothing
     fn find(&mut self) -> Result<()> {
-        //would implement a search struct later on 
+        match self.prompt::<prompt::NoAction>("Search: {}", Some(HistoryKind::Search), true)? {
+            PromptResult::Input(query) => match self.buf().find(&query, self.buf().cursor()) {
+                Some((x, y)) => {
+                    self.buf_mut().set_cursor(x, y); 
+                    self.buf_mut().set_search(Some(query)); 
+                }
+                None => {
+                    self.buf_mut().set_search(None); 
+                    self.screen.set_error_message(format!("Not found: {}", query)); 
+                }
+            }, 
+            PromptResult::Canceled => self.buf_mut().set_search(None), 
+        }
+
+        Ok(())
+    }
+
+    //replace every match of the text asked for in the buffer, as one edit
+    fn replace(&mut self) -> Result<()> {
+        let query = match self.prompt::<prompt::NoAction>("Replace: {}", Some(HistoryKind::Replace), true)? {
+            PromptResult::Input(query) => query, 
+            PromptResult::Canceled => return Ok(()), 
+        }; 
+
+        //the template of the prompt is cut at the first {}
+        let prompt = format!("Replace {} with: {{}}", query.replace("{}", "{ }")); 
+        if let PromptResult::Input(text) = self.prompt::<prompt::NoAction>(&prompt, Some(HistoryKind::Replace), false)? {
+            match self.buf_mut().replace_all(&query, &text) {
+                0 => self.screen.set_error_message(format!("Not found: {}", query)), 
+                n => self.screen.set_info_message(format!("Replaced {} occurrence(s)", n)), 
+            }
+        }
+
         Ok(())
     }
 
-    fn handle_quit(&mut self, s: InputSeq) -> EditStep {
+    fn handle_quit(&mut self) -> EditStep {
         let modified = self.bufs.iter().any(|b | b.modified()); 
         if !modified || self.qutting {
             EditStep::Quit
@@ -274,7 +823,7 @@ impl<I, W> Editor<I, W>
                 "Some of your files are unsaved!"
             );
 
-            EditStep::Continue(s)
+            EditStep::Continue
         }
     }
 
@@ -284,194 +833,945 @@ impl<I, W> Editor<I, W>
 
 
     fn step(&mut self) -> Result<EditStep>{
-        let seq = if let Some(seq) = self.input.next(){
-            seq?
-        
-        } else {
-            return Ok(EditStep::Quit)
-        }; 
+        let event = self.events.next(&mut self.input)?; 
+        let step = self.handle_event(event)?;
+
+        if step.continues(){
+            self.render_screen()?; 
+            self.schedule_message_expiry(); 
+        }
+
 
+        Ok(step)
+    }
 
-        if self.screen.maybe_resize(&mut self.input)? {
-            self.will_reset_screen(); 
+
+    fn handle_event(&mut self, event: Event) -> Result<EditStep> {
+        match event {
+            Event::Key(seq) => return self.process_keypress(seq), 
+            Event::Paste(text) => self.paste(&text), 
+            Event::Mouse(mouse) => self.handle_mouse(mouse), 
+            Event::Resize => {
+                if self.screen.maybe_resize(&mut self.input)? {
+                    self.will_reset_screen(); 
+                }
+            }
+            //render_screen clears the expired message
+            Event::Timer(Timer::MessageExpired) => {}
+            Event::Timer(Timer::CheckFiles) => {
+                self.check_files(); 
+                self.events.schedule(Timer::CheckFiles, CHECK_FILES_INTERVAL); 
+            }
+            //the prompt which asked for it is closed
+            Event::Progress | Event::JobDone => {}
+            Event::FileChanged(path, mtime) => {
+                if let Some(buf) = self.bufs.iter_mut().find(|b| b.path() == Some(path.as_path())) {
+                    if buf.disk_changed(mtime) {
+                        let message = format!("{} was changed on disk", buf.filename()); 
+                        self.screen.set_error_message(message); 
+                    }
+                }
+            }
         }
 
+        Ok(EditStep::Continue)
+    }
 
-        let step = self.process_keypress(seq)?;
 
-        if step.continues(){
-            self.render_screen()?
+    //stat the files of the buffers on another thread, a slow file system does not stop the editor
+    fn check_files(&mut self) {
+        let files: Vec<_> = self.bufs
+            .iter()
+            .filter_map(|b| Some((b.path()?.to_path_buf(), b.mtime()?)))
+            .collect(); 
+        if files.is_empty() {
+            return; 
         }
 
+        self.events.spawn(move |notifier| {
+            for (path, mtime) in files {
+                let modified = match fs::metadata(&path).and_then(|m| m.modified()) {
+                    Ok(modified) if modified != mtime => modified, 
+                    _ => continue, 
+                }; 
+                if !notifier.send(Event::FileChanged(path, modified)) {
+                    break; 
+                }
+            }
+        }); 
+    }
 
-        Ok(step)
+
+    //the message bar is cleared while the user is idle
+    fn schedule_message_expiry(&mut self) {
+        match self.screen.message_expiry() {
+            Some(at) => self.events.schedule_at(Timer::MessageExpired, at), 
+            None => self.events.cancel(Timer::MessageExpired), 
+        }
 
     }
 
     fn show_help(&mut self) -> Result<()> {
-        self.screen.render_help()?; 
+        let help = self.keymap.help(); 
+        self.screen.render_help(&help)?; 
+
+        //any key closes the help, the timers and the other events keep going meanwhile
+        loop {
+            match self.events.next(&mut self.input)? {
+                Event::Resize => {
+                    if self.screen.maybe_resize(&mut self.input)? {
+                        self.screen.render_help(&help)?; 
+                    }
+                }
+                Event::Key(seq) if seq.key != KeySeq::NotIdentified => break, 
+                Event::Key(_) | Event::Mouse(_) | Event::Paste(_) => {}
+                event => {
+                    self.handle_event(event)?; 
+                }
+            }
+        }
 
-        // while let Some(seq) = self.input.next() {
-        //     if self.screen.maybe_resize(&mut self.input) {
-        //         self.screen.render_help()?; 
-        //         self.status_bar.redraw = true; 
-        //     }
+        self.screen.redraw_all(); 
 
+        Ok(())
 
-        //     if seq?.key != KeySeq::NotIdentified {
-        //         break;
-        //     }
-        // }
+    }
 
 
-        self.screen.set_dirty_start(self.screen.row_off); 
+
+    //ask for a command name with fuzzy completion and run it
+    fn command_palette(&mut self) -> Result<bool> {
+        if let PromptResult::Input(name) = self.prompt::<RunCommand>("M-x {}", Some(HistoryKind::Command), true)? {
+            if let Some(cmd) = Command::from_name(&name) {
+                return self.run_command(cmd); 
+            }
+        }
+
+        Ok(false)
+    }
+
+
+    fn switch_theme(&mut self) -> Result<()> {
+        let current = self.screen.theme().name.clone(); 
+        if let PromptResult::Input(name) = self.prompt::<ChooseTheme>(&format!("Theme ({}): {{}}", current), None, true)? {
+            match Theme::load(&name, self.screen.terminal_color()) {
+                Ok(theme) => {
+                    match theme.warnings.first() {
+                        Some(warning) => self.screen.set_error_message(format!("Theme {}: {}", name, warning)), 
+                        None => self.screen.set_info_message(format!("Theme {}", name)), 
+                    }
+                    self.screen.set_theme(theme); 
+                }
+                Err(err) => self.screen.set_error_message(err), 
+            }
+        }
 
         Ok(())
+    }
+
+
+    //returns true when the command asks to quit
+    fn run_command(&mut self, cmd: Command) -> Result<bool> {
+        use Command::*; 
+
+        let rowoff = self.wins.focused().row_off; 
+        let rows = self.wins.focused().rows(); 
+
+        match cmd {
+            Quit => return Ok(true), 
+            Save => self.save()?, 
+            OpenBuffer => self.open_buffer()?, 
+            NextBuffer => self.next_buffer(), 
+            PreviousBuffer => self.previous_buffer(), 
+            SwitchBuffer => self.select_buffer()?, 
+            ListBuffers => self.list_buffers()?, 
+            KillBuffer => self.kill_buffer()?, 
+            FindFile => self.find_file()?, 
+            CursorUp => self.buf_mut().move_cursor_one(CursorDir::Up), 
+            CursorDown => self.buf_mut().move_cursor_one(CursorDir::Down), 
+            CursorRight => self.buf_mut().move_cursor_one(CursorDir::Right), 
+            CursorLeft => self.buf_mut().move_cursor_one(CursorDir::Left), 
+            LineHead => self.buf_mut().move_cursor_to_buffer_edge(CursorDir::Left), 
+            LineEnd => self.buf_mut().move_cursor_to_buffer_edge(CursorDir::Right), 
+            NextPage => self.buf_mut().move_cursor_page(CursorDir::Down, rowoff, rows), 
+            PreviousPage => self.buf_mut().move_cursor_page(CursorDir::Up, rowoff, rows), 
+            NextWord => self.buf_mut().move_cursor_by_word(CursorDir::Right), 
+            PreviousWord => self.buf_mut().move_cursor_by_word(CursorDir::Left), 
+            NextParagraph => self.buf_mut().move_cursor_paragraph(CursorDir::Down), 
+            PreviousParagraph => self.buf_mut().move_cursor_paragraph(CursorDir::Up), 
+            BufferTop => self.buf_mut().move_cursor_to_buffer_edge(CursorDir::Up), 
+            BufferBottom => self.buf_mut().move_cursor_to_buffer_edge(CursorDir::Down), 
+            DeleteChar => self.buf_mut().delete_char(), 
+            DeleteNextChar => self.buf_mut().delete_right_char(), 
+            DeleteWord => self.buf_mut().delete_word(), 
+            DeleteToLineHead => self.buf_mut().delete_until_head_of_line(), 
+            DeleteToLineEnd => self.buf_mut().delete_until_end_of_line(), 
+            InsertTab => self.buf_mut().insert_tab(), 
+            NewLine => self.buf_mut().insert_line(), 
+            Search => self.find()?, 
+            Replace => self.replace()?, 
+            Undo => {
+                if !self.buf_mut().undo() {
+                    self.screen.set_info_message("No older change"); 
+                }
+            }
+            Redo => {
+                if !self.buf_mut().redo() {
+                    self.screen.set_info_message("Already at newest change"); 
+                }
+            }
+            Refresh => {
+                self.screen.redraw_all(); // Clear
+                self.screen.unset_message();
+            }
+            SplitHorizontally => self.split_window(SplitDir::Horizontal), 
+            SplitVertically => self.split_window(SplitDir::Vertical), 
+            CloseWindow => self.close_window(), 
+            OnlyWindow => self.only_window(), 
+            NextWindow => self.next_window(), 
+            WindowLeft => self.move_to_window(CursorDir::Left), 
+            WindowDown => self.move_to_window(CursorDir::Down), 
+            WindowUp => self.move_to_window(CursorDir::Up), 
+            WindowRight => self.move_to_window(CursorDir::Right), 
+            EnlargeHeight => self.resize_window(SplitDir::Horizontal, 1), 
+            ShrinkHeight => self.resize_window(SplitDir::Horizontal, -1), 
+            EnlargeWidth => self.resize_window(SplitDir::Vertical, 1), 
+            ShrinkWidth => self.resize_window(SplitDir::Vertical, -1), 
+            Palette => return self.command_palette(), 
+            Help => self.show_help()?, 
+            SwitchTheme => self.switch_theme()?, 
+            Suspend => self.suspend()?, 
+        }
 
+        Ok(false)
     }
 
 
 
     fn process_keypress(&mut self, s: InputSeq) -> Result<EditStep>{
+        if s.key == KeySeq::NotIdentified {
+            return Ok(EditStep::Continue); 
+        }
+
+        if self.vi.is_some() {
+            return self.vi_keypress(s); 
+        }
+        self.default_keypress(s)
+    }
+
+
+    //the emacs like keymap, vi insert mode uses it too
+    fn default_keypress(&mut self, s: InputSeq) -> Result<EditStep>{
         use KeySeq::*; 
 
+        let prev_cursor = self.buf().cursor(); 
+        self.clear_selection(); 
+
+        let in_chord = !self.keymap.pending_keys().is_empty(); 
+        match self.keymap.feed(s.clone()) {
+            KeyResult::Run(cmd) => {
+                if in_chord {
+                    self.screen.unset_message(); //the prefix shown while waiting
+                }
+                if self.run_command(cmd)? {
+                    return Ok(self.handle_quit()); 
+                }
+            }
+            KeyResult::Pending => {
+                //keep waiting for the rest of the chord, like "C-x -"
+                self.screen.set_info_message(format!("{} -", self.keymap.pending_keys())); 
+                return Ok(EditStep::Continue); 
+            }
+            KeyResult::Canceled => self.screen.set_info_message("Canceled"), 
+            KeyResult::UnboundChord(keys) => self.screen.set_error_message(format!("{} is not mapped", keys)), 
+            KeyResult::Unbound => match &s {
+                InputSeq { key: Key(b), ctrl: false, alt: false, .. } if !b.is_ascii_control() => self.buf_mut().insert_char(*b as char),
+                InputSeq { key: Utf8Key(c), ctrl: false, alt: false, .. } => self.buf_mut().insert_char(*c),
+                _ => self.handle_not_mapped(&s), 
+            }, 
+        }
+
+        self.finish_edit(prev_cursor); 
+        self.qutting = false; 
+        Ok(EditStep::Continue)
+    }
+
+
+    fn vi_mode(&self) -> Mode {
+        self.vi.as_ref().map_or(Mode::Insert, |vi| vi.mode)
+    }
+
+    //only called while the vi keymap is on
+    fn vi_mut(&mut self) -> &mut Vi {
+        self.vi.as_mut().unwrap()
+    }
+
+
+    //keys of the vi keymap, insert mode uses the default keymap until ESC
+    fn vi_keypress(&mut self, s: InputSeq) -> Result<EditStep> {
+        let step = if self.vi_mode() == Mode::Insert {
+            let esc = s.key == KeySeq::Key(0x1b) && !s.alt; 
+            if let Some(keys) = &mut self.vi_mut().recording {
+                keys.push(s.clone()); 
+            }
+
+            if esc {
+                self.leave_insert(); 
+                EditStep::Continue
+            } else {
+                self.default_keypress(s)?
+            }
+        } else {
+            self.vi_normal_keypress(s)?
+        }; 
+
+        self.vi_after_key()?; 
+        Ok(step)
+    }
+
+
+    fn leave_insert(&mut self) {
+        let vi = self.vi_mut(); 
+        vi.mode = Mode::Normal; 
+        if let Some(keys) = vi.recording.take() {
+            vi.last_change = keys; 
+        }
+
+        let (cx, cy) = self.buf().cursor(); 
+        if cx > 0 {
+            self.buf_mut().set_cursor(cx - 1, cy); 
+            self.screen.cursor_moved = true; 
+        }
+    }
+
+
+    //keys of normal and visual mode make up commands like "d2w"
+    fn vi_normal_keypress(&mut self, s: InputSeq) -> Result<EditStep> {
+        use KeySeq::*; 
+
+        if s.key == Key(0x1b) && !s.alt {
+            if !self.vi_mut().keys.is_empty() {
+                self.vi_mut().keys.clear(); 
+            } else if self.vi_mode().is_visual() {
+                self.exit_visual(); 
+            }
+            return Ok(EditStep::Continue); 
+        }
+
+        if vi::key_char(&s).is_none() {
+            self.vi_mut().keys.clear(); 
+            let prev_cursor = self.buf().cursor(); 
+            match s.key {
+                Key(b'd') if s.ctrl && !s.alt => self.scroll_half_page(false), 
+                Key(b'u') if s.ctrl && !s.alt => self.scroll_half_page(true), 
+                Key(b'r') if s.ctrl && !s.alt => {
+                    if !self.buf_mut().redo() {
+                        self.screen.set_info_message("Already at newest change"); 
+                    }
+                }
+                //keys without a vi meaning do nothing
+                _ => return Ok(EditStep::Continue), 
+            }
+            self.finish_edit(prev_cursor); 
+            return Ok(EditStep::Continue); 
+        }
+
+        //a selection made with the mouse ends like in the default keymap
+        if !self.vi_mode().is_visual() {
+            self.clear_selection(); 
+        }
+
+        let vi = self.vi_mut(); 
+        vi.keys.push(s); 
+        let (count, cmd) = match vi::parse(&vi.chars(), vi.mode.is_visual()) {
+            Parse::Done(count, cmd) => (count, cmd), 
+            Parse::Pending => return Ok(EditStep::Continue), 
+            Parse::Invalid => {
+                vi.keys.clear(); 
+                return Ok(EditStep::Continue); 
+            }
+        }; 
+
+        //. repeats the keys of the last change, with the ones typed in insert mode after them
+        let keys = std::mem::take(&mut vi.keys); 
+        if !vi.replaying && cmd.changes_text() && !matches!(cmd, vi::Command::Operate(_, Target::Selection)) {
+            vi.recording = Some(keys); 
+        }
 
-        let rowoff = self.screen.row_off; 
-        let rows = self.screen.rows(); 
         let prev_cursor = self.buf().cursor(); 
+        if let Some(step) = self.vi_run(count, cmd)? {
+            return Ok(step); 
+        }
+        self.finish_edit(prev_cursor); 
 
+        let vi = self.vi_mut(); 
+        if vi.mode != Mode::Insert {
+            if let Some(keys) = vi.recording.take() {
+                vi.last_change = keys; 
+            }
+        }
 
-        match &s {
-            InputSeq {
-                key: NotIdentified,
-                ..
-            } => return Ok(EditStep::Continue(s)), 
-            InputSeq { key, ctrl: true, ..} => match key {
-                Key(b'v') => self.buf_mut().move_cursor_page(CursorDir::Up, rowoff, rows),
-                Key(b'f') => self.buf_mut().move_cursor_by_word(CursorDir::Right),
-                Key(b'b') => self.buf_mut().move_cursor_by_word(CursorDir::Left),
-                Key(b'n') => self.buf_mut().move_cursor_paragraph(CursorDir::Down),
-                Key(b'p') => self.buf_mut().move_cursor_paragraph(CursorDir::Up),
-                Key(b'x') => self.previous_buffer(),
-                Key(b'<') => self.buf_mut().move_cursor_to_buffer_edge(CursorDir::Up),
-                Key(b'>') => self.buf_mut().move_cursor_to_buffer_edge(CursorDir::Down),
-                LeftKey => self.buf_mut().move_cursor_to_buffer_edge(CursorDir::Left),
-                RightKey => self.buf_mut().move_cursor_to_buffer_edge(CursorDir::Right),
-                _ => self.handle_not_mapped(&s), 
+        self.qutting = false; 
+        Ok(EditStep::Continue)
+    }
+
+
+    //outside of insert mode the cursor is on a char, never after the end of a line
+    fn vi_after_key(&mut self) -> Result<()> {
+        let mode = self.vi_mode(); 
+        if mode.is_visual() && self.buf().mark().is_none() {
+            //a key of the default keymap ended the selection
+            self.vi_mut().mode = Mode::Normal; 
+        }
+
+        if mode != Mode::Insert {
+            let rows = self.buf().rows(); 
+            let (cx, cy) = self.buf().cursor(); 
+            let y = cmp::min(cy, rows.len().saturating_sub(1)); 
+            let x = cmp::min(cx, rows.get(y).map_or(0, |r| r.len()).saturating_sub(1)); 
+            if (x, y) != (cx, cy) {
+                self.buf_mut().set_cursor(x, y); 
+                self.screen.cursor_moved = true; 
             }
+        }
 
-            InputSeq { key, ctrl: true, ..} => match key {
-                Key(b'p') => self.buf_mut().move_cursor_one(CursorDir::Up),
-                Key(b'b') => self.buf_mut().move_cursor_one(CursorDir::Left),
-                Key(b'n') => self.buf_mut().move_cursor_one(CursorDir::Down),
-                Key(b'f') => self.buf_mut().move_cursor_one(CursorDir::Right),
-                Key(b'v') => self.buf_mut().move_cursor_page(CursorDir::Down, rowoff, rows),
-
-                Key(b'a') => self.buf_mut().move_cursor_to_buffer_edge(CursorDir::Left),
-                Key(b'e') => self.buf_mut().move_cursor_to_buffer_edge(CursorDir::Right),
-                Key(b'd') => self.buf_mut().delete_right_char(),
-                Key(b'g') => self.find()?,
-                Key(b'h') => self.buf_mut().delete_char(),
-                Key(b'k') => self.buf_mut().delete_until_end_of_line(),
-                Key(b'j') => self.buf_mut().delete_until_head_of_line(),
-                Key(b'w') => self.buf_mut().delete_word(),
-                Key(b'l') => {
-                    self.screen.set_dirty_start(self.screen.row_off); // Clear
-                    self.screen.unset_message();
-                    self.status_bar.redraw = true;
-                }
-
-                Key(b's') => self.save()?, 
-                Key(b'i') => self.buf_mut().insert_tab(),
-                Key(b'm') => self.buf_mut().insert_line(), 
-                Key(b'o') => self.open_buffer()?, 
-                Key(b'?') => self.show_help()?, 
-                Key(b'x') => self.next_buffer(),
-                Key(b']') => self.buf_mut().move_cursor_page(CursorDir::Down, rowoff, rows), 
-                Key(b'u') => {
-                    //perform undo actions here
-                }
-                Key(b'r') => {
-                    //perform redo operations here
-                }
-
-                LeftKey => self.buf_mut().move_cursor_by_word(CursorDir::Left), 
-                RightKey => self.buf_mut().move_cursor_by_word(CursorDir::Right), 
-                DownKey => self.buf_mut().move_cursor_by_word(CursorDir::Down), 
-                Key(b'q') => return Ok(self.handle_quit(s)), 
-                //default
-                _ => self.handle_not_mapped(&s),
-            },
-
-            InputSeq { key, ..} => match key {
-                Key(0x1b) => self.buf_mut().move_cursor_page(CursorDir::Up, rowoff, rows), // Clash with Ctrl-[
-                Key(0x08) => self.buf_mut().delete_char(), // Backspace
-                Key(0x7f) => self.buf_mut().delete_char(), // Delete key is mapped to \x1b[3~
-                Key(b'\r') => self.buf_mut().insert_line(),
-                Key(b'q') => return Ok(self.handle_quit(s)), 
-                _ => self.handle_not_mapped(&s),
-            }, 
+        self.update_cursor_shape()
+    }
+
+
+    //a bar while typing text, an underline while an operator waits for its motion
+    fn update_cursor_shape(&mut self) -> Result<()> {
+        let shape = match &self.vi {
+            Some(vi) if matches!(vi.mode, Mode::Insert | Mode::CommandLine) => CursorShape::Bar, 
+            Some(vi) if !vi.keys.is_empty() => CursorShape::Underline, 
+            Some(_) => CursorShape::Block, 
+            None => return Ok(()), 
+        }; 
+        self.screen.set_cursor_shape(shape)
+    }
 
-            InputSeq { key, ..} => match key {
-                Key(0x1b) => self.buf_mut().move_cursor_page(CursorDir::Up, rowoff, rows), // Clash with Ctrl-[
-                Key(0x08) => self.buf_mut().delete_char(), // Backspace
-                Key(0x7f) => self.buf_mut().delete_char(), // Delete key is mapped to \x1b[3~
-                Key(b'\r') => self.buf_mut().insert_line(),
-                Key(b) if !b.is_ascii_control() => self.buf_mut().insert_char(*b as char),
-                Utf8Key(c) => self.buf_mut().insert_char(*c),
-                UpKey => self.buf_mut().move_cursor_one(CursorDir::Up),
-                LeftKey => self.buf_mut().move_cursor_one(CursorDir::Left),
-                DownKey => self.buf_mut().move_cursor_one(CursorDir::Down),
-                RightKey => self.buf_mut().move_cursor_one(CursorDir::Right),
-                PageUpKey => self.buf_mut().move_cursor_page(CursorDir::Up, rowoff, rows),
-                
 
-                PageDownKey => self.buf_mut().move_cursor_page(CursorDir::Down, rowoff, rows), 
-                HomeKey => self.buf_mut().move_cursor_to_buffer_edge(CursorDir::Left),
-                EndKey => self.buf_mut().move_cursor_to_buffer_edge(CursorDir::Right),
+    //Some when the command may quit the editor
+    fn vi_run(&mut self, count: Option<usize>, cmd: vi::Command) -> Result<Option<EditStep>> {
+        use vi::Command::*; 
 
-                DeleteKey => self.buf_mut().delete_right_char(), 
-                _ => self.handle_not_mapped(&s), //default    
+        match cmd {
+            Move(motion) => {
+                let visual = self.vi_mode().is_visual(); 
+                if visual {
+                    self.mark_selection_dirty(); 
+                    //an empty selection takes chars again once the cursor moves
+                    if let Some(anchor) = self.buf().mark().filter(|_| self.buf().selection().is_none()) {
+                        self.buf_mut().select(anchor, SelectionKind::Inclusive); 
+                    }
+                }
+                let (x, y) = vi::motion_target(self.buf().rows(), self.buf().cursor(), motion, count); 
+                self.buf_mut().set_cursor(x, y); 
+                if visual {
+                    self.mark_selection_dirty(); 
+                }
             }
-            
+            Operate(op, target) => self.vi_operate(op, target, count), 
+            Insert(at) => self.vi_insert(at), 
+            Paste { before } => self.vi_paste(before, count), 
+            Visual => self.vi_visual(Mode::Visual), 
+            VisualLine => self.vi_visual(Mode::VisualLine), 
+            SwapAnchor => {
+                if let Some((mx, my)) = self.buf().mark() {
+                    let kind = if self.vi_mode() == Mode::VisualLine { SelectionKind::Lines } else { SelectionKind::Inclusive }; 
+                    let cursor = self.buf().cursor(); 
+                    self.buf_mut().select(cursor, kind); 
+                    self.buf_mut().set_cursor(mx, my); 
+                }
+            }
+            SelectObject(obj) => self.vi_select_object(obj, count), 
+            Ex => return self.vi_command_line(), 
+            Undo => {
+                for _ in 0..count.unwrap_or(1) {
+                    if !self.buf_mut().undo() {
+                        self.screen.set_info_message("No older change"); 
+                        break; 
+                    }
+                }
+            }
+            Repeat => {
+                let keys = self.vi_mut().repeat_keys(count); 
+                self.vi_mut().replaying = true; 
+                let result = keys.into_iter().try_for_each(|key| self.vi_keypress(key).map(|_| ())); 
+                self.vi_mut().replaying = false; 
+                result?; 
+            }
+        }
+
+        Ok(None)
+    }
+
+
+    //v and V start a selection, switch it to the other kind or end it
+    fn vi_visual(&mut self, mode: Mode) {
+        let current = self.vi_mode(); 
+        if current == mode {
+            self.exit_visual(); 
+            return; 
+        }
+
+        let kind = if mode == Mode::VisualLine { SelectionKind::Lines } else { SelectionKind::Inclusive }; 
+        let anchor = self.buf().mark().filter(|_| current.is_visual()).unwrap_or_else(|| self.buf().cursor()); 
+        self.buf_mut().select(anchor, kind); 
+        self.vi_mut().mode = mode; 
+        self.mark_selection_dirty(); 
+    }
+
+
+    //select obj in visual mode. on a selection which has it already the next bigger one is taken
+    fn vi_select_object(&mut self, obj: TextObject, count: Option<usize>) {
+        let buf = self.buf(); 
+        let rows = buf.rows(); 
+        let cursor = buf.cursor(); 
+        let selected = buf.selection().filter(|_| buf.mark() != Some(cursor)); 
+        let covered = |span: Span| match selected {
+            Some(((sx, sy), (ex, ey))) => {
+                let ((ox, oy), (px, py)) = vi::span_range(rows, span); 
+                (sy, sx) <= (oy, ox) && (py, px) <= (ey, ex)
+            }
+            None => false, 
+        }; 
+
+        let mut n = count.unwrap_or(1); 
+        let mut span = vi::object_span(rows, cursor, obj, Some(n)); 
+        while let Some(current) = span.filter(|s| covered(*s)) {
+            n += 1; 
+            match vi::object_span(rows, cursor, obj, Some(n)) {
+                Some(next) if next != current => span = Some(next), 
+                _ => break, 
+            }
+        }
+
+        let (anchor, cursor, kind, mode) = match span {
+            Some(Span::Lines(y0, y1)) => ((0, y0), (0, y1), SelectionKind::Lines, Mode::VisualLine), 
+            //i( on () selects nothing, the cursor goes on the closing bracket
+            Some(Span::Chars(start, end)) if start == end => (start, start, SelectionKind::Exclusive, Mode::Visual), 
+            //the selection takes the char under the cursor, so it goes on the last char
+            Some(Span::Chars(start, end)) => {
+                let last = vi::prev_pos(rows, end).unwrap_or(start); 
+                (start, last, SelectionKind::Inclusive, Mode::Visual)
+            }
+            None => return, 
+        }; 
+
+        self.mark_selection_dirty(); 
+        self.buf_mut().select(anchor, kind); 
+        self.buf_mut().set_cursor(cursor.0, cursor.1); 
+        self.vi_mut().mode = mode; 
+        self.mark_selection_dirty(); 
+    }
+
+
+    fn exit_visual(&mut self) {
+        self.mark_selection_dirty(); 
+        self.buf_mut().set_mark(None); 
+        self.vi_mut().mode = Mode::Normal; 
+    }
+
+
+    //d, c and y over a motion, whole lines or the selection
+    fn vi_operate(&mut self, op: Operator, target: Target, count: Option<usize>) {
+        let mode = self.vi_mode(); 
+        let buf = self.buf(); 
+        let rows = buf.rows(); 
+        let (cx, cy) = buf.cursor(); 
+        let last = rows.len().saturating_sub(1); 
+
+        let span = match target {
+            Target::Selection if mode == Mode::VisualLine => buf.selection().map(|(s, e)| Span::Lines(s.1, e.1)), 
+            Target::Selection => buf.selection().map(|(s, e)| Span::Chars(s, e)), 
+            Target::Lines => Some(Span::Lines(cy, cmp::min(cy + count.unwrap_or(1) - 1, last))), 
+            Target::Object(obj) => vi::object_span(rows, (cx, cy), obj, count), 
+            Target::Motion(motion) if motion.kind() == MotionKind::Lines => {
+                let (_, y) = vi::motion_target(rows, (cx, cy), motion, count); 
+                Some(Span::Lines(cmp::min(y, cy), cmp::max(y, cy)))
+            }
+            Target::Motion(Motion::WordStart) if op == Operator::Change => {
+                let (start, end) = vi::change_word_range(rows, (cx, cy), count); 
+                Some(Span::Chars(start, end))
+            }
+            Target::Motion(motion) => {
+                let (start, end) = vi::motion_range(rows, (cx, cy), motion, count); 
+                Some(Span::Chars(start, end))
+            }
+        }; 
+
+        if mode.is_visual() {
+            self.exit_visual(); 
+        }
+        if let Some(span) = span {
+            self.vi_apply(op, span); 
         }
+        if op == Operator::Change {
+            self.vi_mut().mode = Mode::Insert; 
+        }
+    }
+
+
+    //run op on span, the text goes to the register
+    fn vi_apply(&mut self, op: Operator, span: Span) {
+        let len = |buf: &TextBuffer, y: usize| buf.rows().get(y).map_or(0, |r| r.len()); 
+
+        let register = match span {
+            Span::Lines(y0, y1) => {
+                let end = (len(self.buf(), y1), y1); 
+                let text = format!("{}\n", self.buf().text_range((0, y0), end)); 
+
+                match op {
+                    Operator::Yank => {
+                        let cx = self.buf().cursor().0; 
+                        self.buf_mut().set_cursor(cx, y0); 
+                    }
+                    //one empty line is left to type on
+                    Operator::Change => {
+                        self.buf_mut().delete_range((0, y0), end); 
+                    }
+                    Operator::Delete => {
+                        //the newline after the lines, or before them for the last ones
+                        let rows = self.buf().rows().len(); 
+                        let (start, end) = if y1 + 1 < rows {
+                            ((0, y0), (0, y1 + 1))
+                        } else if y0 > 0 {
+                            ((len(self.buf(), y0 - 1), y0 - 1), end)
+                        } else {
+                            ((0, y0), end)
+                        }; 
+                        self.buf_mut().delete_range(start, end); 
+
+                        let y = cmp::min(y0, self.buf().rows().len().saturating_sub(1)); 
+                        let x = vi::first_non_blank(self.buf().rows(), y); 
+                        self.buf_mut().set_cursor(x, y); 
+                    }
+                }
+                Register { text, lines: true }
+            }
+            Span::Chars(start, end) => {
+                let text = match op {
+                    Operator::Yank => {
+                        self.buf_mut().set_cursor(start.0, start.1); 
+                        self.buf().text_range(start, end)
+                    }
+                    _ => self.buf_mut().delete_range(start, end), 
+                }; 
+                Register { text, lines: false }
+            }
+        }; 
+
+        if !register.text.is_empty() {
+            self.vi_mut().register = register; 
+        }
+    }
 
+
+    //i, a, I, A, o and O
+    fn vi_insert(&mut self, at: InsertAt) {
+        let (cx, cy) = self.buf().cursor(); 
+        let len = self.buf().rows().get(cy).map_or(0, |r| r.len()); 
+
+        match at {
+            InsertAt::Cursor => {}
+            InsertAt::After => self.buf_mut().set_cursor(cmp::min(cx + 1, len), cy), 
+            InsertAt::LineHead => {
+                let x = vi::first_non_blank(self.buf().rows(), cy); 
+                self.buf_mut().set_cursor(x, cy); 
+            }
+            InsertAt::LineEnd => self.buf_mut().set_cursor(len, cy), 
+            InsertAt::LineBelow => {
+                self.buf_mut().set_cursor(len, cy); 
+                self.buf_mut().insert_text("\n"); 
+            }
+            InsertAt::LineAbove => {
+                self.buf_mut().set_cursor(0, cy); 
+                self.buf_mut().insert_text("\n"); 
+                self.buf_mut().set_cursor(0, cy); 
+            }
+        }
+
+        self.vi_mut().mode = Mode::Insert; 
+    }
+
+
+    //p and P put the register after or before the cursor, below or above its line for whole lines
+    fn vi_paste(&mut self, before: bool, count: Option<usize>) {
+        let (text, lines) = {
+            let register = &self.vi_mut().register; 
+            (register.text.repeat(count.unwrap_or(1)), register.lines)
+        }; 
+        if text.is_empty() {
+            return; 
+        }
+
+        let (cx, cy) = self.buf().cursor(); 
+        let len = self.buf().rows().get(cy).map_or(0, |r| r.len()); 
+
+        if lines {
+            let y = if before {
+                self.buf_mut().set_cursor(0, cy); 
+                self.buf_mut().insert_text(&text); 
+                cy
+            } else if cy + 1 < self.buf().rows().len() {
+                self.buf_mut().set_cursor(0, cy + 1); 
+                self.buf_mut().insert_text(&text); 
+                cy + 1
+            } else {
+                //there is no line below to put the text before
+                self.buf_mut().set_cursor(len, cy); 
+                self.buf_mut().insert_text(&format!("\n{}", &text[..text.len() - 1])); 
+                cy + 1
+            }; 
+            let x = vi::first_non_blank(self.buf().rows(), y); 
+            self.buf_mut().set_cursor(x, y); 
+        } else {
+            if !before {
+                self.buf_mut().set_cursor(cmp::min(cx + 1, len), cy); 
+            }
+            self.buf_mut().insert_text(&text); 
+            //on the last char put
+            let (x, y) = self.buf().cursor(); 
+            self.buf_mut().set_cursor(x.saturating_sub(1), y); 
+        }
+    }
+
+
+    //the : prompt, Some when its command may quit the editor
+    fn vi_command_line(&mut self) -> Result<Option<EditStep>> {
+        self.vi_mut().mode = Mode::CommandLine; 
+        self.update_cursor_shape()?; 
+        let result = self.prompt::<prompt::NoAction>(":{}", Some(HistoryKind::Ex), true); 
+        self.vi_mut().mode = Mode::Normal; 
+
+        match result? {
+            PromptResult::Input(line) => self.ex_command(&line), 
+            PromptResult::Canceled => Ok(None), 
+        }
+    }
+
+
+    //:w [file], :saveas file, :q, :q!, :wq [file], :x [file] and :e file
+    fn ex_command(&mut self, line: &str) -> Result<Option<EditStep>> {
+        let line = line.trim(); 
+        let (cmd, arg) = match line.find(char::is_whitespace) {
+            Some(i) => (&line[..i], line[i..].trim()), 
+            None => (line, ""), 
+        }; 
+
+        match cmd {
+            //:w file writes a copy, an unnamed buffer takes the name like with :saveas
+            "w" | "wq" | "x" if !arg.is_empty() && self.buf().has_file() => {
+                match self.buf().save_copy(Path::new(arg)) {
+                    Ok(msg) => self.screen.set_info_message(msg), 
+                    Err(msg) => {
+                        self.screen.set_error_message(msg); 
+                        return Ok(None); 
+                    }
+                }
+                if cmd != "w" {
+                    return Ok(Some(self.handle_quit())); 
+                }
+            }
+            "saveas" | "sav" if arg.is_empty() => self.screen.set_error_message("No file name"), 
+            "w" | "wq" | "x" | "saveas" | "sav" => {
+                if !arg.is_empty() {
+                    self.buf_mut().set_file(arg); 
+                    let idx = self.buf_idx(); 
+                    self.configure_buf(idx); 
+                    let row_off = self.wins.focused().row_off; 
+                    self.wins.set_dirty_start(idx, row_off); 
+                }
+                self.save()?; 
+                //the buffer is still modified when saving failed
+                if matches!(cmd, "wq" | "x") && !self.buf().modified() {
+                    return Ok(Some(self.handle_quit())); 
+                }
+            }
+            "q" if arg.is_empty() => return Ok(Some(self.handle_quit())), 
+            "q!" if arg.is_empty() => return Ok(Some(EditStep::Quit)), 
+            "e" if arg.is_empty() => self.screen.set_error_message("No file name"), 
+            "e" => {
+                if let Err(err) = self.open_file(arg) {
+                    self.screen.set_error_message(format!("Could not open {}: {}", arg, err)); 
+                }
+            }
+            "" => {}
+            _ => self.screen.set_error_message(format!("Not an editor command: {}", line)), 
+        }
+
+        Ok(None)
+    }
+
+
+    //pasted text is inserted as it is, it is never a key binding
+    fn paste(&mut self, text: &str) {
+        let prev_cursor = self.buf().cursor(); 
+        self.clear_selection(); 
+        self.buf_mut().insert_text(text); 
+        self.finish_edit(prev_cursor); 
+        self.qutting = false; 
+    }
+
+
+    //redraw what the last edit changed
+    fn finish_edit(&mut self, prev_cursor: (usize, usize)) {
         if let Some(line) = self.buf_mut().finish_edit() {
-            self.screen.set_dirty_start(line);
+            let idx = self.buf_idx(); 
+            self.wins.set_dirty_start(idx, line);
         }
 
         if self.buf().cursor() != prev_cursor {
             self.screen.cursor_moved = true; 
         }
+    }
 
-        self.qutting = false; 
-        Ok(EditStep::Continue(s))
+
+    //any key ends the selection made with the mouse
+    fn clear_selection(&mut self) {
+        if self.buf().selection().is_some() {
+            self.mark_selection_dirty(); 
+            self.buf_mut().set_mark(None); 
+        }
     }
 
 
 
-    pub fn set_lang(&mut self, lang: Language){
-        let buf = self.buf_mut(); 
-        if buf.lang() == lang {
-            return ;
+    fn handle_mouse(&mut self, mouse: Mouse) {
+        let prev_cursor = self.buf().cursor(); 
+        self.mark_selection_dirty(); 
+
+        match mouse.kind {
+            MouseKind::Press => {
+                self.dragging = false; 
+                //clicks on the message bar, a separator or a status bar do nothing
+                let (idx, y, x) = match self.wins.at(mouse.row, mouse.col) {
+                    Some((idx, y, x)) if y < self.wins.iter().nth(idx).map_or(0, Window::rows) => (idx, y, x), 
+                    _ => return, 
+                }; 
+
+                self.focus_window(idx); 
+                let (cx, cy) = self.wins.focused().text_pos(self.buf().rows(), y, x); 
+                self.buf_mut().set_cursor(cx, cy); 
+
+                let now = Instant::now(); 
+                let double = matches!(self.last_click, Some((t, row, col)) 
+                    if now.duration_since(t) < DOUBLE_CLICK && (row, col) == (mouse.row, mouse.col)); 
+
+                if double {
+                    self.buf_mut().select_word(); 
+                    self.last_click = None; 
+                } else {
+                    self.buf_mut().set_mark(Some((cx, cy))); 
+                    self.dragging = true; 
+                    self.last_click = Some((now, mouse.row, mouse.col)); 
+                }
+            }
+            MouseKind::Drag if self.dragging => {
+                //outside of the window the cursor stops at its nearest edge
+                let rect = self.wins.focused().rect; 
+                let rows = self.wins.focused().rows(); 
+                let y = cmp::min(mouse.row.saturating_sub(rect.top), rows.saturating_sub(1)); 
+                let x = cmp::min(mouse.col.saturating_sub(rect.left), rect.width.saturating_sub(1)); 
+
+                let (cx, cy) = self.wins.focused().text_pos(self.buf().rows(), y, x); 
+                self.buf_mut().set_cursor(cx, cy); 
+            }
+            MouseKind::Drag => {}
+            MouseKind::Release => self.dragging = false, 
+            MouseKind::WheelUp | MouseKind::WheelDown => {
+                let idx = match self.wins.at(mouse.row, mouse.col) {
+                    Some((idx, _, _)) => idx, 
+                    None => return, 
+                }; 
+                self.focus_window(idx); 
+                self.scroll_wheel(mouse.kind == MouseKind::WheelUp); 
+            }
+        }
+
+        self.mark_selection_dirty(); 
+        if self.buf().cursor() != prev_cursor {
+            self.screen.cursor_moved = true; 
+        }
+    }
+
+
+    //scroll the focused window by WHEEL_LINES, the cursor stays inside the window
+    fn scroll_wheel(&mut self, up: bool) {
+        let len = self.buf().rows().len(); 
+        let win = self.wins.focused_mut(); 
+        win.row_off = if up {
+            win.row_off.saturating_sub(WHEEL_LINES)
+        } else {
+            cmp::min(win.row_off + WHEEL_LINES, len.saturating_sub(1))
+        }; 
+        let row_off = win.row_off; 
+
+        let (cx, cy) = self.buf().cursor(); 
+        let win = self.wins.focused(); 
+        let last = win.text_pos(self.buf().rows(), win.rows().saturating_sub(1), 0).1; 
+        if cy < row_off {
+            self.buf_mut().restore_cursor((cx, row_off)); 
+        } else if cy > last {
+            self.buf_mut().restore_cursor((cx, last)); 
         }
 
-        buf.set_lang(lang); 
+        let idx = self.buf_idx(); 
+        self.wins.set_dirty_start(idx, row_off); 
     }
 
+
+    //C-d and C-u of vi, the window and the cursor move by half of its lines
+    fn scroll_half_page(&mut self, up: bool) {
+        let len = self.buf().rows().len(); 
+        let win = self.wins.focused_mut(); 
+        let half = cmp::max(win.rows() / 2, 1); 
+        win.row_off = if up {
+            win.row_off.saturating_sub(half)
+        } else {
+            cmp::min(win.row_off + half, len.saturating_sub(1))
+        }; 
+        let row_off = win.row_off; 
+
+        let (cx, cy) = self.buf().cursor(); 
+        let y = if up { cy.saturating_sub(half) } else { cmp::min(cy + half, len.saturating_sub(1)) }; 
+        self.buf_mut().restore_cursor((cx, y)); 
+
+        let idx = self.buf_idx(); 
+        self.wins.set_dirty_start(idx, row_off); 
+    }
+
+
+    //redraw the lines from the selection or the cursor down, called before and after they change
+    fn mark_selection_dirty(&mut self) {
+        let line = match self.buf().selection() {
+            Some(((_, sy), _)) => sy, 
+            None => self.buf().cursor().1, 
+        }; 
+        let idx = self.buf_idx(); 
+        self.wins.set_dirty_start(idx, line); 
+    }
+
+
+
     pub fn save(&mut self) -> Result<()> {
         let mut create = false; 
 
         if !self.buf().has_file() {
             //get our template here
-            let template = "Save as: {} (^G or ESC to cancel"; 
+            let template = "Save as: {} (^G or ESC to cancel, Tab to complete)"; 
 
-            if let PromptResult::Input(input) = self.prompt::<prompt::NoAction>(template, true)? {
+            if let PromptResult::Input(input) = self.prompt::<CompletePath>(template, Some(HistoryKind::Open), true)? {
                 let prev_lang = self.buf().lang(); 
                 self.buf_mut().set_file(input); //catch the input here
+                let idx = self.buf_idx(); 
+                self.configure_buf(idx); 
                 
                 if prev_lang != self.buf().lang() {
-                    self.screen.set_dirty_start(self.screen.row_off); 
+                    let idx = self.buf_idx(); 
+                    let row_off = self.wins.focused().row_off; 
+                    self.wins.set_dirty_start(idx, row_off); 
                 }
 
                 create = true;
@@ -499,43 +1799,48 @@ impl<I, W> Editor<I, W>
         output: W, 
         window_size: Option<(usize, usize)>, 
         paths: &[P], 
+        config: Config, 
     ) -> Result<Editor<I, W>> {
 
         if paths.is_empty() {
-            return Self::new(input, output, window_size); 
+            return Self::new(input, output, window_size, config); 
         }
 
         //a reference => a reference
 
         let screen = Screen::new(window_size, &mut input, output)?;
-        let bufs: Vec<_> = paths.iter().map(TextBuffer::open).collect::<Result<_>>()?; 
-        let status_bar=  Status::from_buffer(&bufs[0], (1, bufs.len())); 
+        let bufs: Vec<_> = paths
+            .iter()
+            .map(|p| TextBuffer::open(p, &config.settings_for_file(p.as_ref()).charset))
+            .collect::<Result<_>>()?; 
+        let wins = Windows::new(Window::new(&bufs[0], 0, (1, bufs.len()))); 
 
 
-        Ok(Editor {
+        let mut editor = Editor {
             input, 
             qutting: false, 
-            buf_idx: 0, 
             bufs,
+            wins,
             screen,
-            status_bar,
-        })
+            history: History::load(),
+            keymap: Keymap::new(),
+            config: Config::new(),
+            last_click: None,
+            dragging: false,
+            raw_mode: None,
+            events: Events::new()?,
+            vi: None,
+        }; 
+        editor.configure(config); 
+        Ok(editor)
 
     
     }
 
     
     fn buf_mut(&mut self) -> &mut TextBuffer {
-        &mut self.bufs[self.buf_idx]
-    }
-
-
-    pub fn lang(&self) -> Language {
-        self.buf().lang()
-    }
-
-    pub fn screen(&self) -> &'_ Screen<W> {
-        &self.screen
+        let idx = self.buf_idx(); 
+        &mut self.bufs[idx]
     }
 
 
@@ -545,7 +1850,18 @@ impl<I, W> Editor<I, W>
 
 #[cfg(test)]
 mod tests {
-    //writing test here
+    use super::*; 
+
+    #[test]
+    fn file_key_is_the_same_for_every_spelling() {
+        let key = file_key(Path::new("src/main.rs")); 
+        assert_eq!(file_key(Path::new("./src/main.rs")), key); 
+        assert_eq!(file_key(&env::current_dir().unwrap().join("src/main.rs")), key); 
+
+        let new = file_key(Path::new("src/not_created_yet.rs")); 
+        assert_eq!(file_key(Path::new("./src/not_created_yet.rs")), new); 
+        assert!(new.is_absolute()); 
+    }
 }
 
 //we want to share state here
@@ -7,6 +7,7 @@ use std::time::SystemTimeError;
 
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     IoError(io::Error), 
     SystemTimeError(SystemTimeError), 
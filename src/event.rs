@@ -0,0 +1,335 @@
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::error::Result;
+use crate::input::{InputSeq, KeySeq, Mouse};
+
+
+//everything the editor waits for
+#[derive(Debug)]
+pub enum Event {
+    Key(InputSeq),
+    Paste(String),
+    Mouse(Mouse),
+    Resize,
+    Timer(Timer),
+    FileChanged(PathBuf, SystemTime), //the file of a buffer was written by another program, at that time
+    Progress, //a background task of the open prompt, such as the walk of the file finder, found more
+    JobDone,  //that background task finished
+}
+
+
+impl From<InputSeq> for Event {
+    fn from(seq: InputSeq) -> Self {
+        match seq.key {
+            KeySeq::Paste(text) => Event::Paste(text),
+            KeySeq::Mouse(mouse) => Event::Mouse(mouse),
+            KeySeq::Resize => Event::Resize,
+            _ => Event::Key(seq),
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Timer {
+    MessageExpired, //the message bar can be cleared
+    CheckFiles,     //look for files changed on disk
+}
+
+
+//input which can be waited for together with the other events
+pub trait EventInput: Iterator<Item = Result<InputSeq>> {
+    //the next input, None when the timeout passed or wake became readable first
+    fn next_event(&mut self, wake: RawFd, timeout: Option<Duration>) -> Result<Option<InputSeq>>;
+
+    //width and height of the terminal from a cursor position report, None without a reply in time
+    fn query_window_size<W: Write>(&mut self, output: W, timeout: Duration) -> Result<Option<(usize, usize)>>;
+}
+
+
+impl<T: EventInput> EventInput for &mut T {
+    fn next_event(&mut self, wake: RawFd, timeout: Option<Duration>) -> Result<Option<InputSeq>> {
+        (**self).next_event(wake, timeout)
+    }
+
+    fn query_window_size<W: Write>(&mut self, output: W, timeout: Duration) -> Result<Option<(usize, usize)>> {
+        (**self).query_window_size(output, timeout)
+    }
+}
+
+
+const TICK_MILLIS: u64 = 10;
+const SLOTS: usize = 256;
+
+
+//hashed timer wheel: a timer due at tick t waits in slot t % SLOTS, so expiring only looks at the
+//slots of the ticks which passed
+pub struct TimerWheel {
+    start: Instant,
+    now: u64, //last tick which was expired
+    slots: Vec<Vec<(u64, Timer)>>,
+}
+
+
+impl TimerWheel {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            now: 0,
+            slots: vec![vec![]; SLOTS],
+        }
+    }
+
+    fn tick(&self, at: Instant) -> u64 {
+        at.saturating_duration_since(self.start).as_millis() as u64 / TICK_MILLIS
+    }
+
+    //a timer is scheduled at most once, scheduling it again moves it
+    pub fn schedule(&mut self, timer: Timer, at: Instant) {
+        self.cancel(timer);
+        //rounded up so that it never fires early
+        let tick = cmp::max(self.tick(at + Duration::from_millis(TICK_MILLIS - 1)), self.now + 1);
+        self.slots[tick as usize % SLOTS].push((tick, timer));
+    }
+
+    pub fn cancel(&mut self, timer: Timer) {
+        for slot in &mut self.slots {
+            slot.retain(|(_, t)| *t != timer);
+        }
+    }
+
+    //when the next timer is due
+    pub fn deadline(&self) -> Option<Instant> {
+        self.slots
+            .iter()
+            .flatten()
+            .map(|(tick, _)| *tick)
+            .min()
+            .map(|tick| self.start + Duration::from_millis(tick * TICK_MILLIS))
+    }
+
+    //remove the timers due at now, earliest first
+    pub fn expire(&mut self, now: Instant) -> Vec<Timer> {
+        let end = self.tick(now);
+        let mut due = vec![];
+
+        //after a long wait every slot may hold due timers, each slot is visited once
+        for tick in (self.now + 1..=end).take(SLOTS) {
+            let slot = &mut self.slots[tick as usize % SLOTS];
+            due.extend(slot.iter().filter(|(t, _)| *t <= end).copied());
+            slot.retain(|(t, _)| *t > end);
+        }
+
+        self.now = cmp::max(self.now, end);
+        due.sort_by_key(|(tick, _)| *tick);
+        due.into_iter().map(|(_, timer)| timer).collect()
+    }
+}
+
+
+//sends events to the editor from other threads and wakes it up
+#[derive(Clone)]
+pub struct Notifier {
+    sender: Sender<Event>,
+    wake: Arc<UnixStream>,
+}
+
+
+impl Notifier {
+    //false once the editor is gone
+    pub fn send(&self, event: Event) -> bool {
+        if self.sender.send(event).is_err() {
+            return false;
+        }
+        //a full pipe already wakes the editor
+        let _ = (&*self.wake).write(&[1]);
+        true
+    }
+}
+
+
+//the event loop: input, expired timers and the events of other threads
+pub struct Events {
+    timers: TimerWheel,
+    queue: VecDeque<Event>,
+    deferred: Vec<Event>,
+    receiver: Receiver<Event>,
+    wake: UnixStream,
+    notifier: Notifier,
+}
+
+
+impl Events {
+    pub fn new() -> io::Result<Self> {
+        let (wake, write) = UnixStream::pair()?;
+        wake.set_nonblocking(true)?;
+        write.set_nonblocking(true)?;
+        let (sender, receiver) = mpsc::channel();
+
+        Ok(Self {
+            timers: TimerWheel::new(),
+            queue: VecDeque::new(),
+            deferred: vec![],
+            receiver,
+            wake,
+            notifier: Notifier { sender, wake: Arc::new(write) },
+        })
+    }
+
+    pub fn notifier(&self) -> Notifier {
+        self.notifier.clone()
+    }
+
+    pub fn schedule(&mut self, timer: Timer, after: Duration) {
+        self.timers.schedule(timer, Instant::now() + after);
+    }
+
+    pub fn schedule_at(&mut self, timer: Timer, at: Instant) {
+        self.timers.schedule(timer, at);
+    }
+
+    pub fn cancel(&mut self, timer: Timer) {
+        self.timers.cancel(timer);
+    }
+
+    //run work on its own thread, it sends its events through the notifier
+    pub fn spawn<F>(&self, work: F)
+    where
+        F: FnOnce(&Notifier) + Send + 'static,
+    {
+        let notifier = self.notifier();
+        thread::spawn(move || work(&notifier));
+    }
+
+    //keep an event for after a prompt or a question, which only wait for their own events
+    pub fn defer(&mut self, event: Event) {
+        self.deferred.push(event);
+    }
+
+    //the deferred events come next, in the order they came in
+    pub fn resume(&mut self) {
+        for event in self.deferred.drain(..).rev() {
+            self.queue.push_front(event);
+        }
+    }
+
+    //wait for the next event, the timers fire while no input comes
+    pub fn next<I: EventInput>(&mut self, input: &mut I) -> Result<Event> {
+        loop {
+            if let Some(event) = self.queue.pop_front() {
+                return Ok(event);
+            }
+
+            let mut drain = [0u8; 64];
+            while matches!(self.wake.read(&mut drain), Ok(n) if n > 0) {}
+            self.queue.extend(self.receiver.try_iter());
+            self.queue.extend(self.timers.expire(Instant::now()).into_iter().map(Event::Timer));
+            if !self.queue.is_empty() {
+                continue;
+            }
+
+            let timeout = self.timers.deadline().map(|at| at.saturating_duration_since(Instant::now()));
+            if let Some(seq) = input.next_event(self.wake.as_raw_fd(), timeout)? {
+                return Ok(Event::from(seq));
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn timers_expire_in_order() {
+        let mut wheel = TimerWheel::new();
+        let start = wheel.start;
+        wheel.schedule(Timer::CheckFiles, start + ms(50));
+        wheel.schedule(Timer::MessageExpired, start + ms(20));
+
+        assert_eq!(wheel.deadline(), Some(start + ms(20)));
+        assert!(wheel.expire(start + ms(10)).is_empty());
+        assert_eq!(wheel.expire(start + ms(100)), vec![Timer::MessageExpired, Timer::CheckFiles]);
+        assert_eq!(wheel.deadline(), None);
+    }
+
+    #[test]
+    fn timers_never_fire_early() {
+        let mut wheel = TimerWheel::new();
+        let start = wheel.start;
+        wheel.schedule(Timer::MessageExpired, start + ms(25));
+
+        assert!(wheel.expire(start + ms(24)).is_empty());
+        assert_eq!(wheel.expire(start + ms(30)), vec![Timer::MessageExpired]);
+    }
+
+    #[test]
+    fn scheduling_again_moves_the_timer() {
+        let mut wheel = TimerWheel::new();
+        let start = wheel.start;
+        wheel.schedule(Timer::CheckFiles, start + ms(20));
+        wheel.schedule(Timer::CheckFiles, start + ms(200));
+
+        assert!(wheel.expire(start + ms(100)).is_empty());
+        assert_eq!(wheel.expire(start + ms(200)), vec![Timer::CheckFiles]);
+    }
+
+    #[test]
+    fn canceled_timers_do_not_fire() {
+        let mut wheel = TimerWheel::new();
+        let start = wheel.start;
+        wheel.schedule(Timer::CheckFiles, start + ms(20));
+        wheel.cancel(Timer::CheckFiles);
+
+        assert_eq!(wheel.deadline(), None);
+        assert!(wheel.expire(start + ms(100)).is_empty());
+    }
+
+    #[test]
+    fn timers_further_than_one_turn_of_the_wheel() {
+        let mut wheel = TimerWheel::new();
+        let start = wheel.start;
+        let later = TICK_MILLIS * SLOTS as u64 * 3 + 50;
+        wheel.schedule(Timer::CheckFiles, start + ms(later));
+
+        //the slot of the timer comes around before it is due
+        assert!(wheel.expire(start + ms(60)).is_empty());
+        assert!(wheel.expire(start + ms(later - 10)).is_empty());
+        assert_eq!(wheel.expire(start + ms(later)), vec![Timer::CheckFiles]);
+    }
+
+    #[test]
+    fn timers_due_after_a_long_wait() {
+        let mut wheel = TimerWheel::new();
+        let start = wheel.start;
+        wheel.schedule(Timer::MessageExpired, start + ms(30));
+        wheel.schedule(Timer::CheckFiles, start + ms(5000));
+
+        assert_eq!(wheel.expire(start + ms(60_000)), vec![Timer::MessageExpired, Timer::CheckFiles]);
+    }
+
+    #[test]
+    fn deferred_events_come_back_in_order() {
+        let mut events = Events::new().unwrap();
+        events.defer(Event::Timer(Timer::CheckFiles));
+        events.defer(Event::Resize);
+        events.resume();
+
+        assert!(matches!(events.queue.pop_front(), Some(Event::Timer(Timer::CheckFiles))));
+        assert!(matches!(events.queue.pop_front(), Some(Event::Resize)));
+    }
+}
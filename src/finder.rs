@@ -0,0 +1,314 @@
+use std::cmp;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::mem;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+
+use ignore::{WalkBuilder, WalkState};
+
+use crate::error::Result;
+use crate::event::{Event, Notifier};
+use crate::fuzzy;
+use crate::input::{InputSeq, KeySeq};
+use crate::prompt::{Action, Prompt, PromptResult};
+
+
+//how many of the best matches are kept, and cycled through with Tab
+const MAX_CANDIDATES: usize = 50;
+
+//paths a walker thread collects before it wakes the prompt up
+const BATCH_SIZE: usize = 256;
+
+
+//the paths found by one walker thread, sent together so that the prompt is not woken for each
+struct Batch {
+    paths: Vec<String>,
+    tx: Sender<Vec<String>>,
+    notifier: Notifier,
+}
+
+
+impl Batch {
+    //false once the finder was closed
+    fn push(&mut self, path: String) -> bool {
+        self.paths.push(path);
+        self.paths.len() < BATCH_SIZE || self.send()
+    }
+
+    fn send(&mut self) -> bool {
+        if self.paths.is_empty() {
+            return true;
+        }
+        self.tx.send(mem::take(&mut self.paths)).is_ok() && self.notifier.send(Event::Progress)
+    }
+}
+
+
+impl Drop for Batch {
+    fn drop(&mut self) {
+        self.send();
+    }
+}
+
+
+//paths under a directory, collected by a background walker honoring .gitignore and hidden files
+pub struct FileIndex {
+    paths: Vec<String>,
+    rx: Receiver<Vec<String>>,
+    walking: bool,
+}
+
+
+impl FileIndex {
+    //the walker wakes the prompt through notifier with Event::Progress when it found more paths
+    //and with Event::JobDone when it is done
+    pub fn spawn<P: AsRef<Path>>(root: P, notifier: Notifier) -> Self {
+        let walk_root = root.as_ref().to_path_buf();
+        let (tx, rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            WalkBuilder::new(&walk_root)
+                .hidden(true)
+                .git_ignore(true)
+                .require_git(false)
+                .build_parallel()
+                .run(|| {
+                    let mut batch = Batch { paths: vec![], tx: tx.clone(), notifier: notifier.clone() };
+                    let walk_root = walk_root.clone();
+                    Box::new(move |entry| {
+                        let entry = match entry {
+                            Ok(entry) => entry,
+                            Err(_) => return WalkState::Continue,
+                        };
+
+                        if !entry.file_type().map(|t| t.is_file()).unwrap_or(false) {
+                            return WalkState::Continue;
+                        }
+
+                        let path = entry.path().strip_prefix(&walk_root).unwrap_or(entry.path());
+                        if batch.push(path.to_string_lossy().into_owned()) {
+                            WalkState::Continue
+                        } else {
+                            WalkState::Quit //the finder was closed
+                        }
+                    })
+                });
+
+            //the receiver sees the end of the walk
+            drop(tx);
+            notifier.send(Event::JobDone);
+        });
+
+        Self {
+            paths: vec![],
+            rx,
+            walking: true,
+        }
+    }
+
+    //take in what the walker found so far, returns true when there was anything new
+    pub fn poll(&mut self) -> bool {
+        let before = self.paths.len();
+
+        while self.walking {
+            match self.rx.try_recv() {
+                Ok(paths) => self.paths.extend(paths),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => self.walking = false,
+            }
+        }
+
+        before != self.paths.len()
+    }
+
+    pub fn paths(&self) -> &[String] {
+        &self.paths
+    }
+
+    pub fn walking(&self) -> bool {
+        self.walking
+    }
+}
+
+
+//choose a file of the working directory tree by fuzzy matching its path.
+//The prompt result is the chosen path, relative to the working directory.
+pub struct FindFile {
+    index: FileIndex,
+    input: String,
+    matched: Vec<usize>, //indices of paths matching input, reused while the input only grows
+    scanned: usize,      //paths already tested against input
+    candidates: Vec<usize>,
+    selected: usize,
+}
+
+
+impl FindFile {
+    fn update<W: Write>(&mut self, prompt: &mut Prompt<'_, W>, input: &str) {
+        let paths = self.index.paths();
+        let pattern = fuzzy::Pattern::new(input);
+        let same_input = input == self.input;
+
+        //typing more characters can only drop matches, so narrow the previous ones down
+        if same_input {
+            //the paths scanned before still match
+        } else if input.starts_with(self.input.as_str()) {
+            self.matched.retain(|i| pattern.matches(&paths[*i]));
+        } else {
+            self.matched = (0..self.scanned).filter(|i| pattern.matches(&paths[*i])).collect();
+        }
+
+        let new = self.matched.len();
+        self.matched.extend((self.scanned..paths.len()).filter(|i| pattern.matches(&paths[*i])));
+        self.scanned = paths.len();
+        self.input = input.to_string();
+
+        self.candidates = if same_input {
+            //only new paths came in, the best of them and of the previous best are the best of all
+            let previous = mem::take(&mut self.candidates);
+            fuzzy::top(
+                input,
+                previous.into_iter().chain(self.matched[new..].iter().copied()).map(|i| (i, paths[i].as_str())),
+                MAX_CANDIDATES,
+            )
+        } else {
+            fuzzy::top(
+                input,
+                self.matched.iter().map(|i| (*i, paths[*i].as_str())),
+                MAX_CANDIDATES,
+            )
+        };
+        self.selected = 0;
+        self.update_hint(prompt);
+    }
+
+    fn update_hint<W: Write>(&self, prompt: &mut Prompt<'_, W>) {
+        let paths = self.index.paths();
+        let status = if self.index.walking() {
+            format!(" ({} files, scanning...)", paths.len())
+        } else {
+            format!(" ({}/{})", self.matched.len(), paths.len())
+        };
+
+        if self.candidates.is_empty() {
+            prompt.set_hint(format!("{} [No match]", status));
+            return;
+        }
+
+        let len = self.candidates.len();
+        let shown = cmp::min(len, 10);
+        let labels: Vec<&str> = (0..shown)
+            .map(|i| paths[self.candidates[(self.selected + i) % len]].as_str())
+            .collect();
+
+        prompt.set_hint(format!("{} {{{}}}", status, labels.join(" | ")));
+    }
+}
+
+
+impl Action for FindFile {
+    fn new<W: Write>(prompt: &mut Prompt<'_, W>) -> Self {
+        let root = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+        let mut action = Self {
+            index: FileIndex::spawn(root, prompt.notifier()),
+            input: String::new(),
+            matched: vec![],
+            scanned: 0,
+            candidates: vec![],
+            selected: 0,
+        };
+
+        action.index.poll();
+        action.update(prompt, "");
+        action
+    }
+
+    fn on_seq<W: Write>(
+        &mut self,
+        prompt: &mut Prompt<'_, W>,
+        input: &str,
+        seq: InputSeq,
+    ) -> Result<bool> {
+        use KeySeq::*;
+
+        let len = self.candidates.len();
+        match seq {
+            InputSeq { key: TabKey, .. } | InputSeq { key: Key(b'n'), ctrl: true, .. } if len > 0 => {
+                self.selected = (self.selected + 1) % len;
+                self.update_hint(prompt);
+            }
+            InputSeq { key: Key(b'p'), ctrl: true, .. } if len > 0 => {
+                self.selected = (self.selected + len - 1) % len;
+                self.update_hint(prompt);
+            }
+            _ if input != self.input => self.update(prompt, input),
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    fn on_progress<W: Write>(&mut self, prompt: &mut Prompt<'_, W>, input: &str) -> Result<bool> {
+        let walking = self.index.walking();
+        if !self.index.poll() && walking == self.index.walking() {
+            return Ok(false);
+        }
+
+        self.update(prompt, input);
+        Ok(true)
+    }
+
+    fn on_end<W: Write>(
+        self,
+        prompt: &mut Prompt<'_, W>,
+        result: PromptResult,
+    ) -> Result<PromptResult> {
+        prompt.set_hint("");
+
+        match result {
+            PromptResult::Input(_) => match self.candidates.get(self.selected) {
+                Some(&idx) => Ok(PromptResult::Input(self.index.paths()[idx].clone())),
+                None => {
+                    prompt.set_error_message("No file matches");
+                    Ok(PromptResult::Canceled)
+                }
+            },
+            PromptResult::Canceled => Ok(result),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::Duration;
+
+    use crate::event::Events;
+
+    #[test]
+    fn index_skips_ignored_and_hidden_files() {
+        let top = std::env::temp_dir().join(format!("detty-finder-{}", std::process::id()));
+        fs::create_dir_all(top.join("src")).unwrap();
+        fs::create_dir_all(top.join(".cache")).unwrap();
+        fs::write(top.join(".gitignore"), "*.log\n").unwrap();
+        for file in ["main.rs", "src/lib.rs", "build.log", ".hidden", ".cache/a.rs"] {
+            fs::write(top.join(file), "").unwrap();
+        }
+
+        let events = Events::new().unwrap();
+        let mut index = FileIndex::spawn(&top, events.notifier());
+        while index.walking() {
+            index.poll();
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let mut paths = index.paths().to_vec();
+        paths.sort();
+        assert_eq!(paths, vec!["main.rs", "src/lib.rs"]);
+
+        fs::remove_dir_all(&top).unwrap();
+    }
+}
@@ -0,0 +1,251 @@
+//fuzzy matching of a typed pattern against names, paths and commands
+
+//a run must outscore the same letters spread over word starts: with paths, "edit" should rank
+//src/editor.rs above src/e_d_i_t.rs. Each letter of the run gets BONUS_CONSECUTIVE, each spread
+//one gets BONUS_WORD_START - PENALTY_GAP, so the former has to be the larger
+const SCORE_MATCH: i64 = 16;
+const BONUS_CONSECUTIVE: i64 = 12;
+const BONUS_WORD_START: i64 = 10;
+const BONUS_BASENAME: i64 = 12;
+const PENALTY_GAP: i64 = 3;
+
+
+fn is_separator(c: char) -> bool {
+    matches!(c, '/' | '\\' | '_' | '-' | '.' | ' ' | ':')
+}
+
+
+//a pattern prepared once and matched against many texts
+pub struct Pattern {
+    chars: Vec<char>,
+    case_sensitive: bool,
+}
+
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Self {
+        //smart case: any upper case letter in the pattern makes the match case sensitive
+        let case_sensitive = pattern.chars().any(char::is_uppercase);
+        let chars = if case_sensitive {
+            pattern.chars().collect()
+        } else {
+            pattern.chars().flat_map(char::to_lowercase).collect()
+        };
+
+        Self { chars, case_sensitive }
+    }
+
+    fn eq(&self, p: char, t: char) -> bool {
+        if self.case_sensitive || p == t {
+            p == t
+        } else if t.is_ascii() {
+            p == t.to_ascii_lowercase()
+        } else {
+            t.to_lowercase().eq(std::iter::once(p))
+        }
+    }
+
+    //quick test whether the pattern is a subsequence of text, without scoring it
+    pub fn matches(&self, text: &str) -> bool {
+        let mut chars = text.chars();
+        self.chars.iter().all(|p| chars.any(|t| self.eq(*p, t)))
+    }
+
+    //score how well the pattern matches text as a subsequence, None when it does not match at all.
+    //Matches at word starts, in the file name part of a path and in runs score higher.
+    pub fn score(&self, text: &str) -> Option<i64> {
+        if self.chars.is_empty() {
+            return Some(0);
+        }
+
+        //paths are mostly ASCII, which can be indexed without collecting the chars
+        if text.is_ascii() {
+            let bytes = text.as_bytes();
+            self.score_chars(bytes.len(), |i| bytes[i] as char)
+        } else {
+            let chars: Vec<char> = text.chars().collect();
+            self.score_chars(chars.len(), |i| chars[i])
+        }
+    }
+
+    fn score_chars<F: Fn(usize) -> char>(&self, len: usize, at: F) -> Option<i64> {
+        let pat = &self.chars;
+
+        //find where the first complete match ends scanning forward
+        let mut pi = 0;
+        let mut end = 0;
+        for i in 0..len {
+            if self.eq(pat[pi], at(i)) {
+                pi += 1;
+                if pi == pat.len() {
+                    end = i + 1;
+                    break;
+                }
+            }
+        }
+
+        if pi < pat.len() {
+            return None;
+        }
+
+        //then walk backward from there to find the tightest start
+        let mut pi = pat.len();
+        let mut start = end;
+        while pi > 0 {
+            start -= 1;
+            if self.eq(pat[pi - 1], at(start)) {
+                pi -= 1;
+            }
+        }
+
+        let basename = (0..len).rev().find(|i| at(*i) == '/').map(|i| i + 1).unwrap_or(0);
+        let mut score = 0;
+        let mut pi = 0;
+        let mut prev_match: Option<usize> = None;
+
+        for i in start..end {
+            let c = at(i);
+            if !self.eq(pat[pi], c) {
+                continue;
+            }
+
+            score += SCORE_MATCH;
+
+            if i == basename {
+                score += BONUS_BASENAME;
+            } else if i > 0 {
+                let prev = at(i - 1);
+                if is_separator(prev) || prev.is_lowercase() && c.is_uppercase() {
+                    score += BONUS_WORD_START;
+                }
+            }
+
+            if i >= basename {
+                score += 1;
+            }
+
+            match prev_match {
+                Some(p) if p + 1 == i => score += BONUS_CONSECUTIVE,
+                Some(p) => score -= PENALTY_GAP * (i - p - 1).min(8) as i64,
+                None => {}
+            }
+
+            prev_match = Some(i);
+            pi += 1;
+            if pi == pat.len() {
+                break;
+            }
+        }
+
+        //prefer shorter texts when everything else is equal
+        Some(score * 64 - len.min(63) as i64)
+    }
+}
+
+
+fn scored<'a, T, I>(pattern: &str, items: I) -> Vec<(i64, usize, T)>
+where
+    I: Iterator<Item = (T, &'a str)>,
+{
+    let pattern = Pattern::new(pattern);
+
+    items
+        .enumerate()
+        .filter_map(|(n, (item, text))| pattern.score(text).map(|s| (s, n, item)))
+        .collect()
+}
+
+
+fn by_score<T>(a: &(i64, usize, T), b: &(i64, usize, T)) -> std::cmp::Ordering {
+    b.0.cmp(&a.0).then(a.1.cmp(&b.1))
+}
+
+
+//items matching pattern, best first. Items with the same score keep their order.
+pub fn rank<'a, T, I>(pattern: &str, items: I) -> Vec<T>
+where
+    I: Iterator<Item = (T, &'a str)>,
+{
+    let mut scored = scored(pattern, items);
+    scored.sort_by(by_score);
+    scored.into_iter().map(|(_, _, item)| item).collect()
+}
+
+
+//the limit best items matching pattern, best first. Cheaper than rank on long lists.
+pub fn top<'a, T, I>(pattern: &str, items: I, limit: usize) -> Vec<T>
+where
+    I: Iterator<Item = (T, &'a str)>,
+{
+    let mut scored = scored(pattern, items);
+    if scored.len() > limit {
+        scored.select_nth_unstable_by(limit, by_score);
+        scored.truncate(limit);
+    }
+
+    scored.sort_by(by_score);
+    scored.into_iter().map(|(_, _, item)| item).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(pattern: &str, text: &str) -> Option<i64> {
+        Pattern::new(pattern).score(text)
+    }
+
+    #[test]
+    fn subsequences_match() {
+        assert!(score("mrs", "src/main.rs").is_some());
+        assert_eq!(score("", "anything"), Some(0));
+        assert_eq!(score("rsm", "src/main.rs"), None);
+        assert_eq!(score("x", ""), None);
+    }
+
+    #[test]
+    fn smart_case() {
+        assert!(score("readme", "README.md").is_some());
+        assert!(score("README", "README.md").is_some());
+        assert_eq!(score("README", "readme.md"), None);
+        assert!(score("ÉTÉ", "été.txt").is_none());
+        assert!(score("été", "ÉTÉ.txt").is_some());
+    }
+
+    #[test]
+    fn file_names_beat_directories() {
+        assert!(score("main", "src/main.rs") > score("main", "main/src/lib.rs"));
+    }
+
+    #[test]
+    fn word_starts_beat_letters_inside_words() {
+        assert!(score("fb", "foo_bar") > score("fb", "fooxbar"));
+        assert!(score("fb", "fooBar") > score("fb", "fooxbar"));
+    }
+
+    #[test]
+    fn shorter_texts_win_ties() {
+        assert!(score("abc", "abc") > score("abc", "abcd"));
+    }
+
+    #[test]
+    fn rank_keeps_the_order_of_ties() {
+        let items = ["b.rs", "a.rs", "zz", "c.rs"];
+        let ranked = rank("rs", items.iter().enumerate().map(|(i, s)| (i, *s)));
+        assert_eq!(ranked, vec![0, 1, 3]);
+    }
+
+    #[test]
+    fn top_keeps_the_best() {
+        let items = ["xaxbxc", "abc", "axbc", "zzz"];
+        let best = top("abc", items.iter().enumerate().map(|(i, s)| (i, *s)), 2);
+        assert_eq!(best, vec![1, 2]);
+    }
+
+    #[test]
+    fn runs_beat_letters_spread_over_word_starts() {
+        assert!(score("edit", "src/editor.rs") > score("edit", "src/e_d_i_t.rs"));
+        assert!(score("lib", "src/lib.rs") > score("lib", "src/line_items_builder.rs"));
+    }
+}
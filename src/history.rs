@@ -0,0 +1,188 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+
+//entries kept for each kind of prompt
+const MAX_ENTRIES: usize = 200;
+//lines the history file may grow to before it is rewritten with only the entries kept
+const MAX_LINES: usize = MAX_ENTRIES * 5 * 2;
+
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum HistoryKind {
+    Search,
+    Open,
+    Replace,
+    Command,
+    Ex,
+}
+
+
+impl HistoryKind {
+    const ALL: [HistoryKind; 5] = [HistoryKind::Search, HistoryKind::Open, HistoryKind::Replace, HistoryKind::Command, HistoryKind::Ex];
+
+    fn name(self) -> &'static str {
+        use HistoryKind::*;
+
+        match self {
+            Search => "search",
+            Open => "open",
+            Replace => "replace",
+            Command => "command",
+            Ex => "ex",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<HistoryKind> {
+        HistoryKind::ALL.iter().copied().find(|k| k.name() == name)
+    }
+
+    fn idx(self) -> usize {
+        self as usize
+    }
+}
+
+
+//inputs of past prompts, oldest first, saved to disk between sessions
+pub struct History {
+    entries: [Vec<String>; 5],
+    path: Option<PathBuf>,
+}
+
+
+//$XDG_DATA_HOME/detty/history, falling back to ~/.local/share/detty/history
+fn default_path() -> Option<PathBuf> {
+    let data_dir = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".local").join("share")))?;
+
+    Some(data_dir.join("detty").join("history"))
+}
+
+
+impl History {
+    pub fn empty() -> Self {
+        Self {
+            entries: Default::default(),
+            path: None,
+        }
+    }
+
+    //read the history of the previous sessions, a missing or unreadable file means no history
+    pub fn load() -> Self {
+        Self::load_from(default_path())
+    }
+
+    fn load_from(path: Option<PathBuf>) -> Self {
+        let mut history = Self::empty();
+        history.path = path;
+
+        let mut lines = 0;
+        if let Some(file) = history.path.as_ref().and_then(|p| File::open(p).ok()) {
+            for line in io::BufReader::new(file).lines().map_while(|l| l.ok()) {
+                lines += 1;
+                let mut it = line.splitn(2, '\t');
+                if let (Some(kind), Some(entry)) = (it.next().and_then(HistoryKind::from_name), it.next()) {
+                    history.add(kind, entry);
+                }
+            }
+        }
+
+        //sessions only append to the file, drop the duplicates and old entries now and then
+        if lines > MAX_LINES {
+            let _ = history.save();
+        }
+
+        history
+    }
+
+    pub fn entries(&self, kind: HistoryKind) -> &[String] {
+        &self.entries[kind.idx()]
+    }
+
+    fn add(&mut self, kind: HistoryKind, entry: &str) {
+        let entries = &mut self.entries[kind.idx()];
+        entries.retain(|e| e != entry);
+        entries.push(entry.to_string());
+
+        if entries.len() > MAX_ENTRIES {
+            entries.remove(0);
+        }
+    }
+
+    //remember entry as the newest of its kind and append it to the history file. the file is
+    //not rewritten, so other sessions running meanwhile keep their entries
+    pub fn push(&mut self, kind: HistoryKind, entry: &str) -> io::Result<()> {
+        if entry.is_empty() {
+            return Ok(());
+        }
+
+        self.add(kind, entry);
+
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut f = OpenOptions::new().create(true).append(true).open(path)?;
+        f.write_all(format!("{}\t{}\n", kind.name(), entry).as_bytes())
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut f = io::BufWriter::new(File::create(path)?);
+        for kind in HistoryKind::ALL.iter() {
+            for entry in self.entries(*kind) {
+                writeln!(f, "{}\t{}", kind.name(), entry)?;
+            }
+        }
+
+        f.flush()
+    }
+
+    //index of the newest entry older than before containing query
+    pub fn search(&self, kind: HistoryKind, query: &str, before: usize) -> Option<usize> {
+        let entries = self.entries(kind);
+        entries[..before.min(entries.len())].iter().rposition(|e| e.contains(query))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sessions_keep_each_others_entries() {
+        let path = env::temp_dir().join(format!("detty-history-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut first = History::load_from(Some(path.clone()));
+        let mut second = History::load_from(Some(path.clone()));
+        first.push(HistoryKind::Open, "a.rs").unwrap();
+        second.push(HistoryKind::Open, "b.rs").unwrap();
+        first.push(HistoryKind::Search, "needle").unwrap();
+        second.push(HistoryKind::Open, "a.rs").unwrap();
+
+        let history = History::load_from(Some(path.clone()));
+        assert_eq!(history.entries(HistoryKind::Open), ["b.rs", "a.rs"]);
+        assert_eq!(history.entries(HistoryKind::Search), ["needle"]);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
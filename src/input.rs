@@ -1,15 +1,21 @@
 
 use std::io; 
 use std::fmt; 
-use std::io::Read;
+use std::io::{Read, Write};
+use std::collections::VecDeque;
 use std::ops::Deref;
 use std::ops::DerefMut;
-use std::os::unix::io::AsRawFd; 
+use std::os::unix::io::{AsRawFd, RawFd}; 
+use std::os::unix::net::UnixStream; 
 use std::str; 
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
 
 
 use crate::error::{ Error, Result}; 
+use crate::color::Rgb;
+use crate::event::EventInput;
 
 
 //deriving error from the debug trait 
@@ -17,7 +23,26 @@ use crate::error::{ Error, Result};
 
 
 pub struct InputSequence {
-    stdin: StdinMode
+    stdin: StdinMode, 
+    pending: VecDeque<InputSeq>, //keys read while waiting for a reply of the terminal
+    bytes: VecDeque<u8>, //read from stdin but not decoded yet
+    resize: UnixStream, //readable after a SIGWINCH
+    escape_timeout: Duration, //how long to wait for the rest of a sequence after ESC
+    cursor_query: bool, //a cursor position report is expected, it looks like a modified F3
+}
+
+
+//a bare Esc when nothing follows it in this time, terminals send a whole sequence at once
+const ESCAPE_TIMEOUT: Duration = Duration::from_millis(25);
+
+
+
+//what InputSequence::wait woke up for
+enum Ready {
+    Input, 
+    Resize, 
+    Wake, 
+    Timeout, 
 }
 
 
@@ -25,7 +50,30 @@ pub struct InputSequence {
 pub struct StdinMode {
    // visual: bool, 
     stdin: io::Stdin, 
-     origin: termios::Termios
+     origin: termios::Termios, 
+    raw: termios::Termios, 
+}
+
+
+//switches the terminal between its original mode and the raw mode of the editor, to suspend it
+#[derive(Clone, Copy)]
+pub struct RawMode {
+    fd: i32, 
+    origin: termios::Termios, 
+    raw: termios::Termios, 
+}
+
+
+impl RawMode {
+    pub fn leave(&self) -> Result<()> {
+        termios::tcsetattr(self.fd, termios::TCSAFLUSH, &self.origin)?; 
+        Ok(())
+    }
+
+    pub fn enter(&self) -> Result<()> {
+        termios::tcsetattr(self.fd, termios::TCSAFLUSH, &self.raw)?; 
+        Ok(())
+    }
 }
 
 
@@ -62,18 +110,56 @@ impl StdinMode  {
         Ok( StdinMode {
             stdin, 
            origin,
+            raw: termios,
             //visual: true,
         })
     }
 
 
-    pub fn input_keys(self) -> InputSequence {
-        InputSequence {  stdin: self }
+    pub fn raw_mode(&self) -> RawMode {
+        RawMode { fd: self.stdin.as_raw_fd(), origin: self.origin, raw: self.raw }
+    }
+
+
+    //the keys of stdin, with a KeySeq::Resize whenever the terminal was resized
+    pub fn input_keys(self) -> Result<InputSequence> {
+        let (resize, write) = UnixStream::pair()?; 
+        resize.set_nonblocking(true)?; 
+        write.set_nonblocking(true)?; 
+        signal_hook::low_level::pipe::register(signal_hook::consts::SIGWINCH, write)?; 
+
+        Ok(InputSequence {
+            stdin: self, 
+            pending: VecDeque::new(), 
+            bytes: VecDeque::new(), 
+            resize, 
+            escape_timeout: ESCAPE_TIMEOUT, 
+            cursor_query: false, 
+        })
     }
 }
 
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum MouseKind {
+    Press, //of the left button
+    Drag, 
+    Release, 
+    WheelUp, 
+    WheelDown, 
+}
+
+
+//a mouse event at a cell of the screen, counted from 0
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub struct Mouse {
+    pub kind: MouseKind, 
+    pub row: usize, 
+    pub col: usize, 
+}
+
+
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
 pub enum KeySeq {
     LeftKey, 
     NotIdentified,
@@ -87,7 +173,17 @@ pub enum KeySeq {
     HomeKey, 
     EndKey,
     DeleteKey, 
-    Cursor(usize, usize) // a tuple for this
+    InsertKey, 
+    TabKey, 
+    EnterKey, 
+    FunctionKey(u8), //F1 to F12
+    Paste(String), //text pasted while bracketed paste mode is on
+    Mouse(Mouse), 
+    Resize, //the terminal got a SIGWINCH
+    Cursor(usize, usize), // a tuple for this
+    Background(Rgb), //reply of the terminal to the OSC 11 query of its background color
+    KeyboardFlags(u8), //reply to the query of the kitty keyboard protocol flags
+    DeviceAttributes, //reply to the primary device attributes query, which every terminal sends
 }
 
 
@@ -101,24 +197,37 @@ impl fmt::Display for KeySeq {
             UpKey => write!(f, "UP"), 
             DownKey => write!(f, "DOWN"), 
             DeleteKey => write!(f, "DELETE"), 
+            InsertKey => write!(f, "INSERT"), 
+            TabKey => write!(f, "TAB"), 
+            EnterKey => write!(f, "ENTER"), 
+            FunctionKey(n) => write!(f, "F{}", n), 
+            Paste(_) => write!(f, "PASTE"), 
+            Mouse(_) => write!(f, "MOUSE"), 
+            Resize => write!(f, "RESIZE"), 
             PageUpKey => write!(f, "PAGEUP"), 
             PageDownKey => write!(f, "PAGEDOWN"), 
             EndKey => write!(f, "END"), 
-            Cursor(top, bottom) => write!(f, "CURSOR"),
+            Cursor(..) => write!(f, "CURSOR"),
+            Background(_) => write!(f, "BACKGROUND"),
+            KeyboardFlags(_) => write!(f, "KEYBOARDFLAGS"),
+            DeviceAttributes => write!(f, "DEVICEATTRIBUTES"),
             NotIdentified => write!(f, "NOTIDENTIFIED"),
             Utf8Key(x) => write!(f, "{}", x),
             HomeKey => write!(f, "HOME"),
             Key(b' ') => write!(f, "SPACE"),
+            Key(0x7f) => write!(f, "BACKSPACE"),
+            Key(0x1b) => write!(f, "ESC"),
+            Key(b) if b.is_ascii_control() => write!(f, "\\x{:x}", b),
             Key(b) => write!(f, "{}", *b as char), 
-            Key(b) if b.is_ascii_control() => write!(f, "\\x{:x}", b)
-            // /write!(f, "\\x{:x}", b),
         }
     }
 }
 
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
 pub struct InputSeq {
     pub alt: bool, 
     pub ctrl: bool, 
+    pub shift: bool, //only for keys which do not change with shift, like arrows
     pub key: KeySeq, 
 }
 
@@ -130,6 +239,7 @@ impl InputSeq {
         Self{
             ctrl: false, 
             alt: false,
+            shift: false,
             key
         }
     }
@@ -139,6 +249,7 @@ impl InputSeq {
         Self {
             ctrl: true, 
             alt: false, 
+            shift: false,
             key
         }
     }
@@ -149,6 +260,7 @@ impl InputSeq {
             key, 
             ctrl: false, 
             alt: true,
+            shift: false,
         }
     }
 
@@ -166,39 +278,128 @@ impl fmt::Display for InputSeq {
             write!(f, "M-")?;
         }
 
+        if self.shift {
+            write!(f, "S-")?;
+        }
+
         write!(f, "{}", self.key)
     }
 }
 
 
+//parse a key written the way Display prints it, such as "C-s", "M-x", "C-M-f", "S-UP", "F5" or "a".
+//C-i and C-m can only be told apart from TAB and ENTER with the kitty keyboard protocol
+impl FromStr for InputSeq {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        use KeySeq::*; 
+
+        let mut seq = InputSeq::new(NotIdentified); 
+        let mut rest = s; 
+        loop {
+            if let Some(r) = rest.strip_prefix("C-").filter(|r| !r.is_empty()) {
+                seq.ctrl = true; 
+                rest = r; 
+            } else if let Some(r) = rest.strip_prefix("M-").filter(|r| !r.is_empty()) {
+                seq.alt = true; 
+                rest = r; 
+            } else if let Some(r) = rest.strip_prefix("S-").filter(|r| !r.is_empty()) {
+                seq.shift = true; 
+                rest = r; 
+            } else {
+                break; 
+            }
+        }
+
+        seq.key = match rest {
+            "LEFT" => LeftKey, 
+            "RIGHT" => RightKey, 
+            "UP" => UpKey, 
+            "DOWN" => DownKey, 
+            "DELETE" => DeleteKey, 
+            "INSERT" => InsertKey, 
+            "PAGEUP" => PageUpKey, 
+            "PAGEDOWN" => PageDownKey, 
+            "HOME" => HomeKey, 
+            "END" => EndKey, 
+            "SPACE" => Key(b' '), 
+            "BACKSPACE" => Key(0x7f), 
+            "ESC" => Key(0x1b), 
+            "ENTER" => EnterKey, 
+            "TAB" => TabKey, 
+            _ => {
+                let function = rest.strip_prefix('F').and_then(|n| n.parse::<u8>().ok()).filter(|n| (1..=12).contains(n)); 
+                let mut chars = rest.chars(); 
+                match (function, chars.next(), chars.next()) {
+                    (Some(n), ..) => FunctionKey(n), 
+                    //control keys arrive as lower case letters with the ctrl flag
+                    (None, Some(c), None) if c.is_ascii() && seq.ctrl => Key(c.to_ascii_lowercase() as u8), 
+                    (None, Some(c), None) if c.is_ascii() && !c.is_ascii_control() => Key(c as u8), 
+                    (None, Some(c), None) if !c.is_ascii() => Utf8Key(c), 
+                    _ => return Err(format!("Unknown key {:?}", s)), 
+                }
+            }
+        }; 
+
+        Ok(seq)
+    }
+}
+
+
 impl InputSequence {
+    //stdin is read through its descriptor, the buffer of io::Stdin would hide bytes from poll
     fn read_byte(&mut self) -> Result<Option<u8>> {
-        // //let tra: Vec<u8> = Vec::new(); 
-        let mut one_byte:[u8; 1] = [0]; //i dont know the meaning of this yet
+        if self.bytes.is_empty() {
+            let mut chunk = [0u8; 1024]; 
+            let n = loop {
+                let n = unsafe { libc::read(self.stdin.as_raw_fd(), chunk.as_mut_ptr() as *mut libc::c_void, chunk.len()) }; 
+                if n >= 0 {
+                    break n as usize; 
+                }
+
+                let err = io::Error::last_os_error(); 
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err.into()); 
+                }
+            }; 
+            self.bytes.extend(&chunk[..n]); 
+        }
 
-        // Ok(Some(one_byte[0]))
-        // // Ok(if self.stdin.read(&mut one_byte)? == 0 {
-        // //     None
-        // // }else {
-        // //     Some(one_byte[0])
-        // // })
+        Ok(self.bytes.pop_front())
+    }
 
 
-        Ok(if self.stdin.read(&mut one_byte)? == 0 {
-            None
+    //the next byte of an escape sequence, None when it does not come in time
+    fn read_sequence_byte(&mut self) -> Result<Option<u8>> {
+        if self.wait_input(self.escape_timeout)? {
+            self.read_byte()
         } else {
-            Some(one_byte[0])
-        })
+            Ok(None)
+        }
+    }
+
+
+    pub fn set_escape_timeout(&mut self, timeout: Duration) {
+        self.escape_timeout = timeout; 
     }
 
 
 
     fn decode_escape_sequence(&mut self)-> Result<InputSeq>{
         use KeySeq::*;   
-        match self.read_byte()? {
+        //a lone Esc and Alt with a key both start with ESC, only the timing tells them apart
+        match self.read_sequence_byte()? {
             // Some(_) => todo!(),
             // None => todo!(),
             Some(b'[') => { /* fall through */ }
+            Some(b']') => return self.decode_osc(), 
+            Some(b'O') => {
+                return Ok(match self.read_sequence_byte()? {
+                    Some(b) => decode_ss3(b), 
+                    None => InputSeq::alt(Key(b'O')), 
+                }); 
+            }
             Some(streams) => {
                 let mut seq = self.decode(streams)?; 
                 seq.alt = true; 
@@ -206,56 +407,185 @@ impl InputSequence {
             }
 
 
-            Some(b) if b.is_ascii_control() => return Ok(InputSeq::new(Key(0x1b))),
             None => return Ok(InputSeq::new(Key(0x1b)))
         }; 
 
 
+        //parameter and intermediate bytes, then one final byte in 0x40..=0x7e
         let mut buf = vec![]; 
         let cmd = loop {
-            if let Some(stream) = self.read_byte()? {
-                match stream {
-                    b'A' | b'B' | b'C' | b'D' | b'F' | b'H' | b'K' | b'J' | b'R' | b'c' | b'f'
-                    | b'g' | b'h' | b'l' | b'm' | b'n' | b'q' | b't' | b'y' | b'~' => break stream,
-                    _ => buf.push(stream)
-                }
-            
-            }else {
-                return Ok(InputSeq::new(NotIdentified));
+            match self.read_sequence_byte()? {
+                //linux console function keys, ESC [ [ A
+                Some(b'[') if buf.is_empty() => buf.push(b'['), 
+                Some(stream) if buf.first() == Some(&b'[') => break stream, 
+                Some(stream @ 0x40..=0x7e) => break stream, 
+                Some(stream) => buf.push(stream), 
+                //Alt-[ alone
+                None if buf.is_empty() => return Ok(InputSeq::alt(Key(b'['))), 
+                None => return Ok(InputSeq::new(NotIdentified)), 
             }
         };
 
+        if cmd == b'~' && buf == b"200" {
+            return self.decode_paste(); 
+        }
 
-        let mut args = buf.split(|b|*b == b';'); 
-        match cmd {
-            b'H' | b'F' => {
-                let key = match cmd {
-                    b'H' => HomeKey, 
-                    b'F' => EndKey,
-                    _ => unreachable!(),
-                }; 
+        Ok(decode_csi(&buf, cmd, self.cursor_query))
+    }
 
-                let ctrl = args.next() == Some(b"1") && args.next() == Some(b"5");
-                let alt = false; 
-                Ok(InputSeq { key, ctrl, alt })
+
+    //the text between ESC [ 200 ~ and ESC [ 201 ~, read as it is without decoding any key
+    fn decode_paste(&mut self) -> Result<InputSeq> {
+        const END: &[u8] = b"\x1b[201~"; 
+
+        let mut buf = vec![]; 
+        while !buf.ends_with(END) {
+            match self.read_byte()? {
+                Some(b) => buf.push(b), 
+                None => break, 
             }
+        }
 
-            b'~' => {
-                match args.next() {
-                    Some(b"5") => Ok(InputSeq::new(PageUpKey)), 
-                    Some(b"6") => Ok(InputSeq::new(PageDownKey)), 
-                    Some(b"1") | Some(b"7") => Ok(InputSeq::new(HomeKey)), 
-                    Some(b"4") | Some(b"8") => Ok(InputSeq::new(EndKey)), 
-                    Some(b"3") => Ok(InputSeq::new(DeleteKey)), 
-                    _ => Ok(InputSeq::new(NotIdentified))
+        if buf.ends_with(END) {
+            buf.truncate(buf.len() - END.len()); 
+        }
+        Ok(InputSeq::new(KeySeq::Paste(String::from_utf8_lossy(&buf).into_owned())))
+    }
+
+
+    //operating system command, ESC ] <text> ended by BEL or ESC \. only the reply to the background
+    //color query is understood
+    fn decode_osc(&mut self) -> Result<InputSeq> {
+        let mut buf = vec![]; 
+        loop {
+            match self.read_sequence_byte()? {
+                //Alt-] alone
+                None if buf.is_empty() => return Ok(InputSeq::alt(KeySeq::Key(b']'))), 
+                Some(0x07) | None => break, 
+                Some(0x1b) => {
+                    self.read_sequence_byte()?; 
+                    break; 
                 }
+                Some(b) if buf.len() < 256 => buf.push(b), 
+                Some(_) => {}
+            }
+        }
+
+        let key = str::from_utf8(&buf)
+            .ok()
+            .and_then(|s| s.strip_prefix("11;"))
+            .and_then(parse_osc_color)
+            .map(KeySeq::Background)
+            .unwrap_or(KeySeq::NotIdentified); 
+        Ok(InputSeq::new(key))
+    }
+
+
+    //wait until there is something to read on stdin, false when timeout passed first
+    fn wait_input(&self, timeout: Duration) -> Result<bool> {
+        if !self.bytes.is_empty() {
+            return Ok(true); 
+        }
+
+        let millis = timeout.as_millis().min(i32::MAX as u128) as i32; 
+        Ok(poll(&[self.stdin.as_raw_fd()], millis)?[0])
+    }
+
+
+    //block until there is input, the terminal was resized, wake is readable or the timeout passed
+    fn wait(&mut self, wake: Option<RawFd>, timeout: Option<Duration>) -> Result<Ready> {
+        if !self.bytes.is_empty() {
+            return Ok(Ready::Input); 
+        }
+
+        let deadline = timeout.map(|t| Instant::now() + t); 
+        let mut fds = vec![self.stdin.as_raw_fd(), self.resize.as_raw_fd()]; 
+        fds.extend(wake); 
+
+        loop {
+            //rounded up, poll would return just before the deadline
+            let millis = match deadline {
+                Some(at) => at.saturating_duration_since(Instant::now()).as_micros().div_ceil(1000).min(i32::MAX as u128) as i32, 
+                None => -1, 
+            }; 
+            let ready = poll(&fds, millis)?; 
+
+            if ready[1] {
+                //several signals before we woke up are a single resize
+                let mut drain = [0u8; 64]; 
+                while matches!(self.resize.read(&mut drain), Ok(n) if n > 0) {}
+                return Ok(Ready::Resize); 
             }
+            if ready[0] {
+                return Ok(Ready::Input); 
+            }
+            if ready.get(2) == Some(&true) {
+                return Ok(Ready::Wake); 
+            }
+            if deadline.is_some_and(|at| Instant::now() >= at) {
+                return Ok(Ready::Timeout); 
+            }
+        }
+    }
+
 
-            _ => unreachable!(),
+    //send a query to the terminal followed by a device attributes query. every terminal answers
+    //the latter, so no reply before it means the terminal does not support the query. timeout is
+    //for the terminals which do not answer at all. keys typed meanwhile are kept for the editor
+    fn query<W, T, F>(&mut self, mut output: W, request: &[u8], timeout: Duration, reply: F) -> Result<Option<T>>
+    where
+        W: Write,
+        F: Fn(&KeySeq) -> Option<T>,
+    {
+        output.write_all(request)?; 
+        output.write_all(b"\x1b[c")?; 
+        output.flush()?; 
+
+        let mut found = None; 
+        let deadline = Instant::now() + timeout; 
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now()); 
+            if left.is_zero() || !self.wait_input(left)? {
+                return Ok(found); 
+            }
+
+            let seq = self.read_seq()?; 
+            if seq.key == KeySeq::DeviceAttributes {
+                return Ok(found); 
+            }
+
+            match reply(&seq.key).filter(|_| found.is_none()) {
+                Some(value) => found = Some(value), 
+                None => self.pending.push_back(seq), 
+            }
         }
     }
 
 
+    pub fn raw_mode(&self) -> RawMode {
+        self.stdin.raw_mode()
+    }
+
+
+    //the background color of the terminal with OSC 11
+    pub fn query_background<W: Write>(&mut self, output: W, timeout: Duration) -> Result<Option<Rgb>> {
+        self.query(output, b"\x1b]11;?\x1b\\", timeout, |key| match key {
+            KeySeq::Background(rgb) => Some(*rgb), 
+            _ => None, 
+        })
+    }
+
+
+    //whether the terminal supports the kitty keyboard protocol
+    pub fn query_keyboard_protocol<W: Write>(&mut self, output: W, timeout: Duration) -> Result<bool> {
+        let flags = self.query(output, b"\x1b[?u", timeout, |key| match key {
+            KeySeq::KeyboardFlags(flags) => Some(*flags), 
+            _ => None, 
+        })?; 
+        Ok(flags.is_some())
+    }
+
+
     fn decode(&mut self, streams: u8) -> Result<InputSeq> {
         use KeySeq::*; 
 
@@ -271,14 +601,13 @@ impl InputSequence {
             
             0x00..=0x1f => match streams {
                 0x1b => self.decode_escape_sequence(), 
+                0x09 => Ok(InputSeq::new(TabKey)), 
+                0x0d => Ok(InputSeq::new(EnterKey)), 
                 0x00 | 0x1f => Ok(InputSeq::ctrl(Key(streams | 0b0010_0000))), 
                 0x01c | 0x01d => Ok(InputSeq::ctrl(Key(streams | 0b0100_0000))),
 
                 _ => Ok(InputSeq::ctrl(Key(streams | 0b0110_0000)))
             }
-
-            0x20..=0x7f => Ok(InputSeq::new(Key(streams))), 
-            0x80..=0x9f => Ok(InputSeq::new(KeySeq::NotIdentified))
         }
     }
 
@@ -291,27 +620,21 @@ impl InputSequence {
         let mut buf = [0;4]; 
         buf[0] = data; //assign the data i32 -> u8
         let mut len = 1; 
-        let fill = buf[..len].to_vec(); 
-
 
-        
+        //keep reading while the sequence is only incomplete, an invalid byte ends it
         loop {
-            
-            if let Some(x) = self.read_byte()? {
-                buf[len] = data; 
-                len += 1; 
-            
-            }else {
-                return Err(Error::NotUtf8iInput(fill))
-            }    
-
-
-            if let Ok(x) = std::str::from_utf8(&buf){
-                return Ok(InputSeq::new(KeySeq::Utf8Key(x.chars().next().unwrap())))
+            match std::str::from_utf8(&buf[..len]) {
+                Ok(x) => return Ok(InputSeq::new(KeySeq::Utf8Key(x.chars().next().unwrap()))), 
+                Err(e) if e.error_len().is_none() && len < 4 => {}, 
+                Err(_) => return Err(Error::NotUtf8iInput(buf[..len].to_vec())), 
             }
 
-            if len == 4 {
-                return Err(Error::NotUtf8iInput(buf.to_vec()))
+            match self.read_byte()? {
+                Some(b) => {
+                    buf[len] = b; 
+                    len += 1; 
+                }
+                None => return Err(Error::NotUtf8iInput(buf[..len].to_vec())), 
             }
         }
     }
@@ -366,8 +689,387 @@ impl Iterator for InputSequence {
     fn next(&mut self) -> Option<Self::Item> {
         //but we could read the next-byte from the missing member from the iterator crate
         //self.read_byte()
-        Some(self.read_seq())
+        if let Some(seq) = self.pending.pop_front() {
+            return Some(Ok(seq)); 
+        }
+
+        match self.wait(None, None) {
+            Ok(Ready::Resize) => Some(Ok(InputSeq::new(KeySeq::Resize))), 
+            Ok(_) => Some(self.read_seq()), 
+            Err(err) => Some(Err(err)), 
+        }
     }
 }
 
 
+impl EventInput for InputSequence {
+    fn next_event(&mut self, wake: RawFd, timeout: Option<Duration>) -> Result<Option<InputSeq>> {
+        if let Some(seq) = self.pending.pop_front() {
+            return Ok(Some(seq)); 
+        }
+
+        match self.wait(Some(wake), timeout)? {
+            Ready::Input => self.read_seq().map(Some), 
+            Ready::Resize => Ok(Some(InputSeq::new(KeySeq::Resize))), 
+            Ready::Wake | Ready::Timeout => Ok(None), 
+        }
+    }
+
+    //the cursor stops at the bottom right corner when moved far enough, it is put back after
+    fn query_window_size<W: Write>(&mut self, output: W, timeout: Duration) -> Result<Option<(usize, usize)>> {
+        self.cursor_query = true; 
+        let size = self.query(output, b"\x1b7\x1b[999C\x1b[999B\x1b[6n\x1b8", timeout, |key| match key {
+            KeySeq::Cursor(row, col) => Some((*col, *row)), 
+            _ => None, 
+        }); 
+        self.cursor_query = false; 
+        size
+    }
+}
+
+
+
+//poll the descriptors for input, which of them are readable. a signal interrupting the wait
+//means none is
+fn poll(fds: &[i32], timeout: i32) -> io::Result<Vec<bool>> {
+    let mut fds: Vec<_> = fds.iter().map(|&fd| libc::pollfd { fd, events: libc::POLLIN, revents: 0 }).collect(); 
+
+    if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) } == -1 {
+        let err = io::Error::last_os_error(); 
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err); 
+        }
+        return Ok(vec![false; fds.len()]); 
+    }
+
+    Ok(fds.iter().map(|p| p.revents & (libc::POLLIN | libc::POLLHUP) != 0).collect())
+}
+
+
+//"rgb:rrrr/gggg/bbbb" where each channel has 1 to 4 hex digits
+fn parse_osc_color(spec: &str) -> Option<Rgb> {
+    let mut channels = spec.strip_prefix("rgb:")?.split('/').map(|c| {
+        let max = 16u32.checked_pow(c.len() as u32)?.checked_sub(1).filter(|_| c.len() <= 4)?; 
+        let value = u32::from_str_radix(c, 16).ok()?; 
+        Some((value * 255 / max.max(1)) as u8)
+    }); 
+
+    let rgb = Rgb(channels.next()??, channels.next()??, channels.next()??); 
+    match channels.next() {
+        None => Some(rgb), 
+        Some(_) => None, 
+    }
+}
+
+
+
+//modifier parameter of xterm, 1 + (1 for shift, 2 for alt, 4 for ctrl, 8 for meta)
+fn apply_modifier(mut seq: InputSeq, param: Option<&[u8]>) -> InputSeq {
+    let bits = param
+        .and_then(|p| str::from_utf8(p).ok())
+        .and_then(|p| p.parse::<u8>().ok())
+        .map(|m| m.saturating_sub(1))
+        .unwrap_or(0); 
+
+    seq.shift |= bits & 1 != 0; 
+    seq.alt |= bits & (2 | 8) != 0; 
+    seq.ctrl |= bits & 4 != 0; 
+    seq
+}
+
+
+//ESC [ <params> <cmd>, cursor_query when the terminal is asked for the cursor position
+fn decode_csi(params: &[u8], cmd: u8, cursor_query: bool) -> InputSeq {
+    use KeySeq::*; 
+
+    //replies of the terminal to our queries
+    if params.first() == Some(&b'?') {
+        return InputSeq::new(match cmd {
+            b'u' => KeyboardFlags(parse_num(&params[1..]).unwrap_or(0) as u8), 
+            b'c' => DeviceAttributes, 
+            _ => NotIdentified, 
+        }); 
+    }
+
+    if cmd == b'u' {
+        return decode_kitty(params); 
+    }
+
+    if params.first() == Some(&b'<') && (cmd == b'M' || cmd == b'm') {
+        return decode_sgr_mouse(&params[1..], cmd == b'm'); 
+    }
+
+    if params.first() == Some(&b'[') {
+        return match cmd {
+            b'A'..=b'E' => InputSeq::new(FunctionKey(cmd - b'A' + 1)), 
+            _ => InputSeq::new(NotIdentified), 
+        }; 
+    }
+
+    let mut args = params.split(|b| *b == b';'); 
+    let first = args.next().filter(|a| !a.is_empty()); 
+    let modifier = args.next(); 
+
+    let key = match cmd {
+        b'A' => UpKey, 
+        b'B' => DownKey, 
+        b'C' => RightKey, 
+        b'D' => LeftKey, 
+        b'H' => HomeKey, 
+        b'F' => EndKey, 
+        b'P' => FunctionKey(1), 
+        b'Q' => FunctionKey(2), 
+        b'S' => FunctionKey(4), 
+        //ESC [ <row> ; <col> R is a cursor position report only while we ask for one, else
+        //ESC [ 1 ; <modifier> R is F3 with shift, alt or ctrl
+        b'R' if cursor_query => match (first.and_then(parse_num), modifier.and_then(parse_num)) {
+            (Some(row), Some(col)) => return InputSeq::new(Cursor(row, col)), 
+            _ => FunctionKey(3), 
+        }, 
+        b'R' => FunctionKey(3), 
+        //shift+tab
+        b'Z' => TabKey, 
+        b'~' => match first {
+            Some(b"1") | Some(b"7") => HomeKey, 
+            Some(b"2") => InsertKey, 
+            Some(b"3") => DeleteKey, 
+            Some(b"4") | Some(b"8") => EndKey, 
+            Some(b"5") => PageUpKey, 
+            Some(b"6") => PageDownKey, 
+            Some(n) => match parse_num(n) {
+                Some(n @ 11..=15) => FunctionKey(n as u8 - 10), 
+                Some(n @ 17..=21) => FunctionKey(n as u8 - 11), 
+                Some(n @ 23..=24) => FunctionKey(n as u8 - 12), 
+                _ => NotIdentified, 
+            }, 
+            None => NotIdentified, 
+        }, 
+        _ => NotIdentified, 
+    }; 
+
+    if key == NotIdentified {
+        return InputSeq::new(key); 
+    }
+
+    let mut seq = apply_modifier(InputSeq::new(key), modifier); 
+    //shift+tab
+    seq.shift |= cmd == b'Z'; 
+    seq
+}
+
+
+//a key of the kitty keyboard protocol, ESC [ <code>[:<alternates>] ; <modifiers>[:<event>] [; <text>] u
+//where code is the unicode code point of the key without shift
+fn decode_kitty(params: &[u8]) -> InputSeq {
+    use KeySeq::*; 
+
+    let mut args = params.split(|b| *b == b';').map(|a| a.split(|b| *b == b':').next().and_then(parse_num)); 
+    let code = args.next().flatten(); 
+    //1 + (1 for shift, 2 for alt, 4 for ctrl, 8 for super, 32 for meta, 64 for caps lock, ...)
+    let mods = args.next().flatten().unwrap_or(1).saturating_sub(1); 
+
+    let mut seq = InputSeq {
+        shift: mods & 1 != 0, 
+        alt: mods & (2 | 32) != 0, 
+        ctrl: mods & 4 != 0, 
+        key: NotIdentified, 
+    }; 
+
+    seq.key = match code.and_then(|c| char::from_u32(c as u32)) {
+        Some('\x1b') => Key(0x1b), 
+        Some('\r') => EnterKey, 
+        Some('\t') => TabKey, 
+        Some('\x7f') | Some('\x08') => Key(0x7f), 
+        //legacy terminals send C-/ as C-?, which is the key of the help
+        Some('/') if seq.ctrl => Key(b'?'), 
+        Some(c) if c.is_ascii_lowercase() && seq.shift && !seq.ctrl => {
+            seq.shift = false; 
+            Key(c.to_ascii_uppercase() as u8)
+        }
+        Some(c) if c.is_ascii_graphic() || c == ' ' => Key(c as u8), 
+        //functional keys such as the keypad ones are in the private use area
+        Some(c) if !c.is_ascii() && !('\u{e000}'..='\u{f8ff}').contains(&c) => Utf8Key(c), 
+        _ => return InputSeq::new(NotIdentified), 
+    }; 
+
+    seq
+}
+
+
+//SGR mouse report, ESC [ < <button> ; <col> ; <row> M, ending with m when a button is released
+fn decode_sgr_mouse(params: &[u8], release: bool) -> InputSeq {
+    use MouseKind::*; 
+
+    let mut args = params.split(|b| *b == b';').map(parse_num); 
+    let (button, col, row) = match (args.next().flatten(), args.next().flatten(), args.next().flatten()) {
+        (Some(b), Some(col), Some(row)) if col > 0 && row > 0 => (b, col - 1, row - 1), 
+        _ => return InputSeq::new(KeySeq::NotIdentified), 
+    }; 
+
+    //4, 8 and 16 are shift, alt and ctrl, 32 is motion while a button is down and 64 the wheel
+    let kind = match (button & !(4 | 8 | 16), release) {
+        (0, false) => Press, 
+        (0, true) => Release, 
+        (32, _) => Drag, 
+        (64, _) => WheelUp, 
+        (65, _) => WheelDown, 
+        _ => return InputSeq::new(KeySeq::NotIdentified), 
+    }; 
+
+    InputSeq {
+        shift: button & 4 != 0, 
+        alt: button & 8 != 0, 
+        ctrl: button & 16 != 0, 
+        key: KeySeq::Mouse(Mouse { kind, row, col }), 
+    }
+}
+
+
+//ESC O <cmd>, sent for arrows in application cursor mode and for F1 to F4
+fn decode_ss3(cmd: u8) -> InputSeq {
+    use KeySeq::*; 
+
+    InputSeq::new(match cmd {
+        b'A' => UpKey, 
+        b'B' => DownKey, 
+        b'C' => RightKey, 
+        b'D' => LeftKey, 
+        b'H' => HomeKey, 
+        b'F' => EndKey, 
+        b'P' => FunctionKey(1), 
+        b'Q' => FunctionKey(2), 
+        b'R' => FunctionKey(3), 
+        b'S' => FunctionKey(4), 
+        _ => NotIdentified, 
+    })
+}
+
+
+fn parse_num(arg: &[u8]) -> Option<usize> {
+    str::from_utf8(arg).ok()?.parse().ok()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use KeySeq::*;
+
+    fn key(s: &str) -> InputSeq {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn csi_arrows_with_modifiers() {
+        assert_eq!(decode_csi(b"", b'A', false), key("UP"));
+        assert_eq!(decode_csi(b"1;5", b'C', false), key("C-RIGHT"));
+        assert_eq!(decode_csi(b"1;3", b'D', false), key("M-LEFT"));
+        assert_eq!(decode_csi(b"1;2", b'B', false), key("S-DOWN"));
+        assert_eq!(decode_csi(b"1;8", b'H', false), key("C-M-S-HOME"));
+    }
+
+    #[test]
+    fn csi_tilde_keys() {
+        assert_eq!(decode_csi(b"3", b'~', false), key("DELETE"));
+        assert_eq!(decode_csi(b"5;5", b'~', false), key("C-PAGEUP"));
+        assert_eq!(decode_csi(b"15", b'~', false), key("F5"));
+        assert_eq!(decode_csi(b"24;2", b'~', false), key("S-F12"));
+        assert_eq!(decode_csi(b"16", b'~', false).key, NotIdentified);
+    }
+
+    #[test]
+    fn csi_shift_tab_and_linux_console_f_keys() {
+        assert_eq!(decode_csi(b"", b'Z', false), key("S-TAB"));
+        assert_eq!(decode_csi(b"[", b'C', false), key("F3"));
+    }
+
+    #[test]
+    fn csi_r_is_f3_unless_the_cursor_is_asked_for() {
+        assert_eq!(decode_csi(b"1;2", b'R', false), key("S-F3"));
+        assert_eq!(decode_csi(b"1;5", b'R', false), key("C-F3"));
+        assert_eq!(decode_csi(b"", b'R', false), key("F3"));
+        assert_eq!(decode_csi(b"24;80", b'R', true), InputSeq::new(Cursor(24, 80)));
+        assert_eq!(decode_csi(b"", b'R', true), key("F3"));
+    }
+
+    #[test]
+    fn osc_colors() {
+        assert_eq!(parse_osc_color("rgb:ffff/ffff/ffff"), Some(Rgb(255, 255, 255)));
+        assert_eq!(parse_osc_color("rgb:0000/8080/ffff"), Some(Rgb(0, 128, 255)));
+        assert_eq!(parse_osc_color("rgb:f/0/8"), Some(Rgb(255, 0, 136)));
+        assert_eq!(parse_osc_color("rgb:1e/1e/2e"), Some(Rgb(30, 30, 46)));
+    }
+
+    #[test]
+    fn invalid_osc_colors() {
+        assert_eq!(parse_osc_color("#ffffff"), None);
+        assert_eq!(parse_osc_color("rgb:ff/ff"), None);
+        assert_eq!(parse_osc_color("rgb:ff/ff/ff/ff"), None);
+        assert_eq!(parse_osc_color("rgb:fffff/0/0"), None);
+        assert_eq!(parse_osc_color("rgb:gg/0/0"), None);
+        assert_eq!(parse_osc_color("rgb://"), None);
+    }
+
+    #[test]
+    fn kitty_keys() {
+        assert_eq!(decode_kitty(b"97"), key("a"));
+        assert_eq!(decode_kitty(b"97;5"), key("C-a"));
+        assert_eq!(decode_kitty(b"97;3"), key("M-a"));
+        assert_eq!(decode_kitty(b"105;5"), InputSeq::ctrl(Key(b'i')));
+        assert_eq!(decode_kitty(b"13"), key("ENTER"));
+        assert_eq!(decode_kitty(b"9;2"), key("S-TAB"));
+        assert_eq!(decode_kitty(b"27"), key("ESC"));
+        assert_eq!(decode_kitty(b"127"), key("BACKSPACE"));
+        assert_eq!(decode_kitty(b"47;5"), key("C-?"));
+        assert_eq!(decode_kitty(b"233"), InputSeq::new(Utf8Key('é')));
+    }
+
+    #[test]
+    fn kitty_shift_makes_upper_case_letters() {
+        assert_eq!(decode_kitty(b"120;2"), key("X"));
+        assert_eq!(decode_kitty(b"120:88;4"), key("M-X"));
+        assert_eq!(decode_kitty(b"120;6"), key("C-S-x"));
+    }
+
+    #[test]
+    fn kitty_event_types_and_functional_keys() {
+        assert_eq!(decode_kitty(b"97;1:1"), key("a"));
+        assert_eq!(decode_kitty(b"57399").key, NotIdentified);
+        assert_eq!(decode_kitty(b"").key, NotIdentified);
+    }
+
+    #[test]
+    fn csi_query_replies() {
+        assert_eq!(decode_csi(b"?1", b'u', false).key, KeyboardFlags(1));
+        assert_eq!(decode_csi(b"?62;22", b'c', false).key, DeviceAttributes);
+    }
+
+    //decode the first byte of a sequence, the rest is already buffered as if read from stdin
+    fn decode_bytes(bytes: &[u8]) -> Result<InputSeq> {
+        let (resize, _) = UnixStream::pair().unwrap(); 
+        //a zeroed termios is never applied, the input is leaked so its Drop does not restore it
+        let stdin = StdinMode { stdin: io::stdin(), origin: unsafe { std::mem::zeroed() }, raw: unsafe { std::mem::zeroed() } }; 
+        let mut input = std::mem::ManuallyDrop::new(InputSequence {
+            stdin, 
+            pending: VecDeque::new(), 
+            bytes: bytes[1..].iter().copied().collect(), 
+            resize, 
+            escape_timeout: ESCAPE_TIMEOUT, 
+            cursor_query: false, 
+        }); 
+        input.decode(bytes[0])
+    }
+
+    #[test]
+    fn utf8_keys() {
+        assert_eq!(decode_bytes("é".as_bytes()).unwrap(), InputSeq::new(Utf8Key('é')));
+        assert_eq!(decode_bytes("日".as_bytes()).unwrap(), InputSeq::new(Utf8Key('日')));
+        assert_eq!(decode_bytes(b"a").unwrap(), key("a"));
+    }
+
+    #[test]
+    fn invalid_utf8_keys() {
+        assert!(matches!(decode_bytes(&[0xc3, 0x41]), Err(Error::NotUtf8iInput(b)) if b == [0xc3, 0x41]));
+        assert!(matches!(decode_bytes(&[0xa9]), Err(Error::NotUtf8iInput(b)) if b == [0xa9]));
+    }
+}
@@ -28,6 +28,13 @@ impl Language {
     }
 
 
+    pub fn from_name(name: &str) -> Option<Language> {
+        use Language::*; 
+
+        [Plain, Rust].iter().copied().find(|lang| lang.name() == name)
+    }
+
+
     fn file_exts(self) -> &'static [&'static str] {
         use Language::*; 
 
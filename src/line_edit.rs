@@ -0,0 +1,215 @@
+use unicode_width::UnicodeWidthChar;
+
+use crate::input::{InputSeq, KeySeq};
+
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+
+//single line text with a cursor, edited with the usual readline/Emacs keys
+pub struct LineEdit {
+    text: String,
+    cursor: usize, //byte index into text, always on a char boundary
+    killed: String,
+}
+
+
+impl LineEdit {
+    pub fn new() -> Self {
+        Self {
+            text: String::new(),
+            cursor: 0,
+            killed: String::new(),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    pub fn into_string(self) -> String {
+        self.text
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    //replace the whole text, the cursor goes to its end
+    pub fn set<S: Into<String>>(&mut self, text: S) {
+        self.text = text.into();
+        self.cursor = self.text.len();
+    }
+
+    //display columns between the head of the text and the cursor
+    pub fn cursor_width(&self) -> usize {
+        self.text[..self.cursor].chars().map(|c| c.width_cjk().unwrap_or(1)).sum()
+    }
+
+    pub fn insert_str(&mut self, s: &str) {
+        self.text.insert_str(self.cursor, s);
+        self.cursor += s.len();
+    }
+
+    pub fn insert_char(&mut self, c: char) {
+        self.text.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+    }
+
+    fn prev_boundary(&self, at: usize) -> usize {
+        self.text[..at].char_indices().next_back().map(|(i, _)| i).unwrap_or(0)
+    }
+
+    fn next_boundary(&self, at: usize) -> usize {
+        self.text[at..].chars().next().map(|c| at + c.len_utf8()).unwrap_or(at)
+    }
+
+    fn word_start_before(&self, at: usize) -> usize {
+        let mut i = at;
+        while i > 0 && !self.text[..i].chars().next_back().map(is_word_char).unwrap_or(false) {
+            i = self.prev_boundary(i);
+        }
+        while i > 0 && self.text[..i].chars().next_back().map(is_word_char).unwrap_or(false) {
+            i = self.prev_boundary(i);
+        }
+        i
+    }
+
+    fn word_end_after(&self, at: usize) -> usize {
+        let mut i = at;
+        while i < self.text.len() && !self.text[i..].chars().next().map(is_word_char).unwrap_or(false) {
+            i = self.next_boundary(i);
+        }
+        while i < self.text.len() && self.text[i..].chars().next().map(is_word_char).unwrap_or(false) {
+            i = self.next_boundary(i);
+        }
+        i
+    }
+
+    fn kill(&mut self, start: usize, end: usize) {
+        if start < end {
+            self.killed = self.text[start..end].to_string();
+            self.text.replace_range(start..end, "");
+            self.cursor = start;
+        }
+    }
+
+    //apply an editing key, returns false when seq is not an editing key
+    pub fn handle(&mut self, seq: &InputSeq) -> bool {
+        use KeySeq::*;
+
+        match seq {
+            InputSeq { key: LeftKey, ctrl: false, alt: false, .. } | InputSeq { key: Key(b'b'), ctrl: true, .. } => {
+                self.cursor = self.prev_boundary(self.cursor);
+            }
+            InputSeq { key: RightKey, ctrl: false, alt: false, .. } | InputSeq { key: Key(b'f'), ctrl: true, .. } => {
+                self.cursor = self.next_boundary(self.cursor);
+            }
+            InputSeq { key: HomeKey, .. } | InputSeq { key: Key(b'a'), ctrl: true, .. } => self.cursor = 0,
+            InputSeq { key: EndKey, .. } | InputSeq { key: Key(b'e'), ctrl: true, .. } => self.cursor = self.text.len(),
+            InputSeq { key: LeftKey, .. } | InputSeq { key: Key(b'b'), alt: true, .. } => {
+                self.cursor = self.word_start_before(self.cursor);
+            }
+            InputSeq { key: RightKey, .. } | InputSeq { key: Key(b'f'), alt: true, .. } => {
+                self.cursor = self.word_end_after(self.cursor);
+            }
+            InputSeq { key: Key(b'h'), ctrl: true, .. } | InputSeq { key: Key(0x7f), .. } => {
+                let start = self.prev_boundary(self.cursor);
+                self.text.replace_range(start..self.cursor, "");
+                self.cursor = start;
+            }
+            InputSeq { key: DeleteKey, .. } | InputSeq { key: Key(b'd'), ctrl: true, .. } => {
+                let end = self.next_boundary(self.cursor);
+                self.text.replace_range(self.cursor..end, "");
+            }
+            InputSeq { key: Key(b'k'), ctrl: true, .. } => self.kill(self.cursor, self.text.len()),
+            InputSeq { key: Key(b'u'), ctrl: true, .. } => self.kill(0, self.cursor),
+            InputSeq { key: Key(b'w'), ctrl: true, .. } => self.kill(self.word_start_before(self.cursor), self.cursor),
+            InputSeq { key: Key(b'd'), alt: true, .. } => self.kill(self.cursor, self.word_end_after(self.cursor)),
+            InputSeq { key: Key(b'y'), ctrl: true, .. } => {
+                let killed = self.killed.clone();
+                self.insert_str(&killed);
+            }
+            InputSeq { key: Key(b), ctrl: false, alt: false, .. } if !b.is_ascii_control() => self.insert_char(*b as char),
+            InputSeq { key: Utf8Key(c), ctrl: false, alt: false, .. } => self.insert_char(*c),
+            //a prompt has one line
+            InputSeq { key: Paste(text), .. } => self.insert_str(text.lines().next().unwrap_or("")),
+            _ => return false,
+        }
+
+        true
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //text with the cursor at its end, after the space separated keys
+    fn edit(text: &str, keys: &str) -> LineEdit {
+        let mut line = LineEdit::new();
+        line.set(text);
+        for key in keys.split_whitespace() {
+            assert!(line.handle(&key.parse().unwrap()), "{}", key);
+        }
+        line
+    }
+
+    fn cursor(line: &LineEdit) -> &str {
+        &line.as_str()[..line.cursor]
+    }
+
+    #[test]
+    fn word_motions() {
+        assert_eq!(cursor(&edit("foo bar_baz  ", "M-b")), "foo ");
+        assert_eq!(cursor(&edit("foo bar_baz  ", "M-b M-b")), "");
+        assert_eq!(cursor(&edit("foo bar_baz  ", "C-LEFT C-LEFT")), "");
+        assert_eq!(cursor(&edit("foo, bar", "C-a M-f")), "foo");
+        assert_eq!(cursor(&edit("foo, bar", "C-a M-f C-RIGHT")), "foo, bar");
+        assert_eq!(cursor(&edit("日本 語", "M-b")), "日本 ");
+    }
+
+    #[test]
+    fn kills() {
+        let line = edit("foo bar baz", "M-b C-k");
+        assert_eq!((line.as_str(), line.killed.as_str()), ("foo bar ", "baz"));
+        let line = edit("foo bar baz", "C-w");
+        assert_eq!((line.as_str(), line.killed.as_str()), ("foo bar ", "baz"));
+        let line = edit("foo bar baz", "M-b C-u");
+        assert_eq!((line.as_str(), line.killed.as_str(), cursor(&line)), ("baz", "foo bar ", ""));
+        let line = edit("foo bar", "C-a M-d");
+        assert_eq!((line.as_str(), line.killed.as_str()), (" bar", "foo"));
+    }
+
+    #[test]
+    fn yank() {
+        assert_eq!(edit("foo bar", "C-w C-a C-y").as_str(), "barfoo ");
+        assert_eq!(edit("foo bar", "C-w C-y C-y").as_str(), "foo barbar");
+        assert_eq!(edit("foo", "C-y").as_str(), "foo");
+    }
+
+    #[test]
+    fn edits_between_chars() {
+        assert_eq!(edit("日本", "LEFT BACKSPACE").as_str(), "本");
+        assert_eq!(edit("日本", "C-a DELETE").as_str(), "本");
+        assert_eq!(edit("日本", "LEFT é").as_str(), "日é本");
+    }
+
+    #[test]
+    fn cursor_width_counts_columns() {
+        assert_eq!(edit("日本", "").cursor_width(), 4);
+        assert_eq!(edit("日本", "LEFT").cursor_width(), 2);
+        assert_eq!(edit("a日本", "C-a C-f").cursor_width(), 1);
+    }
+
+    #[test]
+    fn other_keys_are_left_to_the_prompt() {
+        let mut line = edit("foo", "");
+        assert!(!line.handle(&"ENTER".parse().unwrap()));
+        assert!(!line.handle(&"C-g".parse().unwrap()));
+        assert_eq!(line.as_str(), "foo");
+    }
+}
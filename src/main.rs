@@ -4,14 +4,18 @@
 use std::process::exit;
 use std::env; 
 use std::io;
+use std::time::Duration;
 
 use getopts::Options; 
 
 
-use crate::screen::{ HELP, VERSION}; 
-use crate::error::{ Result, Error};
+use crate::screen::VERSION; 
+use crate::command::Keymap;
+use crate::config::Config;
+use crate::error::Result;
 use crate::editor::Editor;
 use crate::input::StdinMode;
+use crate::theme::Background;
 /**
  * At some point i got a moved Copy trait error, 
  * I was just lost, i had to trace the error down to every input keyword on my code
@@ -22,6 +26,10 @@ use crate::input::StdinMode;
 static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc; 
 
 
+//how long to wait for the terminal to answer a query, such as its background color
+const QUERY_TIMEOUT: Duration = Duration::from_millis(100);
+
+
 mod screen;
 mod color; 
 mod error;
@@ -34,7 +42,18 @@ mod edit_diff;
 mod prompt;
 mod message;
 mod input;
-mod buffer;
+mod window;
+mod fuzzy;
+mod finder;
+mod complete;
+mod history;
+mod line_edit;
+mod command;
+mod config;
+mod project;
+mod theme;
+mod event;
+mod vi;
 
 
 fn print_help(program: &str, opts: Options) {
@@ -52,7 +71,7 @@ fn print_help(program: &str, opts: Options) {
         Mappings:
             {maps}",
                 prog = program,
-                maps = HELP,
+                maps = Keymap::with_bindings(&Config::load().keys).0.help(),
     );
 
 
@@ -65,9 +84,31 @@ fn print_help(program: &str, opts: Options) {
 fn edit(files: Vec<String>) -> Result<()>{
    // Editor::open(input, output, window_size, paths)
 
-   let input = StdinMode::new()?.input_keys();
+   let config = Config::load(); 
+   let mut input = StdinMode::new()?.input_keys()?;
+   let raw_mode = input.raw_mode(); 
+
+   let settings = config.settings(); 
+   input.set_escape_timeout(Duration::from_millis(settings.escape_timeout)); 
+
+   //only an "auto" theme depends on the background
+   let background = if settings.theme == "auto" && settings.background == "auto" {
+       input.query_background(io::stdout(), QUERY_TIMEOUT)?.map(Background::from_rgb)
+   } else {
+       None
+   }; 
+   let kitty_keyboard = input.query_keyboard_protocol(io::stdout(), QUERY_TIMEOUT)?; 
+
    //let output = io::stdout();  
-   Editor::open(input, io::stdout(), None, &files)?.edit()
+   let mut editor = Editor::open(input, io::stdout(), None, &files, config)?; 
+   if let Some(background) = background {
+       editor.set_background(background); 
+   }
+   if kitty_keyboard {
+       editor.enable_kitty_keyboard()?; 
+   }
+   editor.set_raw_mode(raw_mode); 
+   editor.edit()
 }
 
 
@@ -0,0 +1,286 @@
+use std::fs;
+use std::path::Path;
+
+use globset::GlobBuilder;
+
+use crate::config::{Config, SettingsLayer};
+
+
+const EDITORCONFIG: &str = ".editorconfig";
+const PROJECT_CONFIG: &str = ".detty.toml";
+
+
+//settings of the project a file belongs to, from the .editorconfig files and the nearest .detty.toml
+//in the directories above it
+pub struct Project {
+    pub editorconfig: SettingsLayer,
+    pub config: Option<Config>,
+    pub warnings: Vec<String>,
+}
+
+
+impl Project {
+    pub fn load(file: &Path) -> Self {
+        let file = if file.is_absolute() {
+            file.to_path_buf()
+        } else {
+            std::env::current_dir().map(|d| d.join(file)).unwrap_or_else(|_| file.to_path_buf())
+        };
+
+        let mut project = Self {
+            editorconfig: SettingsLayer::default(),
+            config: None,
+            warnings: vec![],
+        };
+
+        //nearest directory first
+        let mut editorconfigs = vec![];
+        for dir in file.ancestors().skip(1) {
+            if project.config.is_none() {
+                let path = dir.join(PROJECT_CONFIG);
+                if path.is_file() {
+                    let config = Config::from_file(&path);
+                    project.warnings.extend(config.warnings.iter().cloned());
+                    if !config.keys.is_empty() {
+                        project.warnings.push(format!("{}: key bindings are only read from the user config", path.display()));
+                    }
+                    project.config = Some(config);
+                }
+            }
+
+            let path = dir.join(EDITORCONFIG);
+            if let Ok(text) = fs::read_to_string(&path) {
+                let parsed = EditorConfig::parse(&text);
+                let root = parsed.root;
+                editorconfigs.push((dir.to_path_buf(), parsed));
+                if root {
+                    break;
+                }
+            }
+        }
+
+        //the nearest file is read last so that its sections win
+        let mut props = Props::default();
+        for (dir, editorconfig) in editorconfigs.iter().rev() {
+            editorconfig.apply(dir, &file, &mut props);
+        }
+        project.editorconfig = props.finish();
+
+        project
+    }
+}
+
+
+//the properties of the sections read so far. tab_width follows the last indent_size unless it is
+//set itself, which is only known once every section of every file has been read
+#[derive(Default)]
+struct Props {
+    layer: SettingsLayer,
+    tab_width: Option<usize>,
+    indent_is_tab: bool,
+}
+
+
+impl Props {
+    fn finish(mut self) -> SettingsLayer {
+        let indent_size = if self.indent_is_tab { None } else { self.layer.indent_width };
+        self.layer.tab_width = self.tab_width.or(indent_size);
+        if self.indent_is_tab {
+            self.layer.indent_width = self.layer.tab_width.or(self.layer.indent_width);
+        }
+
+        self.layer
+    }
+}
+
+
+struct Section {
+    glob: String,
+    props: Vec<(String, String)>,
+}
+
+
+//the parts of an .editorconfig file we need: whether it is the root and its sections in order
+struct EditorConfig {
+    root: bool,
+    sections: Vec<Section>,
+}
+
+
+impl EditorConfig {
+    fn parse(text: &str) -> Self {
+        let mut config = Self { root: false, sections: vec![] };
+
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if let Some(glob) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                config.sections.push(Section { glob: glob.to_string(), props: vec![] });
+                continue;
+            }
+
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim().to_lowercase(), value.trim().to_string()),
+                None => continue,
+            };
+
+            match config.sections.last_mut() {
+                Some(section) => section.props.push((key, value)),
+                None if key == "root" => config.root = value.eq_ignore_ascii_case("true"),
+                None => {}
+            }
+        }
+
+        config
+    }
+
+    //apply the sections matching file, which is somewhere under dir
+    fn apply(&self, dir: &Path, file: &Path, props: &mut Props) {
+        let rel = match file.strip_prefix(dir) {
+            Ok(rel) => rel,
+            Err(_) => return,
+        };
+
+        for section in self.sections.iter().filter(|s| section_matches(&s.glob, rel)) {
+            for (key, value) in &section.props {
+                apply_prop(props, key, &value.to_lowercase());
+            }
+        }
+    }
+}
+
+
+//a glob without a slash matches the file name in any directory, one with a slash is relative to the
+//directory of the .editorconfig
+fn section_matches(glob: &str, rel: &Path) -> bool {
+    let pattern = if glob.contains('/') {
+        glob.trim_start_matches('/').to_string()
+    } else {
+        format!("**/{}", glob)
+    };
+
+    //** matches across directories even inside a name, like "src/**.rs", which globset wants
+    //as a component of its own
+    let pattern = pattern.replace("/**", "/**/*").replace("**/*/", "**/").replace("**/**", "**");
+
+    GlobBuilder::new(&pattern)
+        .literal_separator(true)
+        .build()
+        .map(|g| g.compile_matcher().is_match(rel))
+        .unwrap_or(false)
+}
+
+
+fn apply_prop(props: &mut Props, key: &str, value: &str) {
+    //"unset" and values we do not understand leave the setting as it was
+    let number = value.parse::<usize>().ok().filter(|n| *n > 0);
+    let flag = match value {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    };
+
+    let layer = &mut props.layer;
+    match key {
+        "indent_style" => match value {
+            "tab" => layer.expand_tabs = Some(false),
+            "space" => layer.expand_tabs = Some(true),
+            _ => {}
+        },
+        "indent_size" if value == "tab" => props.indent_is_tab = true,
+        "indent_size" => {
+            if let Some(n) = number {
+                layer.indent_width = Some(n);
+                props.indent_is_tab = false;
+            }
+        }
+        "tab_width" => props.tab_width = number.or(props.tab_width),
+        "end_of_line" if matches!(value, "lf" | "crlf" | "cr") => layer.end_of_line = Some(value.to_string()),
+        "charset" if matches!(value, "utf-8" | "utf-8-bom" | "latin1" | "utf-16be" | "utf-16le") => {
+            layer.charset = Some(value.to_string())
+        }
+        "trim_trailing_whitespace" => layer.trim_trailing_whitespace = flag.or(layer.trim_trailing_whitespace),
+        "insert_final_newline" => layer.insert_final_newline = flag.or(layer.insert_final_newline),
+        _ => {}
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(glob: &str, rel: &str) -> bool {
+        section_matches(glob, Path::new(rel))
+    }
+
+    #[test]
+    fn globs_without_slash_match_in_any_directory() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(matches("*.rs", "src/deep/main.rs"));
+        assert!(!matches("*.rs", "main.rsx"));
+        assert!(matches("*.{js,ts}", "web/app.ts"));
+        assert!(matches("{Makefile,*.mk}", "build/rules.mk"));
+        assert!(!matches("*.{js,ts}", "app.rs"));
+    }
+
+    #[test]
+    fn globs_with_slash_are_relative_to_the_file() {
+        for glob in ["src/**.rs", "src/**/*.rs", "/src/**.rs"] {
+            assert!(matches(glob, "src/main.rs"), "{}", glob);
+            assert!(matches(glob, "src/a/b/lib.rs"), "{}", glob);
+            assert!(!matches(glob, "main.rs"), "{}", glob);
+            assert!(!matches(glob, "tests/src/main.rs"), "{}", glob);
+        }
+        assert!(!matches("src/*.rs", "src/a/lib.rs"));
+    }
+
+    #[test]
+    fn root_is_read_before_the_first_section() {
+        assert!(EditorConfig::parse("root = TRUE\n[*]\nindent_size = 2\n").root);
+        assert!(!EditorConfig::parse("[*]\nroot = true\n").root);
+        assert!(!EditorConfig::parse("# root = true\n").root);
+    }
+
+    fn settings(text: &str, rel: &str) -> SettingsLayer {
+        let mut props = Props::default();
+        EditorConfig::parse(text).apply(Path::new("/p"), &Path::new("/p").join(rel), &mut props);
+        props.finish()
+    }
+
+    #[test]
+    fn tab_width_follows_the_last_indent_size() {
+        let text = "[*]\nindent_size = 4\n[*.yml]\nindent_size = 2\n";
+        assert_eq!(settings(text, "a.yml").tab_width, Some(2));
+        assert_eq!(settings(text, "a.rs").tab_width, Some(4));
+
+        let text = "[*]\ntab_width = 8\nindent_size = 4\n[*.yml]\nindent_size = 2\n";
+        assert_eq!(settings(text, "a.yml").tab_width, Some(8));
+        assert_eq!(settings(text, "a.yml").indent_width, Some(2));
+
+        let text = "[*]\nindent_size = tab\ntab_width = 3\n";
+        assert_eq!(settings(text, "a.c").indent_width, Some(3));
+    }
+
+    #[test]
+    fn nearest_file_wins_up_to_the_root() {
+        let top = std::env::temp_dir().join(format!("detty-project-{}", std::process::id()));
+        let dir = top.join("repo").join("sub");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(top.join(EDITORCONFIG), "[*]\ninsert_final_newline = false\ncharset = latin1\n").unwrap();
+        fs::write(top.join("repo").join(EDITORCONFIG), "root = true\n[*]\nindent_size = 4\nend_of_line = crlf\n").unwrap();
+        fs::write(dir.join(EDITORCONFIG), "[*.yml]\nindent_size = 2\n").unwrap();
+
+        let layer = Project::load(&dir.join("a.yml")).editorconfig;
+        assert_eq!(layer.indent_width, Some(2));
+        assert_eq!(layer.tab_width, Some(2));
+        assert_eq!(layer.end_of_line.as_deref(), Some("crlf"));
+        //above the root
+        assert_eq!(layer.charset, None);
+        assert_eq!(layer.insert_final_newline, None);
+
+        fs::remove_dir_all(&top).unwrap();
+    }
+}
@@ -1,11 +1,18 @@
 use std::io::Write; 
 
+use unicode_width::UnicodeWidthChar;
+
 
 
 use crate::input::{InputSeq, KeySeq};
+use crate::event::{Event, EventInput, Events, Notifier};
 use crate::screen::Screen; 
-use crate::status::{TextBuffer, Status}; 
+use crate::status::TextBuffer; 
+use crate::window::Windows;
 use crate::error::Result;
+use crate::fuzzy;
+use crate::history::{History, HistoryKind};
+use crate::line_edit::LineEdit;
 
 
 
@@ -17,7 +24,7 @@ pub enum PromptResult {
 
 pub struct NoAction; 
 impl Action  for NoAction {
-    fn new<W:Write>(prompt: &mut Prompt<'_, W>) -> Self {
+    fn new<W:Write>(_prompt: &mut Prompt<'_, W>) -> Self {
         Self
     }
 }
@@ -26,25 +33,24 @@ impl Action  for NoAction {
 struct PromptTemplate<'a> {
     prefix: &'a str, 
     suffix: &'a str, 
-    prefix_chars: usize,
+    prefix_width: usize,
 }
 
 
 impl<'a> PromptTemplate<'a> {
     fn new(prefix: &'a str, suffix: &'a str) -> Self {
-        let prefix_chars = prefix.chars().count(); 
+        let prefix_width = prefix.chars().map(|c| c.width_cjk().unwrap_or(1)).sum(); 
 
         Self {
             prefix, 
             suffix, 
-            prefix_chars,
+            prefix_width,
         }
     }
 
     
     fn build(&self, input: &str) -> String {
         let cap = self.prefix.len() + self.suffix.len() + input.len(); 
-        println!("A capacity of {} ", cap); 
 
         let mut buf = String::with_capacity(cap);
         buf.push_str(self.prefix); 
@@ -54,100 +60,316 @@ impl<'a> PromptTemplate<'a> {
     }
 
 
-    fn cursor_col(&self, input: &str) -> usize {
-        self.prefix_chars + input.chars().count() + 1 //
+    fn cursor_col(&self, input: &LineEdit) -> usize {
+        self.prefix_width + input.cursor_width() + 1 //
     }
 }
 
 
 
+//where the prompt is in its history, while browsing it with Up/Down or searching it with Ctrl-R
+struct Recall {
+    kind: HistoryKind, 
+    idx: usize, //the number of entries stands for the text being typed
+    draft: String, 
+    search: Option<String>, 
+    before_search: String,
+}
+
+
+enum SearchStep {
+    Searching, 
+    Submit, 
+    Done,
+    Leave, //the key is not for the search, the prompt handles it as usual
+}
+
+
 pub struct Prompt<'a, W: Write> {
     screen: &'a mut Screen<W>, 
-    buf: &'a mut TextBuffer, 
-    sb: &'a mut Status,
+    wins: &'a mut Windows, 
+    bufs: &'a mut [TextBuffer], 
+    history: &'a mut History, 
+    events: &'a mut Events, 
+    recall: Option<Recall>, 
     empty_is_cancel: bool, 
+    hint: String, //shown after the prompt, e.g. the candidates of an action
+    new_input: Option<String>, 
 }
 
 
 impl<'a, W: Write> Prompt<'a, W> {
 
-    pub fn new<'s: 'a, 'tb: 'a,  'sb: 'a>(
+    pub fn new<'s: 'a, 'w: 'a,  'b: 'a, 'h: 'a, 'e: 'a>(
         screen: &'s mut Screen<W>, 
-        buf: &'tb mut TextBuffer, 
-        sb: &'sb mut Status, 
+        wins: &'w mut Windows, 
+        bufs: &'b mut [TextBuffer], 
+        history: &'h mut History, 
+        events: &'e mut Events, 
+        kind: Option<HistoryKind>, 
         empty_is_cancel: bool,
     ) -> Self {
+        let recall = kind.map(|kind| Recall {
+            kind, 
+            idx: history.entries(kind).len(), 
+            draft: String::new(), 
+            search: None, 
+            before_search: String::new(), 
+        }); 
+
         Self {
             screen, 
-            buf, 
-            sb, 
+            wins, 
+            bufs, 
+            history, 
+            events, 
+            recall, 
             empty_is_cancel,
+            hint: String::new(), 
+            new_input: None, 
         }
     }
 
 
-    fn render_screen(&mut self, input: &str, template: &PromptTemplate) -> Result<()>{
+    pub fn set_hint<S: Into<String>>(&mut self, hint: S) {
+        self.hint = hint.into(); 
+    }
 
-        self.screen.set_info_message(template.build(input)); 
-        self.sb.update_from_but(self.buf); 
-        
+
+    //replace the text typed so far, e.g. by a completion
+    pub fn set_input(&mut self, input: String) {
+        self.new_input = Some(input); 
+    }
+
+
+    //the buffer of the focused window
+    pub fn buf(&self) -> &TextBuffer {
+        &self.bufs[self.wins.focused().buf_idx]
+    }
+
+
+    //for the threads of an action, Event::Progress reaches its on_progress and Event::JobDone its on_done
+    pub fn notifier(&self) -> Notifier {
+        self.events.notifier()
+    }
+
+
+    pub fn set_error_message<S: Into<String>>(&mut self, message: S) {
+        self.screen.set_error_message(message); 
+    }
+
+
+    fn render_screen(&mut self, input: &LineEdit, template: &PromptTemplate) -> Result<()>{
+        let query = self.recall.as_ref().and_then(|r| r.search.as_ref()); 
+        let (message, col) = match query {
+            Some(query) => {
+                let head = format!("(history search) `{}': ", query); 
+                let col = head.chars().map(|c| c.width_cjk().unwrap_or(1)).sum::<usize>() + input.cursor_width() + 1; 
+                (head + input.as_str(), col)
+            }
+            None => {
+                let mut message = template.build(input.as_str()); 
+                message.push_str(&self.hint); 
+                (message, template.cursor_col(input))
+            }
+        }; 
+        self.screen.set_info_message(message); 
+
+        let win = self.wins.focused_mut(); 
+        win.status_bar.update_from_but(&self.bufs[win.buf_idx]); 
+        self.screen.render(self.wins, self.bufs)?; 
 
         let row = self.screen.rows() + 2; 
-        let col = template.cursor_col(input); 
         self.screen.force_set_cursor(row, col)?; 
 
-        self.sb.redraw = false; 
-
         Ok(())
-        
-        // self.sb.update_from_but(self.buf); //still passing the buffer down
-        // let row = self.screen.rows() + 2;
-        // let col = template.cursor_col(input); 
-        // self.screen.force_set_cursor(row, col)?;
-        // self.sb.redraw = false; 
+    }
 
+    //move through the history with Up/Down, returns false when the key is not for the history
+    fn browse_history(&mut self, seq: &InputSeq, buf: &mut LineEdit) -> bool {
+        use KeySeq::*; 
+
+        let recall = match self.recall.as_mut() {
+            Some(recall) => recall, 
+            None => return false, 
+        }; 
+        let entries = self.history.entries(recall.kind); 
+
+        match seq {
+            InputSeq { key: UpKey, .. } | InputSeq { key: Key(b'p'), alt: true, .. } => {
+                if recall.idx > 0 {
+                    if recall.idx == entries.len() {
+                        recall.draft = buf.as_str().to_string(); 
+                    }
+                    recall.idx -= 1; 
+                    buf.set(entries[recall.idx].as_str()); 
+                }
+            }
+            InputSeq { key: DownKey, .. } | InputSeq { key: Key(b'n'), alt: true, .. } => {
+                if recall.idx < entries.len() {
+                    recall.idx += 1; 
+                    buf.set(entries.get(recall.idx).unwrap_or(&recall.draft).as_str()); 
+                }
+            }
+            InputSeq { key: Key(b'r'), ctrl: true, .. } => {
+                recall.search = Some(String::new()); 
+                recall.before_search = buf.as_str().to_string(); 
+            }
+            _ => return false, 
+        }
+
+        true
     }
 
+
+    //incremental search of the history started with Ctrl-R
+    fn search_history(&mut self, seq: &InputSeq, buf: &mut LineEdit) -> SearchStep {
+        use KeySeq::*; 
+
+        let recall = match self.recall.as_mut() {
+            Some(recall) => recall, 
+            None => return SearchStep::Done, 
+        }; 
+        let query = match recall.search.as_mut() {
+            Some(query) => query, 
+            None => return SearchStep::Done, 
+        }; 
+        let len = self.history.entries(recall.kind).len(); 
+
+        let before = match seq {
+            InputSeq { key: Key(b'r'), ctrl: true, .. } => recall.idx, 
+            InputSeq { key: Key(b'h'), ctrl: true, .. } | InputSeq { key: Key(0x7f), .. } => {
+                query.pop(); 
+                len
+            }
+            InputSeq { key: Key(b), ctrl: false, alt: false, .. } if !b.is_ascii_control() => {
+                query.push(*b as char); 
+                len
+            }
+            InputSeq { key: Utf8Key(c), ctrl: false, alt: false, .. } => {
+                query.push(*c); 
+                len
+            }
+            InputSeq { key: Key(b'g'), ctrl: true, .. } | InputSeq { key: Key(0x1b), .. } => {
+                buf.set(recall.before_search.as_str()); 
+                recall.search = None; 
+                return SearchStep::Done; 
+            }
+            InputSeq { key: EnterKey, .. } => {
+                recall.search = None; 
+                return SearchStep::Submit; 
+            }
+            _ => {
+                recall.search = None; 
+                return SearchStep::Leave; 
+            }
+        }; 
+
+        match self.history.search(recall.kind, query, before) {
+            Some(idx) => {
+                recall.idx = idx; 
+                buf.set(self.history.entries(recall.kind)[idx].as_str()); 
+            }
+            None => self.screen.set_error_message(format!("No older history matches `{}'", query)), 
+        }
+
+        SearchStep::Searching
+    }
+
+
+    fn in_history_search(&self) -> bool {
+        self.recall.as_ref().map(|r| r.search.is_some()).unwrap_or(false)
+    }
+
+
     pub fn run<A, S, I>(&mut self, prompt: S, mut input: I) -> Result<PromptResult> 
     where 
         A: Action, 
         S: AsRef<str>,
-        I: Iterator<Item = Result<InputSeq>>,
+        I: EventInput,
     {
 
         let mut action = A::new(self); 
-        let mut buf = String::new(); 
+        let mut buf = LineEdit::new(); 
         let mut cancelled = false; 
 
 
         
         let template = {
-            let mut it = prompt.as_ref().splitn(2, "{}"); 
-            let prefix = it.next().unwrap(); 
-            let suffix = it.next().unwrap(); 
+            let (prefix, suffix) = prompt.as_ref().split_once("{}").unwrap(); 
             PromptTemplate::new(prefix, suffix)
         }; 
 
-        self.render_screen("", &template); 
+        self.render_screen(&buf, &template)?; 
 
 
-        while let Some(seq) = input.next() {
+        loop {
             use KeySeq::*; 
 
-            if self.screen.maybe_resize(&mut input)? {
-                self.screen.set_dirty_start(self.screen.row_off); 
-                self.sb.redraw = true; 
+            let seq = match self.events.next(&mut input)? {
+                Event::Key(seq) => seq, 
+                Event::Paste(text) => InputSeq::new(Paste(text)), 
+                Event::Resize => {
+                    if self.screen.maybe_resize(&mut input)? {
+                        self.screen.redraw_all(); 
+                        self.render_screen(&buf, &template)?; 
+                    }
+                    continue; 
+                }
+                Event::Mouse(_) => continue, 
+                Event::Progress => {
+                    if action.on_progress(self, buf.as_str())? {
+                        self.render_screen(&buf, &template)?; 
+                    }
+                    continue; 
+                }
+                Event::JobDone => {
+                    if action.on_done(self, buf.as_str())? {
+                        self.render_screen(&buf, &template)?; 
+                    }
+                    continue; 
+                }
+                //timers and file checks are for the editor, after the prompt
+                event => {
+                    self.events.defer(event); 
+                    continue; 
+                }
+            }; 
+
+            if self.in_history_search() {
+                match self.search_history(&seq, &mut buf) {
+                    SearchStep::Submit => break, 
+                    SearchStep::Searching | SearchStep::Done => {
+                        self.render_screen(&buf, &template)?; 
+                        continue; 
+                    }
+                    SearchStep::Leave => {}
+                }
+            }
+
+            if self.browse_history(&seq, &mut buf) {
                 self.render_screen(&buf, &template)?; 
-                continue;
+                continue; 
             }
-            
-            let seq = seq?; 
-            let prev_len = buf.len(); 
 
+            let edited = match &seq {
+                InputSeq { key: Key(b'g'), ctrl: true, .. } | InputSeq { key: Key(0x1b), .. } => {
+                    cancelled = true; 
+                    break; 
+                }
+                InputSeq { key: EnterKey, .. } => break, 
+                seq => buf.handle(seq), 
+            }; 
+
+
+            let mut should_render = action.on_seq(self, buf.as_str(), seq)?; 
+            if let Some(input) = self.new_input.take() {
+                buf.set(input); 
+                should_render = true; 
+            }
 
-            let should_render = action.on_seq(self, buf.as_str(), seq)?; 
-            if should_render || prev_len != buf.len() {
-                self.render_screen(&buf, &template);
+            if should_render || edited {
+                self.render_screen(&buf, &template)?;
             }
         }
 
@@ -159,8 +381,15 @@ impl<'a, W: Write> Prompt<'a, W> {
         
         } else {
             self.screen.unset_message(); 
-            self.sb.redraw = true; 
-            PromptResult::Input(buf)
+            self.wins.focused_mut().status_bar.redraw = true; 
+
+            if let Some(recall) = &self.recall {
+                if let Err(err) = self.history.push(recall.kind, buf.as_str()) {
+                    self.screen.set_error_message(format!("Could not save history: {}", err)); 
+                }
+            }
+
+            PromptResult::Input(buf.into_string())
         }; 
 
         //the return type is a result<promptemplate> to be rendered
@@ -193,4 +422,124 @@ pub trait Action: Sized {
     ) -> Result<bool> {
         Ok(false)
     }
-}
\ No newline at end of file
+
+    //a thread of the action sent Event::Progress, returns whether to render again
+    fn on_progress<W: Write>(
+        &mut self, 
+        _prompt: &mut Prompt<'_, W>, 
+        _input: &str, 
+    ) -> Result<bool> {
+        Ok(false)
+    }
+
+    //a thread of the action sent Event::JobDone, nothing more will come from it
+    fn on_done<W: Write>(
+        &mut self, 
+        prompt: &mut Prompt<'_, W>, 
+        input: &str, 
+    ) -> Result<bool> {
+        self.on_progress(prompt, input)
+    }
+}
+
+
+//switch the focused window to one of the open buffers, narrowed by fuzzy matching the input
+pub struct SwitchBuffer {
+    input: String, 
+    matches: Vec<usize>, 
+    selected: usize,
+}
+
+
+impl SwitchBuffer {
+    fn update<W: Write>(&mut self, prompt: &mut Prompt<'_, W>, input: &str) {
+        let current = prompt.wins.focused().buf_idx; 
+        let names = prompt.bufs.iter().map(TextBuffer::filename).enumerate(); 
+
+        self.matches = if input.is_empty() {
+            //the current buffer is the least likely choice, put it last
+            let (mut others, current): (Vec<_>, Vec<_>) = names.map(|(i, _)| i).partition(|i| *i != current); 
+            others.extend(current); 
+            others
+        } else {
+            fuzzy::rank(input, names)
+        }; 
+        self.input = input.to_string(); 
+        self.selected = 0; 
+        self.update_hint(prompt); 
+    }
+
+    fn update_hint<W: Write>(&self, prompt: &mut Prompt<'_, W>) {
+        if self.matches.is_empty() {
+            prompt.set_hint(" [No match]"); 
+            return; 
+        }
+
+        let len = self.matches.len(); 
+        let labels: Vec<String> = (0..len)
+            .map(|i| {
+                let buf = &prompt.bufs[self.matches[(self.selected + i) % len]]; 
+                format!("{}{}", buf.filename(), if buf.modified() { "*" } else { "" })
+            })
+            .collect(); 
+
+        prompt.set_hint(format!(" {{{}}}", labels.join(" | "))); 
+    }
+}
+
+
+impl Action for SwitchBuffer {
+    fn new<W: Write>(prompt: &mut Prompt<'_, W>) -> Self {
+        let mut action = Self { input: String::new(), matches: vec![], selected: 0 }; 
+        action.update(prompt, ""); 
+        action
+    }
+
+    fn on_seq<W: Write>(
+        &mut self, 
+        prompt: &mut Prompt<'_, W>, 
+        input: &str, 
+        seq: InputSeq, 
+    ) -> Result<bool> {
+        use KeySeq::*; 
+
+        let len = self.matches.len(); 
+        match seq {
+            InputSeq { key: TabKey, .. } | InputSeq { key: Key(b'n'), ctrl: true, .. } if len > 0 => {
+                self.selected = (self.selected + 1) % len; 
+                self.update_hint(prompt); 
+            }
+            InputSeq { key: Key(b'p'), ctrl: true, .. } if len > 0 => {
+                self.selected = (self.selected + len - 1) % len; 
+                self.update_hint(prompt); 
+            }
+            _ if input != self.input => self.update(prompt, input), 
+            _ => return Ok(false), 
+        }
+
+        Ok(true)
+    }
+
+    fn on_end<W: Write>(
+        self, 
+        prompt: &mut Prompt<'_, W>, 
+        result: PromptResult,
+    ) -> Result<PromptResult> {
+        prompt.set_hint(""); 
+
+        if let PromptResult::Input(_) = &result {
+            match self.matches.get(self.selected) {
+                Some(&idx) => {
+                    let win = prompt.wins.focused_mut(); 
+                    if win.buf_idx != idx {
+                        win.buf_idx = idx; 
+                        win.reset_scroll(); 
+                    }
+                }
+                None => prompt.set_error_message("No buffer matches"), 
+            }
+        }
+
+        Ok(result)
+    }
+}
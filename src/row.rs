@@ -4,7 +4,7 @@ use unicode_width::UnicodeWidthChar;
 use crate::error::{ Result, Error}; 
 
 
-const TAB_STOP: usize = 8; 
+pub const TAB_STOP: usize = 8; 
 
 
 
@@ -13,16 +13,18 @@ pub struct Row {
     buf: String, 
     render: String, 
     indices: Vec<usize>,
+    tab_width: usize,
 }
 
 
 impl Row {
 
-    pub fn new<S: Into<String>>(line: S) -> Result<Row>{
+    pub fn new<S: Into<String>>(line: S, tab_width: usize) -> Result<Row>{
         let mut row = Row {
             buf: line.into(), 
             render: "".to_string(), 
             indices: Vec::with_capacity(0), 
+            tab_width,
         }; 
 
         row.update_render()?; 
@@ -31,11 +33,21 @@ impl Row {
     }
     
 
-    pub fn empty() -> Row {
+    pub fn empty(tab_width: usize) -> Row {
         Row {
             buf: "".to_string(), 
             render: "".to_string(), 
             indices: Vec::with_capacity(0), //a vector with capacity of zero 
+            tab_width,
+        }
+    }
+
+
+    //tabs are rendered up to the next multiple of the tab width
+    pub fn set_tab_width(&mut self, tab_width: usize) {
+        if self.tab_width != tab_width {
+            self.tab_width = tab_width; 
+            self.update_render().unwrap(); 
         }
     }
 
@@ -160,7 +172,7 @@ impl Row {
                 loop {
                     self.render.push(' '); 
                     index += 1; 
-                    if index % TAB_STOP == 0 {
+                    if index % self.tab_width == 0 {
                         break; 
                     }
                 }
@@ -222,18 +234,29 @@ impl Row {
 
 
 
-    pub fn delete_char(&mut self, at: usize){
-        if at < self.len() {
-            self.buf.remove(self.byte_idx_of(at)); 
-            self.update_render().unwrap(); 
+    //index of the char drawn at render column rx, the length of the row when rx is after its end
+    pub fn cx_from_rx(&self, rx: usize) -> usize {
+        let mut at = 0; 
+        for (cx, ch) in self.buf.chars().enumerate() {
+            at += if ch == '\t' {
+                self.tab_width - (at % self.tab_width)
+            } else {
+                ch.width_cjk().unwrap_or(1)
+            }; 
+
+            if rx < at {
+                return cx; 
+            }
         }
+
+        self.len()
     }
 
 
     pub fn rx_from_cx(&self, cx: usize) -> usize {
         self[..cx].chars().fold(0, |rx, ch| {
             if ch == '\t'  {
-                rx + TAB_STOP - (rx % TAB_STOP) //proceed TAB_STOP  substract 
+                rx + self.tab_width - (rx % self.tab_width) //proceed tab_width  substract 
             
             }else {
                 rx  + ch.width_cjk().unwrap()
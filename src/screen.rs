@@ -2,55 +2,45 @@
 
 use std::cmp;
 use std::io::Write; 
-use std::time::SystemTime; 
+use std::time::{Duration, Instant}; 
 
 use unicode_width::UnicodeWidthChar;
 
-use crate::color::{Color, TerminalColor};
-use crate::row::Row;
-use crate::status::Status;
-use crate::buffer::TextBuffer;
-use crate::input::{ KeySeq, InputSeq};
+use crate::color::TerminalColor;
+use crate::theme::{Background, Group, Theme};
+use crate::status::TextBuffer;
+use crate::window::{Rect, Window, Windows};
+use crate::event::EventInput;
 use crate::error::{ Error, Result}; 
 use crate::message::DrawMessage;
 
 
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION"); 
-pub const HELP: &str = "\
-Ctrl-Q                        : Quit
-Ctrl-S                        : Save to file
-Ctrl-O                        : Open text buffer
-Ctrl-X                        : Next text buffer
-Alt-X                         : Previous text buffer
-Ctrl-P or UP                  : Move cursor up
-Ctrl-N or DOWN                : Move cursor down
-Ctrl-F or RIGHT               : Move cursor right
-Ctrl-B or LEFT                : Move cursor left
-Ctrl-A or Alt-LEFT or HOME    : Move cursor to head of line
-Ctrl-E or Alt-RIGHT or END    : Move cursor to end of line
-Ctrl-[ or Ctrl-V or PAGE DOWN : Next page
-Ctrl-] or Alt-V or PAGE UP    : Previous page
-Alt-F or Ctrl-RIGHT           : Move cursor to next word
-Alt-B or Ctrl-LEFT            : Move cursor to previous word
-Alt-N or Ctrl-DOWN            : Move cursor to next paragraph
-Alt-P or Ctrl-UP              : Move cursor to previous paragraph
-Alt-<                         : Move cursor to top of file
-Alt->                         : Move cursor to bottom of file
-Ctrl-H or BACKSPACE           : Delete character
-Ctrl-D or DELETE              : Delete next character
-Ctrl-W                        : Delete a word
-Ctrl-J                        : Delete until head of line
-Ctrl-K                        : Delete until end of line
-Ctrl-U                        : Undo last change
-Ctrl-R                        : Redo last undo change
-Ctrl-G                        : Search text
-Ctrl-M                        : New line
-Ctrl-L                        : Refresh screen
-Ctrl-?                        : Show this help";
+//slow serial lines take a while to answer where the cursor is
+const CURSOR_REPORT_TIMEOUT: Duration = Duration::from_millis(500); 
 
 
 
+//set with DECSCUSR, None is the shape the user chose in the terminal
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CursorShape {
+    Block, 
+    Bar, 
+    Underline, 
+}
+
+
+impl CursorShape {
+    fn sequence(self) -> &'static [u8] {
+        match self {
+            CursorShape::Block => b"\x1b[2 q", 
+            CursorShape::Bar => b"\x1b[6 q", 
+            CursorShape::Underline => b"\x1b[4 q", 
+        }
+    }
+}
+
 
 #[derive(PartialEq)]
 enum StatusMessageKind {
@@ -64,7 +54,7 @@ enum StatusMessageKind {
 
 struct MessageState {
     text: String, 
-    timestamp: SystemTime, 
+    timestamp: Instant, 
     kind: StatusMessageKind,
 }
 
@@ -72,7 +62,7 @@ impl MessageState {
     fn new<S: Into<String>>(message: S, kind: StatusMessageKind) -> MessageState {
         MessageState {
             text: message.into(), //convert the type into a string
-            timestamp: SystemTime::now(), 
+            timestamp: Instant::now(), 
             kind,
         }
     }
@@ -81,16 +71,18 @@ impl MessageState {
 
 pub struct Screen<W: Write> {
     output: W, 
-    rx: usize,
     no_cols: usize, 
     no_rows: usize, 
     pub cursor_moved: bool, 
-    pub row_off: usize,
-    pub col_off: usize, 
     terminal_color:TerminalColor,
+    theme: Theme,
+    background: Background,
+    kitty_keyboard: bool,
+    cursor_shape: Option<CursorShape>,
     message: Option<MessageState>, 
-    dirty_start: Option<usize>,
+    redraw_all: bool,
     draw_message: DrawMessage,
+    message_timeout: u64,
 }
 
 
@@ -98,7 +90,7 @@ pub struct Screen<W: Write> {
 impl <W: Write> Screen<W> {
     pub fn new<I>(size: Option<(usize, usize)>, input: I, mut output: W) -> Result<Self>
     where 
-        I: Iterator<Item = Result<InputSeq>>,
+        I: EventInput,
     {
         let (width, height) = if let Some(x) = size {
             x
@@ -113,47 +105,52 @@ impl <W: Write> Screen<W> {
             return Err(Error::TooSmallWindow(width, height));
         }
 
-        output.write(b"\x1b[?1049h")?;
+        enter_terminal_modes(&mut output)?;
 
+        let terminal_color = TerminalColor::getting_from_env();
         Ok(Self {
             output, 
             no_cols: width, 
-            row_off: 0, 
-            col_off: 0,
-            rx: 0,
-            no_rows: height,
+            no_rows: height.saturating_sub(2),
             cursor_moved: true,
-            terminal_color: TerminalColor::getting_from_env(),
+            terminal_color,
+            theme: Theme::builtin(terminal_color),
+            background: Background::from_env().unwrap_or(Background::Dark),
+            kitty_keyboard: false,
+            cursor_shape: None,
             draw_message: DrawMessage::Open, 
-            dirty_start: Some(0),
-            message: Some(MessageState::new("Ctrl-? for Help", StatusMessageKind::Info))
+            redraw_all: true,
+            message_timeout: 5,
+            message: Some(MessageState::new("Ctrl-? for Help, Alt-X for commands", StatusMessageKind::Info))
         
         })
     }
 
 
     fn write_flush(&mut self, bytes: &[u8]) -> Result<()> {
-        self.output.write(bytes)?; 
+        self.output.write_all(bytes)?; 
         self.output.flush()?; 
         Ok(())
     }
 
 
-    fn draw_status_bar<B: Write>(&self, mut buf: B, status_bar: &Status) -> Result<()>{
-        write!(buf, "\x1b[{}H", self.rows() + 1)?;
+    fn draw_status_bar<B: Write>(&self, mut buf: B, win: &Window, focused: bool) -> Result<()>{
+        let status_bar = &win.status_bar; 
+        let width = win.cols(); 
+        write!(buf, "\x1b[{};{}H", win.rect.top + win.rows() + 1, win.rect.left + 1)?;
 
-        buf.write(self.terminal_color.sequence(Color::Invert)); 
+        buf.write_all(self.theme.sequence(if focused { Group::StatusBar } else { Group::StatusBarInactive }))?; 
 
 
         let left = status_bar.left(); 
-        let left = &left[..cmp::min(left.len(), self.no_cols)];
+        let left = truncate_width(&left, width);
         //let hanle multi-byte chars
-        buf.write(left.as_bytes())?;
+        buf.write_all(left.as_bytes())?;
 
 
-        let rest_len = self.no_cols - left.len(); 
+        let rest_len = width - display_width(left); 
         if rest_len == 0 {
-            buf.write(self.terminal_color.sequence(Color::Reset))?; 
+            buf.write_all(self.theme.sequence(Group::Normal))?; 
             return Ok(()); 
         }
 
@@ -161,150 +158,293 @@ impl <W: Write> Screen<W> {
         let right = status_bar.right(); 
         if right.len() > rest_len {
             for _ in 0..rest_len {
-                buf.write(b" ")?;
+                buf.write_all(b" ")?;
             }
 
-            buf.write(self.terminal_color.sequence(Color::Reset)); 
+            buf.write_all(self.theme.sequence(Group::Normal))?; 
             return Ok(())
         }
 
         
         for _ in 0..rest_len - right.len() {
-            buf.write(b" ")?; 
+            buf.write_all(b" ")?; 
         }
 
-        buf.write(right.as_bytes())?;
-        buf.write(self.terminal_color.sequence(Color::Reset))?; 
+        buf.write_all(right.as_bytes())?;
+        buf.write_all(self.theme.sequence(Group::Normal))?; 
         Ok(())
     }
 
 
+    //move to line y of the window and draw its line number, None for a continued or non-text line
+    fn start_line<B: Write>(&self, mut buf: B, win: &Window, y: usize, line_no: Option<usize>) -> Result<()> {
+        write!(buf, "\x1b[{};{}H", win.rect.top + y + 1, win.rect.left + 1)?;
+
+        if win.gutter() > 0 {
+            buf.write_all(self.theme.sequence(Group::Gutter))?;
+            match line_no {
+                Some(n) => write!(buf, "{:>1$} ", n, win.gutter() - 1)?,
+                None => write!(buf, "{:1$}", "", win.gutter())?,
+            }
+            buf.write_all(self.theme.sequence(Group::Normal))?;
+        }
+
+        Ok(())
+    }
+
+    //clear the rest of the line after col text columns
+    fn end_line<B: Write>(&self, mut buf: B, win: &Window, col: usize) -> Result<()> {
+        //ensure to the reset color sequence
+        buf.write_all(self.theme.sequence(Group::Normal))?; 
+
+        if win.rect.left + win.cols() == self.no_cols {
+            buf.write_all(b"\x1b[K")?;
+        }else {
+            for _ in col..win.text_cols() {
+                buf.write_all(b" ")?; 
+            }
+        }
+
+        Ok(())
+    }
+
     fn draw_rows<B: Write>(
         &self, 
         mut buf: B,
+        win: &Window, 
         dirty_start: usize, 
-        row: &[Row]
+        text: &TextBuffer, 
     ) -> Result<()> {
+        let row = text.rows(); 
         let row_len = row.len(); 
+        let width = win.text_cols(); 
 
-        buf.write(self.terminal_color.sequence(Color::Reset))?; 
+        buf.write_all(self.theme.sequence(Group::Normal))?; 
 
-        for y in 0..self.rows() {
-            let file_row = y + self.row_off; 
+        let mut y = 0; 
+        let mut file_row = win.row_off; 
 
+        while y < win.rows() {
             if file_row < dirty_start {
+                y += row.get(file_row).map(|r| win.row_height(r)).unwrap_or(1); 
+                file_row += 1; 
                 continue;
             }
 
-
-            write!(buf, "\x1b[{}H", y + 1)?;
-
-
             if file_row >= row_len {
-                buf.write(self.terminal_color.sequence(Color::NonText))?;
-                buf.write(b"~")?;
-            
-            }else {
-                let row = &row[file_row]; 
-
-
-                let mut col = 0; 
-                let mut prev_color = Color::Reset; 
+                self.start_line(&mut buf, win, y, None)?; 
+                buf.write_all(self.theme.sequence(Group::NonText))?;
+                buf.write_all(b"~")?;
+                self.end_line(&mut buf, win, 1)?; 
+                y += 1; 
+                file_row += 1; 
+                continue;
+            }
 
-                for c in row.render_text().chars(){
-                    col += c.width_cjk().unwrap_or(1); 
-                    if col <= self.col_off{
-                        continue;
-                    
-                    }else if col > self.no_cols + self.col_off {
-                        break;
+            //render columns of the row which are selected
+            let selected = text.selection().and_then(|((sx, sy), (ex, ey))| {
+                let r = &row[file_row]; 
+                if file_row < sy || file_row > ey {
+                    return None; 
+                }
+                let start = if file_row == sy { r.rx_from_cx(sx) } else { 0 }; 
+                let end = if file_row == ey { r.rx_from_cx(ex) } else { usize::MAX }; 
+                Some((start, end))
+            }); 
+
+            //render columns of the matches of the last search
+            let matches = text.search().map(|query| {
+                let r = &row[file_row]; 
+                r.buffer()
+                    .match_indices(query)
+                    .map(|(b, m)| (r.rx_from_cx(r.char_idx_of(b)), r.rx_from_cx(r.char_idx_of(b + m.len()))))
+                    .collect::<Vec<_>>()
+            }).unwrap_or_default(); 
+
+            self.start_line(&mut buf, win, y, Some(file_row + 1))?; 
+            let mut col = 0; 
+            let mut at = 0; 
+            let mut group = Group::Normal; 
+
+            for c in row[file_row].render_text().chars(){
+                let w = c.width_cjk().unwrap_or(1); 
+                let rx = at; 
+                at += w; 
+
+                if win.wraps() {
+                    //continue on the next screen line when the char does not fit
+                    if col + w > width && col > 0 {
+                        self.end_line(&mut buf, win, col)?; 
+                        y += 1; 
+                        if y >= win.rows() {
+                            break; 
+                        }
+                        self.start_line(&mut buf, win, y, None)?; 
+                        col = 0; 
+                        group = Group::Normal; 
                     }
+                
+                }else if col + w <= win.col_off {
+                    col += w; 
+                    continue;
+                
+                }else if col + w > width + win.col_off {
+                    break;
+                }
 
+                //the selection is drawn over the matches
+                let in_selection = selected.is_some_and(|(start, end)| start <= rx && rx < end); 
+                let in_match = matches.iter().any(|&(start, end)| start <= rx && rx < end); 
+                let next = match (in_selection, in_match) {
+                    (true, _) => Group::Selection, 
+                    (false, true) => Group::SearchMatch, 
+                    (false, false) => Group::Normal, 
+                }; 
+                if next != group {
+                    buf.write_all(self.theme.sequence(next))?; 
+                    group = next; 
+                }
 
-                    // let color = hl.color();
-                    // if color != prev_color {
-                    //     if prev_color.has_bg_color() {
-                    //         buf.write(self.term_color.sequence(Color::Reset))?;
-                    //     }
-                    //     buf.write(self.term_color.sequence(color))?;
-                    //     prev_color = color;
-                    // }
+                col += w; 
+                write!(buf, "{}", c)?;
+            }
 
-                    write!(buf, "{}", c)?;
+            if win.wraps() {
+                if col >= width && y < win.rows() {
+                    //the cursor after a full line goes on a line of its own
+                    self.end_line(&mut buf, win, col)?; 
+                    y += 1; 
+                    if y < win.rows() {
+                        self.start_line(&mut buf, win, y, None)?; 
+                    }
+                    col = 0; 
                 }
+            }else {
+                col = col.saturating_sub(win.col_off); 
             }
 
+            if y < win.rows() {
+                self.end_line(&mut buf, win, col)?; 
+            }
 
-            //ensure to the reset color sequence
-            buf.write(self.terminal_color.sequence(Color::Reset))?; 
+            y += 1; 
+            file_row += 1; 
+        }
 
 
-            buf.write(b"\x1b[K")?;
-        }
+        Ok(())
+    }
 
+    fn draw_separators<B: Write>(&self, mut buf: B, seps: &[Rect]) -> Result<()> {
+        buf.write_all(self.theme.sequence(Group::Separator))?; 
 
+        for sep in seps {
+            for y in sep.top..sep.top + sep.height {
+                write!(buf, "\x1b[{};{}H ", y + 1, sep.left + 1)?; 
+            }
+        }
+
+        buf.write_all(self.theme.sequence(Group::Normal))?; 
         Ok(())
     }
 
     fn update_message_bar(&mut self) -> Result<()>{
-        if let Some(m) = &self.message {
-            if SystemTime::now().duration_since(m.timestamp)?.as_secs() > 5 {
+        if let Some(at) = self.message_expiry() {
+            if Instant::now() >= at {
                 self.unset_message(); 
             }
         }
 
+        Ok(())
+    }
 
-        if self.draw_message == DrawMessage::Close {
-            self.set_dirty_start(self.no_rows); 
-        }
+    //when the message in the message bar is cleared
+    pub fn message_expiry(&self) -> Option<Instant> {
+        self.message.as_ref().map(|m| m.timestamp + Duration::from_secs(self.message_timeout))
+    }
 
-        Ok(())
+    //area shared by the windows, the message bar takes the last line when shown
+    fn windows_area(&self) -> Rect {
+        Rect {
+            top: 0, 
+            left: 0, 
+            width: self.no_cols, 
+            height: self.rows() + 1,
+        }
     }
 
     pub fn render(
         &mut self,
-        buf: &TextBuffer, 
-        status_bar: &Status,
+        wins: &mut Windows, 
+        bufs: &[TextBuffer], 
     ) -> Result<()> {
-        self.do_scroll(buf.rows(), buf.cursor());
         self.update_message_bar()?;
-        self.redraw(buf, status_bar)?; 
+
+        if wins.arrange(self.windows_area()) {
+            self.redraw_all = true; 
+        }
+
+        for win in wins.iter_mut() {
+            let buf = &bufs[win.buf_idx]; 
+            win.configure(buf.settings(), buf.rows().len()); 
+        }
+
+        if self.redraw_all {
+            wins.redraw_all(); 
+        }
+
+        let buf = &bufs[wins.focused().buf_idx]; 
+        wins.focused_mut().do_scroll(buf.rows(), buf.cursor());
+
+        self.redraw(wins, bufs)?; 
         self.after_render(); 
+
+        for win in wins.iter_mut() {
+            win.after_render(); 
+        }
+
         Ok(())
     }
 
 
     pub fn redraw(
         &mut self, 
-        text_buf: &TextBuffer,
-        status_bar: &Status
+        wins: &Windows,
+        bufs: &[TextBuffer],
     ) -> Result<()> {
-        let cursor_row = text_buf.cy() - self.row_off + 1; 
-        let cursor_col = self.rx - self.col_off + 1; 
+        let focused = wins.focused(); 
+        let (y, x) = focused.screen_cursor(); 
+        let cursor_row = focused.rect.top + y + 1; 
+        let cursor_col = focused.rect.left + x + 1; 
         let draw_message = self.draw_message; 
 
-        if self.dirty_start.is_none() 
-            && !status_bar.redraw && draw_message == DrawMessage::DoNothing {
+        if !self.redraw_all 
+            && draw_message == DrawMessage::DoNothing 
+            && wins.iter().all(|w| w.dirty_start().is_none() && !w.status_bar.redraw) {
                 if self.cursor_moved{
                     write!(self.output, "\x1b[{};{}H", cursor_row, cursor_col)?;
                     self.output.flush()?;
                 }
+
+                return Ok(()); 
         }
 
 
         self.write_flush(b"\x1b[?25l")?;
 
         let mut buf = Vec::with_capacity((self.rows() + 2) * self.no_cols); 
-        if let Some(s) = self.dirty_start {
-            self.draw_rows(&mut buf, s, text_buf.rows())?; 
+        for (idx, win) in wins.iter().enumerate() {
+            if let Some(s) = win.dirty_start() {
+                self.draw_rows(&mut buf, win, s, &bufs[win.buf_idx])?; 
+            }
 
+            if win.status_bar.redraw {
+                self.draw_status_bar(&mut buf, win, idx == wins.focus())?;
+            }
         }
 
-
-        //when the message bar opens/closes, position of status
-
-        if status_bar.redraw || draw_message == DrawMessage::Open || self.draw_message == DrawMessage::Close {
-            self.draw_status_bar(&mut buf, status_bar)?;
+        if self.redraw_all {
+            self.draw_separators(&mut buf, wins.separators())?; 
         }
 
 
@@ -319,7 +459,7 @@ impl <W: Write> Screen<W> {
 
         
         //remove the cursor -h
-        buf.write(b"\x1b[?25h")?;
+        buf.write_all(b"\x1b[?25h")?;
 
         self.write_flush(&buf)?;
 
@@ -328,21 +468,18 @@ impl <W: Write> Screen<W> {
     }
 
     fn trim_line<S: AsRef<str>>(&self, line: &S) -> String {
-        let line = line.as_ref(); 
-        if line.len() <= self.col_off {
-            return "".to_string(); 
-        }
-
-        line.chars().skip(self.col_off).take(self.no_cols).collect()
+        line.as_ref().chars().take(self.no_cols).collect()
     }
 
-    pub fn render_welcome(&mut self, status_bar: &Status) -> Result<()> {
+    pub fn render_welcome(&mut self, wins: &mut Windows) -> Result<()> {
+        wins.arrange(self.windows_area()); 
+
         self.write_flush(b"\x1b[?25l")?; // Hide cursor
 
 
 
         let mut buf = Vec::with_capacity(self.rows()); 
-        buf.write(self.terminal_color.sequence(Color::Reset))?;
+        buf.write_all(self.theme.sequence(Group::Normal))?;
 
 
         for y in 0..self.rows() {
@@ -356,62 +493,67 @@ impl <W: Write> Screen<W> {
                     let welcome = self.trim_line(&msg_buf);
                     let padding = (self.no_cols - welcome.len()) / 2;
                     if padding > 0 {
-                        buf.write(self.terminal_color.sequence(Color::NonText))?;
-                        buf.write(b"~")?;
-                        buf.write(self.terminal_color.sequence(Color::Reset))?;
+                        buf.write_all(self.theme.sequence(Group::NonText))?;
+                        buf.write_all(b"~")?;
+                        buf.write_all(self.theme.sequence(Group::Normal))?;
                         for _ in 0..padding - 1 {
-                            buf.write(b" ")?;
+                            buf.write_all(b" ")?;
                         }
                     }
-                    buf.write(welcome.as_bytes())?;
+                    buf.write_all(welcome.as_bytes())?;
                 } else {
-                    buf.write(self.terminal_color.sequence(Color::NonText))?;
-                    buf.write(b"~")?;
+                    buf.write_all(self.theme.sequence(Group::NonText))?;
+                    buf.write_all(b"~")?;
                 } 
         }
 
-        buf.write(self.terminal_color.sequence(Color::Reset))?; 
-        self.draw_status_bar(&mut buf, status_bar)?; 
+        buf.write_all(self.theme.sequence(Group::Normal))?; 
+        self.draw_status_bar(&mut buf, wins.focused(), true)?; 
         
         if let Some(message) = &self.message {
             self.draw_message_bar(&mut buf, message)?; 
         }
 
         write!(buf, "\x1b[H")?; // Set cursor to left-top
-        buf.write(b"\x1b[?25h")?; // Show cursor
+        buf.write_all(b"\x1b[?25h")?; // Show cursor
         self.write_flush(&buf)?; 
         
 
         self.after_render();
+        for win in wins.iter_mut() {
+            win.after_render(); 
+        }
+
         Ok(())
     }
 
     
     fn draw_message_bar<B:Write>(&self, mut buf: B, message: &MessageState) -> Result<()>{
-        let text = &message.text[..cmp::min(message.text.len(), self.no_cols)]; 
+        let text = truncate_width(&message.text, self.no_cols); 
 
 
-        write!(buf, "\x1b[{}]H", self.no_rows + 2)?;
+        write!(buf, "\x1b[{}H", self.no_rows + 2)?;
 
         if message.kind == StatusMessageKind::Error {
-            buf.write(self.terminal_color.sequence(Color::RedBg))?; 
+            buf.write_all(self.theme.sequence(Group::ErrorMessage))?; 
         }
 
 
-        buf.write(text.as_bytes())?; 
+        buf.write_all(text.as_bytes())?; 
 
         if message.kind != StatusMessageKind::Info {
-            buf.write(self.terminal_color.sequence(Color::Reset))?; 
+            buf.write_all(self.theme.sequence(Group::Normal))?; 
         }
 
-        buf.write(b"\x1b[K")?;
+        buf.write_all(b"\x1b[K")?;
         Ok(())
     }
 
 
+    //called on a KeySeq::Resize, true when the size really changed and everything must be redrawn
     pub fn maybe_resize<I>(&mut self, input: I) -> Result<bool>
     where 
-        I: Iterator<Item = Result<InputSeq>>, 
+        I: EventInput, 
     {
 
         //do i want the user to be notified when window is shrinking?
@@ -421,10 +563,14 @@ impl <W: Write> Screen<W> {
             return Err(Error::TooSmallWindow(w, h)); 
         }
 
+        if (w, h.saturating_sub(2)) == (self.no_cols, self.no_rows) {
+            return Ok(false); 
+        }
+
 
         self.no_rows = h.saturating_sub(2); 
         self.no_cols = w; 
-        self.dirty_start = Some(0); 
+        self.redraw_all = true; 
         
         Ok(true)
     }
@@ -432,95 +578,105 @@ impl <W: Write> Screen<W> {
     fn after_render(&mut self) {
         //clear state 
 
-        self.dirty_start = None;
+        self.redraw_all = false;
         self.cursor_moved = false; 
         self.draw_message = DrawMessage::DoNothing;
         //self.draw
     }
 
-    fn do_scroll(&mut self, rows: &[Row], (cx, cy): (usize, usize)) {
-        let prev_rowoff = self.row_off; 
-        let prev_coloff = self.col_off; 
-
-        //calculate the x and y coordinate
-
-        if cy < rows.len() {
-            // self.rx = rows[cy].rx
-            self.rx = rows[cy].rx_from_cx(cx); 
+    pub fn rows(&self) -> usize {
+        if self.message.is_some() {
+            self.no_rows
         
         }else {
-            self.rx = 0; 
-        }
-
-        if cy < self.row_off {
-            //scroll up when cursor
-            self.row_off = cy; 
+            self.no_rows + 1
         }
+    }
 
-        if cy >= self.row_off + self.rows() {
-            self.row_off = cy - self.rows() + 1;
-        }
 
-        if self.rx < self.col_off {
-            self.col_off = self.rx;
-        }
 
+    pub fn set_message_timeout(&mut self, secs: u64) {
+        self.message_timeout = secs; 
+    }
 
-        if self.rx >= self.col_off + self.no_rows {
-            self.col_off = self.next_coloff(self.rx - self.no_cols + 1, &rows[cy]); 
-        }
-
+    //repaint every window on the next render, e.g. after the help screen covered them
+    pub fn redraw_all(&mut self) {
+        self.redraw_all = true; 
+    }
 
-        if prev_rowoff != self.row_off || prev_coloff != self.col_off {
-            self.set_dirty_start(self.row_off); 
-        }
+    pub fn terminal_color(&self) -> TerminalColor {
+        self.terminal_color
+    }
 
+    pub fn background(&self) -> Background {
+        self.background
     }
 
-    fn next_coloff(&self, stop: usize, row: &Row) -> usize {
-        let mut col_off = 0; 
+    pub fn set_background(&mut self, background: Background) {
+        self.background = background; 
+    }
 
-        for x in row.render_text().chars() {
-            col_off += x.width_cjk().unwrap_or(1); 
-            if col_off >= stop {
-                //add next base on the previous screen_size
-                break; 
-            }
+    //the flags are kept per screen, so they are pushed once the alternate screen is shown and
+    //popped before leaving it
+    pub fn enable_kitty_keyboard(&mut self) -> Result<()> {
+        if !self.kitty_keyboard {
+            self.write_flush(b"\x1b[>1u")?; 
+            self.kitty_keyboard = true; 
         }
-
-        col_off
+        Ok(())
     }
 
-
-    pub fn rows(&self) -> usize {
-        if self.message.is_some() {
-            self.no_cols
-        
-        }else {
-            self.no_rows + 1
+    //the shape is only written when it changes
+    pub fn set_cursor_shape(&mut self, shape: CursorShape) -> Result<()> {
+        if self.cursor_shape != Some(shape) {
+            self.write_flush(shape.sequence())?; 
+            self.cursor_shape = Some(shape); 
         }
+        Ok(())
     }
 
-
-
-    pub fn cols(&self) -> usize {
-        self.no_cols
+    //give the terminal back to the shell while the editor is suspended
+    pub fn suspend(&mut self) -> Result<()> {
+        self.leave_terminal_modes()
     }
 
-    pub fn set_dirty_start(&mut self, start: usize) {
-        if let Some(x) = self.dirty_start {
-            if x < start {
-                return 
-            }
+    //take the terminal again after a suspend, everything is drawn from scratch
+    pub fn resume(&mut self) -> Result<()> {
+        enter_terminal_modes(&mut self.output)?; 
+        if self.kitty_keyboard {
+            self.output.write_all(b"\x1b[>1u")?; 
+        }
+        if let Some(shape) = self.cursor_shape {
+            self.output.write_all(shape.sequence())?; 
         }
+        self.output.flush()?; 
+        self.redraw_all = true; 
+        Ok(())
+    }
 
-        self.dirty_start = Some(start); 
+    fn leave_terminal_modes(&mut self) -> Result<()> {
+        if self.kitty_keyboard {
+            self.output.write_all(b"\x1b[<u")?; 
+        }
+        if self.cursor_shape.is_some() {
+            self.output.write_all(b"\x1b[0 q")?; 
+        }
+        self.output.write_all(b"\x1b[?1006l\x1b[?1002l")?; 
+        self.output.write_all(b"\x1b[?2004l")?; 
+        self.output.write_all(b"\x1b[?25h")?; 
+        self.output.write_all(b"\x1b[?1049l")?; 
+        self.output.flush()?; 
+        Ok(())
     }
 
+    pub fn theme(&self) -> &Theme {
+        &self.theme
+    }
 
-    //get they message text
-    pub fn message_text(&self) -> &str{ 
-        self.message.as_ref().map(|x| x.text.as_str()).unwrap_or("")
+    //every line is drawn with the colors of the new theme
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme; 
+        self.redraw_all(); 
     }
 
 
@@ -561,8 +717,44 @@ impl <W: Write> Screen<W> {
         Ok(())
     }
 
-    pub fn render_help(&mut self) -> Result<()>{
-        let help: Vec<_> = HELP
+    //full screen list with a title line, e.g. the open buffers. selected line is highlighted
+    pub fn render_list<S: AsRef<str>>(&mut self, title: &str, lines: &[S], selected: usize) -> Result<()> {
+        let height = self.rows() + 1; 
+        let top = (selected + 2).saturating_sub(height); 
+
+        let mut buf = Vec::with_capacity(height * self.no_cols); 
+        buf.write_all(b"\x1b[?25l")?; 
+        buf.write_all(self.theme.sequence(Group::Normal))?; 
+
+        write!(buf, "\x1b[1H")?; 
+        buf.write_all(self.theme.sequence(Group::Title))?; 
+        buf.write_all(truncate_width(title, self.no_cols).as_bytes())?; 
+        buf.write_all(self.theme.sequence(Group::Normal))?; 
+        buf.write_all(b"\x1b[K")?; 
+
+        for y in 1..height {
+            write!(buf, "\x1b[{}H", y + 1)?; 
+
+            if let Some(line) = lines.get(top + y - 1) {
+                if top + y - 1 == selected {
+                    buf.write_all(self.theme.sequence(Group::Selection))?; 
+                }
+                buf.write_all(truncate_width(line.as_ref(), self.no_cols).as_bytes())?; 
+                buf.write_all(self.theme.sequence(Group::Normal))?; 
+            }
+
+            buf.write_all(b"\x1b[K")?; 
+        }
+
+        if let Some(message) = &self.message {
+            self.draw_message_bar(&mut buf, message)?; 
+        }
+
+        self.write_flush(&buf)
+    }
+
+    pub fn render_help(&mut self, help: &str) -> Result<()>{
+        let help: Vec<_> = help
         .split('\n')
         .skip_while(|s| !s.contains(':'))
         .map(str::trim_start)
@@ -585,7 +777,7 @@ impl <W: Write> Screen<W> {
 
     for y in 0..vertical_margin {
         write!(buf, "\x1b[{}H", y + 1)?;
-        buf.write(b"\x1b[K")?;
+        buf.write_all(b"\x1b[K")?;
     }
 
     let left_pad = " ".repeat(left_margin);
@@ -593,25 +785,25 @@ impl <W: Write> Screen<W> {
     for y in vertical_margin..help_height {
         let idx = y - vertical_margin;
         write!(buf, "\x1b[{}H", y + 1)?;
-        buf.write(left_pad.as_bytes())?;
+        buf.write_all(left_pad.as_bytes())?;
 
         let help = &help[idx][..cmp::min(help[idx].len(), self.no_cols)];
-        buf.write(self.terminal_color.sequence(Color::Cyan))?;
-        let mut cols = help.split(':');
+        buf.write_all(self.theme.sequence(Group::Title))?;
+        let mut cols = help.splitn(2, ':');
         if let Some(col) = cols.next() {
-            buf.write(col.as_bytes())?;
+            buf.write_all(col.as_bytes())?;
         }
-        buf.write(self.terminal_color.sequence(Color::Reset))?;
+        buf.write_all(self.theme.sequence(Group::Normal))?;
         if let Some(col) = cols.next() {
             write!(buf, ":{}", col)?;
         }
 
-        buf.write(b"\x1b[K")?;
+        buf.write_all(b"\x1b[K")?;
     }
 
     for y in help_height..rows {
         write!(buf, "\x1b[{}H", y + 1)?;
-        buf.write(b"\x1b[K")?;
+        buf.write_all(b"\x1b[K")?;
     }
 
     self.write_flush(&buf)
@@ -637,23 +829,43 @@ impl <W: Write> Screen<W> {
 
 
 
-fn get_window_size<I, W>(input: I, mut output: W) -> Result<(usize, usize)>
+//give the terminal back the way it was before the editor started
+impl<W: Write> Drop for Screen<W> {
+    fn drop(&mut self) {
+        let _ = self.leave_terminal_modes(); 
+    }
+}
+
+
+//the alternate screen, bracketed paste and mouse reports, left by Screen::leave_terminal_modes
+fn enter_terminal_modes<W: Write>(mut output: W) -> Result<()> {
+    output.write_all(b"\x1b[?1049h")?;
+    //pasted text comes between ESC [ 200 ~ and ESC [ 201 ~
+    output.write_all(b"\x1b[?2004h")?;
+    //SGR mouse reports of clicks, drags and the wheel
+    output.write_all(b"\x1b[?1002h\x1b[?1006h")?;
+    Ok(())
+}
+
+
+
+
+//width and height of the terminal
+fn get_window_size<I, W>(mut input: I, mut output: W) -> Result<(usize, usize)>
     where
-        I: Iterator<Item = Result<InputSeq>>, 
+        I: EventInput, 
         W:Write
 {
     if let Some(x) =  term_size::dimensions_stdout() {
         return Ok(x); 
     }
 
-
-    for tita in input {
-        if let KeySeq::Cursor(height, width) = tita?.key {
-            return Ok((height, width))
-        }
+    //serial consoles and some containers have no size, the terminal still knows where its
+    //cursor is
+    match input.query_window_size(&mut output, CURSOR_REPORT_TIMEOUT)? {
+        Some(size) => Ok(size), 
+        None => Err(Error::UnknownWindowSize), //when the size is unknown by your terminal
     }
-
-    Err(Error::UnknownWindowSize) //when the size is unknown by your terminal
 }
 
 
@@ -663,3 +875,24 @@ fn get_window_size<I, W>(input: I, mut output: W) -> Result<(usize, usize)>
 fn check_window(width: usize, height: usize) -> bool {
     width < 1 || height < 3
 }
+
+
+//width of the text on the terminal, wide characters take two columns
+fn display_width(text: &str) -> usize {
+    text.chars().map(|c| c.width_cjk().unwrap_or(1)).sum()
+}
+
+
+//longest head of text which fits in width columns
+fn truncate_width(text: &str, width: usize) -> &str {
+    let mut col = 0; 
+
+    for (idx, c) in text.char_indices() {
+        col += c.width_cjk().unwrap_or(1); 
+        if col > width {
+            return &text[..idx]; 
+        }
+    }
+
+    text
+}
@@ -2,15 +2,17 @@
 
 
 use std::cmp;
-use std::fs::File;
+use std::fs::{self, File};
+use std::time::SystemTime;
 use std::path::{PathBuf, Path};
-use std::io::{self, Write, BufRead};
+use std::io::{self, Write};
 //use std::slice;
 
 
-use crate::edit_diff::{EditDiff, UndoRedo};
-use crate::language::{Language, Indent}; 
-use crate::row::Row;
+use crate::edit_diff::{EditDiff, EditHistory, UndoRedo};
+use crate::language::Language; 
+use crate::row::{Row, TAB_STOP};
+use crate::config::Settings;
 use crate::setter; 
 use crate::error::Result;
 
@@ -23,10 +25,20 @@ pub struct Status {
     pub redraw: bool, 
     pub line_pos: (usize, usize), 
     pub buf_pos: (usize, usize),
+    pub mode: Option<&'static str>, //the vi mode of the focused window
 }
 
 
 
+//how the text between the mark and the cursor is selected
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SelectionKind {
+    Exclusive, //up to the cursor, as the mouse selects
+    Inclusive, //with the char under the cursor, as vi visual mode
+    Lines,     //whole lines, as vi visual line mode
+}
+
+
 #[derive(Copy, Clone)]
 pub enum CursorDir {
     Left, 
@@ -70,10 +82,16 @@ pub struct TextBuffer {
     row:Vec<Row>,
     undo_count: i32, 
     modified: bool, 
+    history: EditHistory, 
     lang: Language, 
     inserted_undo: bool, 
     dirty_start: Option<usize>,
-    file: Option<FilePath>
+    file: Option<FilePath>,
+    settings: Settings,
+    mark: Option<(usize, usize)>, //the other end of the selection, the cursor is the first one
+    selection_kind: SelectionKind, 
+    mtime: Option<SystemTime>, //of the file when it was last read or written by us
+    search: Option<String>, //the last query searched for, its matches are highlighted
 }
 
 
@@ -89,14 +107,21 @@ impl TextBuffer {
             file: None, 
             undo_count: 0, 
             modified: false, 
+            history: EditHistory::default(), 
             lang: Language::Plain, 
             dirty_start:Some(0), 
             inserted_undo: false,
-            row:vec![Row::empty()],
+            row:vec![Row::empty(TAB_STOP)],
+            settings: Settings::default(),
+            mark: None,
+            selection_kind: SelectionKind::Exclusive,
+            mtime: None,
+            search: None,
         }
     }
 
-    pub fn open<X: AsRef<Path>>(path: X) -> Result<Self>{
+    //read the file in the charset it is written in, as save writes it back
+    pub fn open<X: AsRef<Path>>(path: X, charset: &str) -> Result<Self>{
         let path = path.as_ref(); 
         let file =  Some(FilePath::from(path)); 
 
@@ -111,9 +136,11 @@ impl TextBuffer {
         }
 
 
-        let row = io::BufReader::new(File::open(path)?)
+        let mtime = fs::metadata(path).and_then(|m| m.modified()).ok(); 
+        let text = decode(&fs::read(path)?, charset)?; 
+        let row = text
             .lines()
-            .map(|x| Row::new(x?))
+            .map(|x| Row::new(x, TAB_STOP))
             .collect::<Result<_>>()?; 
 
 
@@ -123,10 +150,16 @@ impl TextBuffer {
             file, 
             undo_count: 0, 
             modified: false,
+            history: EditHistory::default(), 
             lang: Language::detect(path), 
             inserted_undo: false, 
             dirty_start: Some(0), 
             row,
+            settings: Settings::default(),
+            mark: None,
+            selection_kind: SelectionKind::Exclusive,
+            mtime,
+            search: None,
         })
     }
     
@@ -145,21 +178,23 @@ impl TextBuffer {
     //when starting the editor from strach
     
     fn apply_diff(&mut self, diff: &EditDiff, which: UndoRedo) {
-        let (x, y) = diff.apply(&mut self.row, which); 
+        let (x, y) = diff.apply(&mut self.row, which, self.settings.tab_width); 
         self.set_cursor(x, y); 
-        self.set_dirty_start(y); 
+        self.set_dirty_start(diff.first_line().map_or(y, |l| cmp::min(l, y))); 
     }
 
     fn new_diff(&mut self, diff: EditDiff){
         self.apply_diff(&diff, UndoRedo::Redo); 
+        self.history.push(diff); 
         self.modified = true; 
-        //add history here
     }
 
     fn inserted_undo_point(&mut self) {
         if !self.inserted_undo {
             //when is not inserted into 
-
+            if self.history.finish_ongoing_edit() {
+                self.undo_count += 1; 
+            }
 
             self.modified = false; 
             self.inserted_undo = true; 
@@ -186,18 +221,32 @@ impl TextBuffer {
         self.new_diff(EditDiff::InsertChar(self.cx, self.cy, ch)); 
     }
 
+    //insert text as it is, in one edit however many lines it has
+    pub fn insert_text(&mut self, text: &str) {
+        if text.is_empty() {
+            return; 
+        }
+
+        let text = text.replace("\r\n", "\n").replace('\r', "\n"); 
+        self.inserted_undo_point(); 
+        if self.cy == self.row.len() {
+            self.new_diff(EditDiff::Newline); 
+        }
+
+        self.new_diff(EditDiff::InsertText(self.cx, self.cy, text)); 
+    }
+
     
     pub fn insert_tab(&mut self){
         self.inserted_undo_point(); //inset the tab unto a point
-        match self.lang.indent() {
-            Indent::AsIs => self.insert_char('\t'), 
-            Indent::Fixed(indent) => {
-                self.new_diff(EditDiff::Insert(
-                    self.cx, 
-                    self.cy, 
-                    indent.to_string() //changed later to to.owned
-                ))
-            }
+        if self.settings.expand_tabs {
+            self.new_diff(EditDiff::Insert(
+                self.cx, 
+                self.cy, 
+                " ".repeat(self.settings.indent_width),
+            ))
+        } else {
+            self.insert_char('\t')
         }
     }
 
@@ -290,6 +339,12 @@ impl TextBuffer {
         self.cx = x; 
         self.cy = y; 
     }
+
+    //put back a cursor saved by a window, the text may have shrunk meanwhile
+    pub fn restore_cursor(&mut self, (x, y): (usize, usize)) {
+        self.cy = cmp::min(y, self.row.len()); 
+        self.cx = cmp::min(x, self.row.get(self.cy).map(Row::len).unwrap_or(0)); 
+    }
    
     pub fn delete_until_head_of_line(&mut self){
         if self.cx == 0 && self.cy == 0 || self.cy == self.row.len() {
@@ -302,32 +357,11 @@ impl TextBuffer {
         
         }else {
 
-            let removed = self.row[self.cy][..self.cy].to_owned(); 
+            let removed = self.row[self.cy][..self.cx].to_owned(); 
             self.new_diff(EditDiff::Remove(self.cx, self.cy, removed)); 
         }
     }
 
-    pub fn delete_a_word(&mut self){
-        if self.cx == 0 || self.cy == self.row.len() {
-            return ;
-        }
-
-        self.inserted_undo_point(); 
-
-        let mut x = self.cx - 1; 
-        let row = &self.row[self.cy];
-        
-
-        while x > 0 && row.char_at(x).is_ascii_whitespace() {
-            x -= 1;
-        }
-        
-
-        let removed = self.row[self.cy][..self.cx].to_owned(); 
-        self.new_diff(EditDiff::Remove(self.cx, self.cy, removed))
-    }
-
-
     pub fn delete_right_char(&mut self){
         if self.cy == self.row.len() || self.cy == self.row.len() - 1 && self.cx == self.row[self.cy].len(){
             return; 
@@ -497,6 +531,161 @@ impl TextBuffer {
     }
 
 
+    pub fn set_mark(&mut self, mark: Option<(usize, usize)>) {
+        self.mark = mark; 
+        self.selection_kind = SelectionKind::Exclusive; 
+    }
+
+    pub fn mark(&self) -> Option<(usize, usize)> {
+        self.mark
+    }
+
+    //select from anchor to the cursor
+    pub fn select(&mut self, anchor: (usize, usize), kind: SelectionKind) {
+        self.mark = Some(anchor); 
+        self.selection_kind = kind; 
+    }
+
+    //start and end (x, y) of the selected text, None when nothing is selected. the end is exclusive
+    pub fn selection(&self) -> Option<((usize, usize), (usize, usize))> {
+        let (mx, my) = self.mark?; 
+        let (start, end) = if (my, mx) <= (self.cy, self.cx) {
+            ((mx, my), (self.cx, self.cy))
+        } else {
+            ((self.cx, self.cy), (mx, my))
+        }; 
+        let len = |y: usize| self.row.get(y).map(Row::len).unwrap_or(0); 
+
+        match self.selection_kind {
+            SelectionKind::Exclusive if start == end => None, 
+            SelectionKind::Exclusive => Some((start, end)), 
+            SelectionKind::Inclusive => Some((start, (cmp::min(end.0 + 1, len(end.1)), end.1))), 
+            SelectionKind::Lines => Some(((0, start.1), (len(end.1), end.1))), 
+        }
+    }
+
+
+    //the text from start to the exclusive end
+    pub fn text_range(&self, (sx, sy): (usize, usize), (ex, ey): (usize, usize)) -> String {
+        let mut text = String::new(); 
+        for y in sy..=ey {
+            let row = match self.row.get(y) {
+                Some(row) => row, 
+                None => break, 
+            }; 
+            let from = if y == sy { cmp::min(sx, row.len()) } else { 0 }; 
+            let to = if y == ey { cmp::min(ex, row.len()) } else { row.len() }; 
+            text.push_str(&row[from..cmp::max(from, to)]); 
+            if y < ey {
+                text.push('\n'); 
+            }
+        }
+        text
+    }
+
+    //remove the text from start to the exclusive end in one edit, the cursor goes to start
+    pub fn delete_range(&mut self, start: (usize, usize), end: (usize, usize)) -> String {
+        let text = self.text_range(start, end); 
+        if !text.is_empty() {
+            self.inserted_undo_point(); 
+            self.new_diff(EditDiff::RemoveText(start.0, start.1, text.clone())); 
+        }
+        self.set_cursor(start.0, start.1); 
+        text
+    }
+
+    //select the word under the cursor, made of letters, digits and underscores
+    pub fn select_word(&mut self) {
+        let row = match self.row.get(self.cy) {
+            Some(row) => row, 
+            None => return, 
+        }; 
+
+        let is_word = |x: usize| row.char_at_checked(x).is_some_and(|c| c.is_alphanumeric() || c == '_'); 
+        if !is_word(self.cx) {
+            return; 
+        }
+
+        let mut start = self.cx; 
+        while start > 0 && is_word(start - 1) {
+            start -= 1; 
+        }
+        let mut end = self.cx; 
+        while is_word(end) {
+            end += 1; 
+        }
+
+        self.mark = Some((start, self.cy)); 
+        self.cx = end; 
+    }
+
+
+    //where the next query after (x, y) starts, wrapping around the end of the text
+    pub fn find(&self, query: &str, (x, y): (usize, usize)) -> Option<(usize, usize)> {
+        let len = self.row.len(); 
+        if query.is_empty() || len == 0 {
+            return None; 
+        }
+
+        let (sx, sy) = if y < len { (x + 1, y) } else { (0, 0) }; 
+        //the line of the cursor is looked at again last, for the matches before the cursor
+        for i in 0..=len {
+            let cy = (sy + i) % len; 
+            let row = &self.row[cy]; 
+            let from = if i == 0 { row.byte_idx_of(cmp::min(sx, row.len())) } else { 0 }; 
+            let found = row.buffer()[from..].find(query).map(|b| row.char_idx_of(from + b)); 
+
+            match found {
+                Some(cx) if i == len && cx >= sx => return None, 
+                Some(cx) => return Some((cx, cy)), 
+                None => {}
+            }
+        }
+
+        None
+    }
+
+
+    pub fn search(&self) -> Option<&str> {
+        self.search.as_deref()
+    }
+
+    //the matches of the query are drawn on every line, so all of them are redrawn
+    pub fn set_search(&mut self, query: Option<String>) {
+        if self.search != query {
+            self.search = query; 
+            self.set_dirty_start(0); 
+        }
+    }
+
+
+    //replace each query with text in one edit, returns how many were replaced
+    pub fn replace_all(&mut self, query: &str, text: &str) -> usize {
+        if query.is_empty() {
+            return 0; 
+        }
+
+        let cursor = (self.cx, self.cy); 
+        let mut count = 0; 
+        self.inserted_undo_point(); 
+        for y in 0..self.row.len() {
+            let line = self.row[y].buffer(); 
+            let n = line.matches(query).count(); 
+            if n == 0 {
+                continue; 
+            }
+
+            let (old, new) = (line.to_string(), line.replace(query, text)); 
+            self.new_diff(EditDiff::Truncate(y, old)); 
+            self.new_diff(EditDiff::Append(y, new)); 
+            count += n; 
+        }
+
+        self.restore_cursor(cursor); 
+        count
+    }
+
+
     pub fn move_cursor_paragraph(&mut self, dir: CursorDir){
         loop {
             self.move_cursor_one(dir); 
@@ -512,40 +701,116 @@ impl TextBuffer {
     pub fn save(&mut self) -> std::result::Result<String, String>{
         self.inserted_undo_point(); 
 
-        let file = if let Some(file) = &self.file {
-            file
-        
-        } else {
+        if self.file.is_none() {
             return Ok("".to_string()) //ended
+        }
+
+        if self.settings.trim_trailing_whitespace {
+            self.trim_trailing_whitespace(); 
+        }
+
+        let file = self.file.as_ref().unwrap(); 
+        let len = self.write_to(&file.path)?; 
+
+        self.undo_count = 0; 
+        self.modified = false; 
+        self.mtime = fs::metadata(&file.path).and_then(|m| m.modified()).ok(); 
+        Ok(format!("{} bytes written to {}", len, &file.display))
+    }
+
+
+    //write a copy of the text to path, the buffer keeps its own file and stays modified
+    pub fn save_copy(&self, path: &Path) -> std::result::Result<String, String> {
+        let len = self.write_to(path)?; 
+        Ok(format!("{} bytes written to {}", len, path.display()))
+    }
+
+
+    //write the text with the line ends, final newline and charset of the settings, returns the
+    //number of bytes written
+    fn write_to(&self, path: &Path) -> std::result::Result<usize, String> {
+        let eol = match self.settings.end_of_line.as_str() {
+            "crlf" => "\r\n", 
+            "cr" => "\r", 
+            _ => "\n",
         }; 
 
-        let f = match File::create(&file.path) {
+        let mut text = String::new(); 
+        let last = self.row.len().saturating_sub(1); 
+        for (y, line) in self.row.iter().enumerate() {
+            let line = line.buffer(); 
+            //save trimmed the buffer already, a copy is trimmed as it is written
+            if self.settings.trim_trailing_whitespace {
+                text.push_str(line.trim_end_matches([' ', '\t'])); 
+            } else {
+                text.push_str(line); 
+            }
+            if y < last || self.settings.insert_final_newline {
+                text.push_str(eol); 
+            }
+        }
+
+        let bytes = encode(&text, &self.settings.charset)?; 
+
+        let f = match File::create(path) {
             Ok(d) => d, 
             Err(e) => return Err(format!("Could not save: {}", e)),
         }; 
 
         let mut f = io::BufWriter::new(f); 
-        let mut bytes = 0; 
-
-        for line in self.row.iter() {
-            let b = line.buffer(); 
-            writeln!(f, "{}", b);
-            bytes += b.bytes().len() + 1;
+        f.write_all(&bytes)
+            .and_then(|_| f.flush())
+            .map_err(|e| format!("could not write to file: {}", e))?; 
+        Ok(bytes.len())
+    }
+
+
+    //drop the spaces and tabs at the end of each line, as trim_trailing_whitespace asks before saving.
+    //one edit which undo can take back
+    fn trim_trailing_whitespace(&mut self) {
+        let cursor = (self.cx, self.cy); 
+        for y in 0..self.row.len() {
+            let line = self.row[y].buffer(); 
+            let trimmed = line.trim_end_matches([' ', '\t']); 
+            if trimmed.len() < line.len() {
+                let removed = line[trimmed.len()..].to_string(); 
+                self.new_diff(EditDiff::Truncate(y, removed)); 
+            }
         }
 
+        self.restore_cursor(cursor); 
+        self.inserted_undo = false; 
+        self.inserted_undo_point(); 
+    }
 
-        f.flush().map_err(|e| format!("could not flush to file: {}", e)); 
-        
 
-        self.undo_count = 0; 
-        self.modified = false; 
-        Ok(format!("{} bytes written to {}", bytes, &file.display))
+    pub fn undo(&mut self) -> bool {
+        //the edit going on is the first one undone
+        self.inserted_undo = false; 
+        self.inserted_undo_point(); 
+        let state = self.history.undo(&mut self.row, self.settings.tab_width); 
+        let undone = self.after_undoredo(state); 
+        if undone {
+            self.undo_count -= 1; 
+        }
+        undone
+    }
+
+    pub fn redo(&mut self) -> bool {
+        self.inserted_undo = false; 
+        self.inserted_undo_point(); 
+        let state = self.history.redo(&mut self.row, self.settings.tab_width); 
+        let redone = self.after_undoredo(state); 
+        if redone {
+            self.undo_count += 1; 
+        }
+        redone
     }
 
 
-    fn after_undoredo(&mut self, state: Option<(usize, usize, usize, bool)>) -> bool{
+    fn after_undoredo(&mut self, state: Option<(usize, usize, usize)>) -> bool{
         match state {
-            Some((x, y, s, _)) => {
+            Some((x, y, s)) => {
                 self.set_cursor(x, y);
                 self.set_dirty_start(s);
                 true
@@ -567,6 +832,26 @@ impl TextBuffer {
         (self.cx, self.cy)
     }
 
+    pub fn path(&self) -> Option<&Path> {
+        self.file.as_ref().map(|f| f.path.as_path())
+    }
+
+    pub fn mtime(&self) -> Option<SystemTime> {
+        self.mtime
+    }
+
+    //whether the file, found on disk with mtime, was written by another program since we last read
+    //or wrote it. reported once per change, a stat older than our last save is not a change
+    pub fn disk_changed(&mut self, mtime: SystemTime) -> bool {
+        match self.mtime {
+            Some(ours) if mtime > ours => {
+                self.mtime = Some(mtime); 
+                true
+            }
+            _ => false, 
+        }
+    }
+
     pub fn has_file(&self) -> bool {
         self.file.is_some()
     }
@@ -586,23 +871,29 @@ impl TextBuffer {
 
 
 
-    pub fn from_sctrach(&self) -> bool {
+    pub fn is_scratch(&self) -> bool {
        // self.file.is_none() && self.row.len() == 1 && self.row[0].len() == 0
         self.file.is_none()
     }
 
-    pub fn set_lang(&mut self, lang: Language) {
-        self.lang = lang; //assign a lang -> self.lang
-    }
-
-
-
     pub fn lang(&self) -> Language {
         self.lang
     }
 
-    pub fn cy(&self) -> usize {
-        self.cy
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    //a new tab width renders every row again
+    pub fn set_settings(&mut self, settings: Settings) {
+        if settings.tab_width != self.settings.tab_width {
+            for row in self.row.iter_mut() {
+                row.set_tab_width(settings.tab_width); 
+            }
+            self.set_dirty_start(0); 
+        }
+
+        self.settings = settings; 
     }
 
     pub fn modified(&self) -> bool {
@@ -613,6 +904,47 @@ impl TextBuffer {
 
 
 
+//the text of bytes in one of the charsets of EditorConfig, the reverse of encode
+fn decode(bytes: &[u8], charset: &str) -> io::Result<String> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("the file is not valid {}", charset)); 
+    let utf16 = |to_u16: fn([u8; 2]) -> u16| {
+        if !bytes.len().is_multiple_of(2) {
+            return Err(invalid()); 
+        }
+        let units: Vec<u16> = bytes.chunks_exact(2).map(|c| to_u16([c[0], c[1]])).collect(); 
+        String::from_utf16(&units).map_err(|_| invalid())
+    }; 
+
+    match charset {
+        "utf-8-bom" => {
+            let bytes = bytes.strip_prefix(&[0xef, 0xbb, 0xbf]).unwrap_or(bytes); 
+            String::from_utf8(bytes.to_vec()).map_err(|_| invalid())
+        }
+        "latin1" => Ok(bytes.iter().map(|&b| char::from(b)).collect()), 
+        "utf-16be" => utf16(u16::from_be_bytes), 
+        "utf-16le" => utf16(u16::from_le_bytes), 
+        _ => String::from_utf8(bytes.to_vec()).map_err(|_| invalid()), 
+    }
+}
+
+
+//the bytes of text in one of the charsets of EditorConfig
+fn encode(text: &str, charset: &str) -> std::result::Result<Vec<u8>, String> {
+    match charset {
+        "utf-8-bom" => Ok([&[0xef, 0xbb, 0xbf], text.as_bytes()].concat()), 
+        "latin1" => text
+            .chars()
+            .map(|c| u8::try_from(u32::from(c)).map_err(|_| format!("Could not save: {:?} is not in latin1", c)))
+            .collect(), 
+        "utf-16be" => Ok(text.encode_utf16().flat_map(u16::to_be_bytes).collect()), 
+        "utf-16le" => Ok(text.encode_utf16().flat_map(u16::to_le_bytes).collect()), 
+        _ => Ok(text.as_bytes().to_vec()), 
+    }
+}
+
+
+
+
 impl Status {
 
     setter!(set_buf_pos, buf_pos, (usize, usize)); 
@@ -620,22 +952,26 @@ impl Status {
     setter!(set_filename, filename, &str, filename.to_string());
     setter!(set_language, language, Language); 
     setter!(set_line_pos, line_pos, (usize, usize)); 
+    setter!(set_mode, mode, Option<&'static str>); 
 
 
     pub fn from_buffer(buf:&TextBuffer, buf_pos: (usize, usize)) -> Self {
         Self {
-            modified: buf.modified, 
+            modified: buf.modified(), 
             filename: buf.filename().to_string(), //passing a string a string here
             language:buf.lang, 
             line_pos: (buf.cy +1, buf.cx), 
             redraw: false,
             buf_pos,
+            mode: None,
         }
     }
 
     pub fn left(&self) -> String {
+        let mode = self.mode.map(|m| format!("-- {} -- ", m)).unwrap_or_default(); 
         format!(
-            "{:<20?} - {}/{} {}", 
+            "{}{:<20?} - {}/{} {}", 
+            mode, 
             self.filename, 
             self.buf_pos.0, //its picking the first tuple
             self.buf_pos.1, 
@@ -651,9 +987,14 @@ impl Status {
     }
 
     pub fn update_from_but(&mut self, buf: &TextBuffer) {
-        self.set_modified(buf.modified); 
+        self.update_from_view(buf, (buf.cx, buf.cy))
+    }
+
+    //same as update_from_but, for a window whose cursor is not the buffer's one
+    pub fn update_from_view(&mut self, buf: &TextBuffer, (cx, cy): (usize, usize)) {
+        self.set_modified(buf.modified()); 
         self.set_language(buf.lang); 
         self.set_filename(buf.filename()); 
-        self.set_line_pos((buf.cy, buf.cx))
+        self.set_line_pos((cy, cx))
     }
 }
\ No newline at end of file
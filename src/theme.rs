@@ -0,0 +1,509 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use toml::{Table, Value};
+
+use crate::color::{color_16_from_name, Color, Fallback, Rgb, TerminalColor};
+use crate::config;
+use crate::error::Result;
+use crate::fuzzy;
+use crate::input::{InputSeq, KeySeq};
+use crate::prompt::{Action, Prompt, PromptResult};
+
+
+//the group enum and the name of each group in theme files
+macro_rules! groups {
+    ($($group:ident = $name:expr,)*) => {
+        #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+        pub enum Group {
+            $($group,)*
+        }
+
+        const GROUPS: &[(Group, &str)] = &[$((Group::$group, $name),)*];
+    };
+}
+
+
+groups! {
+    Normal = "normal",
+    NonText = "non_text",       //the ~ of lines after the end of the buffer
+    Gutter = "gutter",
+    StatusBar = "status_bar",
+    StatusBarInactive = "status_bar_inactive",
+    Separator = "separator",    //between side by side windows
+    Selection = "selection",
+    SearchMatch = "search_match",
+    ErrorMessage = "error_message",
+    Title = "title",            //titles of lists and the keys in the help
+}
+
+
+impl Group {
+    //the group whose style is used when a theme does not have this one
+    fn parent(self) -> Option<Group> {
+        match self {
+            Group::Gutter => Some(Group::NonText),
+            Group::StatusBarInactive => Some(Group::StatusBar),
+            Group::Separator => Some(Group::StatusBarInactive),
+            _ => None,
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Background {
+    Dark,
+    Light,
+}
+
+
+impl Background {
+    pub fn from_rgb(Rgb(r, g, b): Rgb) -> Background {
+        //relative luminance, green looks much brighter than blue
+        let luma = 2126 * r as u32 + 7152 * g as u32 + 722 * b as u32;
+        if luma < 128 * 10000 {
+            Background::Dark
+        } else {
+            Background::Light
+        }
+    }
+
+    //COLORFGBG is set by some terminals to "<fg>;<bg>" with ANSI color indexes
+    pub fn from_env() -> Option<Background> {
+        let colors = env::var("COLORFGBG").ok()?;
+        let bg = colors.rsplit(';').next()?.parse::<u8>().ok()?;
+        Some(Self::from_rgb(Rgb::from_256(bg)))
+    }
+
+    pub fn from_name(name: &str) -> Option<Background> {
+        match name {
+            "dark" => Some(Background::Dark),
+            "light" => Some(Background::Light),
+            _ => None,
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, Default, Debug)]
+struct Style {
+    fg: Color,
+    bg: Color,
+    bold: bool,
+    underline: bool,
+    reverse: bool,
+}
+
+
+impl Style {
+    fn sgr(&self, term: TerminalColor) -> Vec<String> {
+        let mut params = vec![];
+        params.extend(self.fg.sgr(term, false));
+        params.extend(self.bg.sgr(term, true));
+        if self.bold {
+            params.push("1".to_string());
+        }
+        if self.underline {
+            params.push("4".to_string());
+        }
+        if self.reverse {
+            params.push("7".to_string());
+        }
+        params
+    }
+}
+
+
+const GRUVBOX_DARK: &str = r##"
+background = "dark"
+
+[normal]
+fg = "#fbf1c7"
+bg = "#282828"
+fg16 = "default"
+bg16 = "default"
+
+[non_text]
+fg = "#665c54"
+fg16 = "white"
+
+[status_bar]
+reverse = true
+
+[status_bar_inactive]
+fg = "#665c54"
+fg16 = "white"
+reverse = true
+
+[selection]
+reverse = true
+
+[search_match]
+fg = "#282828"
+bg = "#d79921"
+fg16 = "black"
+bg16 = "bright-yellow"
+
+[error_message]
+fg = "#fbf1c7"
+bg = "#cc241d"
+fg16 = "bright-white"
+bg16 = "red"
+
+[title]
+fg = "#8ec07c"
+fg16 = "bright-cyan"
+"##;
+
+
+const GRUVBOX_LIGHT: &str = r##"
+background = "light"
+
+[normal]
+fg = "#3c3836"
+bg = "#fbf1c7"
+fg16 = "default"
+bg16 = "default"
+
+[non_text]
+fg = "#a89984"
+fg16 = "bright-black"
+
+[status_bar]
+reverse = true
+
+[status_bar_inactive]
+fg = "#a89984"
+fg16 = "bright-black"
+reverse = true
+
+[selection]
+reverse = true
+
+[search_match]
+fg = "#fbf1c7"
+bg = "#b57614"
+fg16 = "black"
+bg16 = "bright-yellow"
+
+[error_message]
+fg = "#fbf1c7"
+bg = "#cc241d"
+fg16 = "bright-white"
+bg16 = "red"
+
+[title]
+fg = "#427b58"
+fg16 = "cyan"
+"##;
+
+
+const BUILTIN: &[(&str, &str)] = &[("gruvbox-dark", GRUVBOX_DARK), ("gruvbox-light", GRUVBOX_LIGHT)];
+
+pub const DEFAULT_THEME: &str = "gruvbox-dark";
+
+
+//$XDG_CONFIG_HOME/detty/themes, one <name>.toml file per theme
+fn themes_dir() -> Option<PathBuf> {
+    config::config_dir().map(|d| d.join("themes"))
+}
+
+
+//the escape sequences to draw each highlight group, made from a theme file for the colors the
+//terminal supports
+pub struct Theme {
+    pub name: String,
+    pub background: Background,
+    styles: Vec<Style>,
+    sequences: Vec<Vec<u8>>,
+    pub warnings: Vec<String>,
+}
+
+
+impl Theme {
+    pub fn builtin(term: TerminalColor) -> Theme {
+        Self::parse(DEFAULT_THEME, GRUVBOX_DARK, term).expect("built-in theme is valid")
+    }
+
+    //a theme from the themes directory, which may replace a built-in one of the same name
+    pub fn load(name: &str, term: TerminalColor) -> std::result::Result<Theme, String> {
+        let name = if name == "default" { DEFAULT_THEME } else { name };
+
+        if let Some(path) = themes_dir().map(|d| d.join(format!("{}.toml", name))).filter(|p| p.is_file()) {
+            let text = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            return Self::parse(name, &text, term).map_err(|e| format!("{}: {}", path.display(), e));
+        }
+
+        match BUILTIN.iter().find(|(n, _)| *n == name) {
+            Some((_, text)) => Self::parse(name, text, term),
+            None => Err(format!("Unknown theme '{}'", name)),
+        }
+    }
+
+    //names of the built-in themes and the files in the themes directory
+    pub fn names() -> Vec<String> {
+        let mut names: Vec<String> = BUILTIN.iter().map(|(n, _)| n.to_string()).collect();
+
+        if let Some(entries) = themes_dir().and_then(|d| fs::read_dir(d).ok()) {
+            for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+                if path.extension().is_some_and(|e| e == "toml") {
+                    if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+                        names.push(stem.to_string());
+                    }
+                }
+            }
+        }
+
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    fn parse(name: &str, text: &str, term: TerminalColor) -> std::result::Result<Theme, String> {
+        let table = text.parse::<Table>().map_err(|e| e.to_string().trim().to_string())?;
+
+        let mut theme = Theme {
+            name: name.to_string(),
+            background: Background::Dark,
+            styles: vec![Style::default(); GROUPS.len()],
+            sequences: vec![],
+            warnings: vec![],
+        };
+        let mut defined = vec![false; GROUPS.len()];
+
+        for (key, value) in &table {
+            if key == "background" {
+                match value.as_str().and_then(Background::from_name) {
+                    Some(background) => theme.background = background,
+                    None => theme.warnings.push("'background' must be \"dark\" or \"light\"".to_string()),
+                }
+                continue;
+            }
+
+            let group = match GROUPS.iter().find(|(_, n)| n == key) {
+                Some((group, _)) => *group,
+                None => {
+                    theme.warnings.push(format!("Unknown highlight group '{}'", key));
+                    continue;
+                }
+            };
+
+            match value {
+                Value::Table(style) => {
+                    theme.styles[group as usize] = parse_style(key, style, &mut theme.warnings);
+                    defined[group as usize] = true;
+                }
+                _ => theme.warnings.push(format!("'{}' must be a section", key)),
+            }
+        }
+
+        //parents come before their children in GROUPS
+        for (group, _) in GROUPS {
+            if let Some(parent) = group.parent().filter(|_| !defined[*group as usize]) {
+                theme.styles[*group as usize] = theme.styles[parent as usize];
+                defined[*group as usize] = defined[parent as usize];
+            }
+        }
+
+        theme.set_terminal_color(term);
+        Ok(theme)
+    }
+
+    //every sequence resets the attributes so that a group never inherits the one drawn before it
+    pub fn set_terminal_color(&mut self, term: TerminalColor) {
+        let normal = self.styles[Group::Normal as usize].sgr(term);
+
+        self.sequences = self
+            .styles
+            .iter()
+            .enumerate()
+            .map(|(i, style)| {
+                let mut params = vec!["0".to_string()];
+                params.extend(normal.iter().cloned());
+                if i != Group::Normal as usize {
+                    params.extend(style.sgr(term));
+                }
+                format!("\x1b[{}m", params.join(";")).into_bytes()
+            })
+            .collect();
+    }
+
+    pub fn sequence(&self, group: Group) -> &[u8] {
+        &self.sequences[group as usize]
+    }
+}
+
+
+fn parse_style(group: &str, table: &Table, warnings: &mut Vec<String>) -> Style {
+    let mut style = Style::default();
+
+    for (key, value) in table {
+        let ok = match key.as_str() {
+            "fg" | "bg" => match value.as_str().and_then(Rgb::parse) {
+                Some(rgb) => {
+                    color_of(&mut style, key).rgb = Some(rgb);
+                    true
+                }
+                None => false,
+            },
+            "fg256" | "bg256" => {
+                let c = match value {
+                    Value::String(s) if s == "default" => Some(Fallback::TerminalDefault),
+                    Value::Integer(i) => u8::try_from(*i).ok().map(Fallback::Index),
+                    _ => None,
+                };
+                match c {
+                    Some(c) => {
+                        color_of(&mut style, key).c256 = c;
+                        true
+                    }
+                    None => false,
+                }
+            }
+            "fg16" | "bg16" => {
+                let c = match value {
+                    Value::String(s) if s == "default" => Some(Fallback::TerminalDefault),
+                    Value::String(name) => color_16_from_name(name).map(Fallback::Index),
+                    Value::Integer(i) => u8::try_from(*i).ok().filter(|c| *c < 16).map(Fallback::Index),
+                    _ => None,
+                };
+                match c {
+                    Some(c) => {
+                        color_of(&mut style, key).c16 = c;
+                        true
+                    }
+                    None => false,
+                }
+            }
+            "bold" | "underline" | "reverse" => match value.as_bool() {
+                Some(b) => {
+                    match key.as_str() {
+                        "bold" => style.bold = b,
+                        "underline" => style.underline = b,
+                        _ => style.reverse = b,
+                    }
+                    true
+                }
+                None => false,
+            },
+            _ => {
+                warnings.push(format!("Unknown key '{}.{}' in theme", group, key));
+                continue;
+            }
+        };
+
+        if !ok {
+            let expected = match key.as_str() {
+                "fg" | "bg" => "a color like \"#rrggbb\"",
+                "fg256" | "bg256" => "a number from 0 to 255 or \"default\"",
+                "fg16" | "bg16" => "a color name like \"bright-red\", a number from 0 to 15 or \"default\"",
+                _ => "true or false",
+            };
+            warnings.push(format!("'{}.{}' must be {}", group, key, expected));
+        }
+    }
+
+    style
+}
+
+
+fn color_of<'a>(style: &'a mut Style, key: &str) -> &'a mut Color {
+    if key.starts_with("bg") {
+        &mut style.bg
+    } else {
+        &mut style.fg
+    }
+}
+
+
+//prompt to pick a theme by name, completing over the known themes
+pub struct ChooseTheme {
+    names: Vec<String>,
+    input: String,
+    matches: Vec<usize>,
+    selected: usize,
+}
+
+
+impl ChooseTheme {
+    fn update<W: Write>(&mut self, prompt: &mut Prompt<'_, W>, input: &str) {
+        self.matches = fuzzy::rank(input, self.names.iter().enumerate().map(|(i, n)| (i, n.as_str())));
+        self.input = input.to_string();
+        self.selected = 0;
+        self.update_hint(prompt);
+    }
+
+    fn update_hint<W: Write>(&self, prompt: &mut Prompt<'_, W>) {
+        let len = self.matches.len();
+        if len == 0 {
+            prompt.set_hint(" [No match]");
+            return;
+        }
+
+        let labels: Vec<&str> = (0..len.min(8))
+            .map(|i| self.names[self.matches[(self.selected + i) % len]].as_str())
+            .collect();
+        prompt.set_hint(format!(" {{{}}}", labels.join(" | ")));
+    }
+}
+
+
+impl Action for ChooseTheme {
+    fn new<W: Write>(prompt: &mut Prompt<'_, W>) -> Self {
+        let mut action = Self {
+            names: Theme::names(),
+            input: String::new(),
+            matches: vec![],
+            selected: 0,
+        };
+        action.update(prompt, "");
+        action
+    }
+
+    fn on_seq<W: Write>(
+        &mut self,
+        prompt: &mut Prompt<'_, W>,
+        input: &str,
+        seq: InputSeq,
+    ) -> Result<bool> {
+        use KeySeq::*;
+
+        let len = self.matches.len();
+        match seq {
+            InputSeq { key: TabKey, .. } | InputSeq { key: Key(b'n'), ctrl: true, .. } if len > 0 => {
+                self.selected = (self.selected + 1) % len;
+                self.update_hint(prompt);
+            }
+            InputSeq { key: Key(b'p'), ctrl: true, .. } if len > 0 => {
+                self.selected = (self.selected + len - 1) % len;
+                self.update_hint(prompt);
+            }
+            _ if input != self.input => self.update(prompt, input),
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    fn on_end<W: Write>(
+        self,
+        prompt: &mut Prompt<'_, W>,
+        result: PromptResult,
+    ) -> Result<PromptResult> {
+        prompt.set_hint("");
+
+        match result {
+            PromptResult::Input(_) => match self.matches.get(self.selected) {
+                Some(i) => Ok(PromptResult::Input(self.names[*i].clone())),
+                None => {
+                    prompt.set_error_message("No theme matches");
+                    Ok(PromptResult::Canceled)
+                }
+            },
+            PromptResult::Canceled => Ok(result),
+        }
+    }
+}
@@ -0,0 +1,1130 @@
+use std::cmp;
+
+use crate::input::{InputSeq, KeySeq};
+use crate::row::Row;
+
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Mode {
+    Normal,
+    Insert,
+    Visual,
+    VisualLine,
+    CommandLine,
+}
+
+
+impl Mode {
+    //shown in the status bar
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Normal => "NORMAL",
+            Mode::Insert => "INSERT",
+            Mode::Visual => "VISUAL",
+            Mode::VisualLine => "V-LINE",
+            Mode::CommandLine => "COMMAND",
+        }
+    }
+
+    pub fn is_visual(self) -> bool {
+        matches!(self, Mode::Visual | Mode::VisualLine)
+    }
+}
+
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Operator {
+    Delete,
+    Change,
+    Yank,
+}
+
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Motion {
+    Left,      //h
+    Right,     //l
+    Up,        //k
+    Down,      //j
+    WordStart, //w
+    WordEnd,   //e
+    WordBack,  //b
+    LineStart, //0
+    LineEnd,   //$
+    FirstLine, //gg
+    LastLine,  //G
+}
+
+
+//how much text an operator takes with a motion
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum MotionKind {
+    Exclusive, //up to the target
+    Inclusive, //with the char at the target
+    Lines,     //every line from the cursor to the target
+}
+
+
+impl Motion {
+    pub fn kind(self) -> MotionKind {
+        use Motion::*;
+
+        match self {
+            Left | Right | WordStart | WordBack | LineStart => MotionKind::Exclusive,
+            WordEnd | LineEnd => MotionKind::Inclusive,
+            Up | Down | FirstLine | LastLine => MotionKind::Lines,
+        }
+    }
+}
+
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ObjectKind {
+    Word,             //w
+    Pair(char, char), //the brackets, like ( or {
+    Quote(char),      //" ' and `
+    Paragraph,        //p
+    Tag,              //t
+}
+
+
+//what i and a take after an operator or in visual mode
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct TextObject {
+    pub kind: ObjectKind,
+    pub around: bool, //a takes the delimiters or the blanks around too, i only what is inside
+}
+
+
+//what an operator works on
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Target {
+    Motion(Motion),
+    Object(TextObject),
+    Lines,     //the operator twice, like dd
+    Selection, //in visual mode
+}
+
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum InsertAt {
+    Cursor,    //i
+    After,     //a
+    LineHead,  //I
+    LineEnd,   //A
+    LineBelow, //o
+    LineAbove, //O
+}
+
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Command {
+    Move(Motion),
+    Operate(Operator, Target),
+    Insert(InsertAt),
+    Paste { before: bool },
+    Visual,
+    VisualLine,
+    SwapAnchor, //o in visual mode
+    SelectObject(TextObject),
+    Ex, //the : prompt
+    Repeat,
+    Undo,
+}
+
+
+impl Command {
+    //commands which . repeats
+    pub fn changes_text(self) -> bool {
+        match self {
+            Command::Operate(op, _) => op != Operator::Yank,
+            Command::Insert(_) | Command::Paste { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum Parse {
+    Done(Option<usize>, Command), //with the count typed before it
+    Pending,
+    Invalid,
+}
+
+
+fn take_count(keys: &[char], at: &mut usize) -> Option<usize> {
+    let start = *at;
+    while *at < keys.len() && keys[*at].is_ascii_digit() && !(*at == start && keys[*at] == '0') {
+        *at += 1;
+    }
+
+    keys[start..*at].iter().collect::<String>().parse().ok()
+}
+
+
+fn parse_motion(keys: &[char], at: usize) -> Result<Motion, Parse> {
+    use Motion::*;
+
+    Ok(match keys.get(at) {
+        None => return Err(Parse::Pending),
+        Some('h') => Left,
+        Some('l') | Some(' ') => Right,
+        Some('k') => Up,
+        Some('j') => Down,
+        Some('w') => WordStart,
+        Some('e') => WordEnd,
+        Some('b') => WordBack,
+        Some('0') => LineStart,
+        Some('$') => LineEnd,
+        Some('G') => LastLine,
+        Some('g') => match keys.get(at + 1) {
+            None => return Err(Parse::Pending),
+            Some('g') => FirstLine,
+            Some(_) => return Err(Parse::Invalid),
+        },
+        Some(_) => return Err(Parse::Invalid),
+    })
+}
+
+
+fn parse_object(keys: &[char], at: usize) -> Result<TextObject, Parse> {
+    use ObjectKind::*;
+
+    let around = match keys.get(at) {
+        None => return Err(Parse::Pending),
+        Some('i') => false,
+        Some('a') => true,
+        Some(_) => return Err(Parse::Invalid),
+    };
+
+    let kind = match keys.get(at + 1) {
+        None => return Err(Parse::Pending),
+        Some('w') => Word,
+        Some('(') | Some(')') | Some('b') => Pair('(', ')'),
+        Some('{') | Some('}') | Some('B') => Pair('{', '}'),
+        Some('[') | Some(']') => Pair('[', ']'),
+        Some('<') | Some('>') => Pair('<', '>'),
+        Some(c @ '"') | Some(c @ '\'') | Some(c @ '`') => Quote(*c),
+        Some('p') => Paragraph,
+        Some('t') => Tag,
+        Some(_) => return Err(Parse::Invalid),
+    };
+    Ok(TextObject { kind, around })
+}
+
+
+//the command typed so far in normal or visual mode, like "2d3w"
+pub fn parse(keys: &[char], visual: bool) -> Parse {
+    use Command::*;
+
+    let mut at = 0;
+    let count = take_count(keys, &mut at);
+    let c = match keys.get(at) {
+        Some(c) => *c,
+        None => return Parse::Pending,
+    };
+
+    let done = |cmd| Parse::Done(count, cmd);
+
+    let op = match c {
+        'i' | 'a' if visual => {
+            return match parse_object(keys, at) {
+                Ok(obj) => done(SelectObject(obj)),
+                Err(parse) => parse,
+            }
+        }
+        'd' | 'x' if visual => return done(Operate(Operator::Delete, Target::Selection)),
+        'c' | 's' if visual => return done(Operate(Operator::Change, Target::Selection)),
+        'y' if visual => return done(Operate(Operator::Yank, Target::Selection)),
+        'o' if visual => return done(SwapAnchor),
+        'd' => Operator::Delete,
+        'c' => Operator::Change,
+        'y' => Operator::Yank,
+        'x' => return done(Operate(Operator::Delete, Target::Motion(Motion::Right))),
+        'X' => return done(Operate(Operator::Delete, Target::Motion(Motion::Left))),
+        's' => return done(Operate(Operator::Change, Target::Motion(Motion::Right))),
+        'D' => return done(Operate(Operator::Delete, Target::Motion(Motion::LineEnd))),
+        'C' => return done(Operate(Operator::Change, Target::Motion(Motion::LineEnd))),
+        'Y' => return done(Operate(Operator::Yank, Target::Lines)),
+        'i' => return done(Insert(InsertAt::Cursor)),
+        'a' => return done(Insert(InsertAt::After)),
+        'I' => return done(Insert(InsertAt::LineHead)),
+        'A' => return done(Insert(InsertAt::LineEnd)),
+        'o' => return done(Insert(InsertAt::LineBelow)),
+        'O' => return done(Insert(InsertAt::LineAbove)),
+        'p' => return done(Paste { before: false }),
+        'P' => return done(Paste { before: true }),
+        'v' => return done(Visual),
+        'V' => return done(VisualLine),
+        ':' => return done(Ex),
+        '.' => return done(Repeat),
+        'u' => return done(Undo),
+        _ => {
+            return match parse_motion(keys, at) {
+                Ok(motion) => done(Move(motion)),
+                Err(parse) => parse,
+            }
+        }
+    };
+
+    //a count after the operator multiplies the one before it, 2d3w deletes 6 words
+    at += 1;
+    let inner = take_count(keys, &mut at);
+    let count = match (count, inner) {
+        (None, None) => None,
+        (a, b) => Some(a.unwrap_or(1) * b.unwrap_or(1)),
+    };
+
+    if keys.get(at) == Some(&c) {
+        return Parse::Done(count, Operate(op, Target::Lines));
+    }
+    if matches!(keys.get(at), Some('i') | Some('a')) {
+        return match parse_object(keys, at) {
+            Ok(obj) => Parse::Done(count, Operate(op, Target::Object(obj))),
+            Err(parse) => parse,
+        };
+    }
+
+    match parse_motion(keys, at) {
+        Ok(motion) => Parse::Done(count, Operate(op, Target::Motion(motion))),
+        Err(parse) => parse,
+    }
+}
+
+
+//the char a key stands for in normal mode, arrows are the hjkl motions
+pub fn key_char(seq: &InputSeq) -> Option<char> {
+    use KeySeq::*;
+
+    if seq.ctrl || seq.alt {
+        return None;
+    }
+
+    match seq.key {
+        Key(0x7f) => Some('h'),
+        Key(b) if !b.is_ascii_control() => Some(b as char),
+        Utf8Key(c) => Some(c),
+        LeftKey => Some('h'),
+        RightKey => Some('l'),
+        UpKey => Some('k'),
+        DownKey | EnterKey => Some('j'),
+        HomeKey => Some('0'),
+        EndKey => Some('$'),
+        _ => None,
+    }
+}
+
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum CharClass {
+    Blank, //spaces and the end of a line
+    Word,
+    Punct,
+}
+
+
+fn line_len(rows: &[Row], y: usize) -> usize {
+    rows.get(y).map(Row::len).unwrap_or(0)
+}
+
+
+fn class_at(rows: &[Row], (x, y): (usize, usize)) -> CharClass {
+    match rows.get(y).and_then(|r| r.char_at_checked(x)) {
+        Some(c) if c.is_whitespace() => CharClass::Blank,
+        Some(c) if c.is_alphanumeric() || c == '_' => CharClass::Word,
+        Some(_) => CharClass::Punct,
+        None => CharClass::Blank,
+    }
+}
+
+
+fn is_empty_line(rows: &[Row], (x, y): (usize, usize)) -> bool {
+    x == 0 && line_len(rows, y) == 0
+}
+
+
+//positions of the text one after another, the end of each line is one too
+fn next_pos(rows: &[Row], (x, y): (usize, usize)) -> Option<(usize, usize)> {
+    if x < line_len(rows, y) {
+        Some((x + 1, y))
+    } else if y + 1 < rows.len() {
+        Some((0, y + 1))
+    } else {
+        None
+    }
+}
+
+
+pub fn prev_pos(rows: &[Row], (x, y): (usize, usize)) -> Option<(usize, usize)> {
+    if x > 0 {
+        Some((x - 1, y))
+    } else if y > 0 {
+        Some((line_len(rows, y - 1), y - 1))
+    } else {
+        None
+    }
+}
+
+
+//start of the next word, an empty line is a word of its own. the end of the text when there is
+//no next word
+fn word_start(rows: &[Row], pos: (usize, usize)) -> (usize, usize) {
+    let class = class_at(rows, pos);
+    let mut pos = pos;
+
+    loop {
+        pos = match next_pos(rows, pos) {
+            Some(p) => p,
+            None => return pos,
+        };
+        if class == CharClass::Blank || class_at(rows, pos) != class {
+            break;
+        }
+    }
+
+    while class_at(rows, pos) == CharClass::Blank && !is_empty_line(rows, pos) {
+        pos = match next_pos(rows, pos) {
+            Some(p) => p,
+            None => return pos,
+        };
+    }
+    pos
+}
+
+
+//last char of the word after the cursor
+fn word_end(rows: &[Row], pos: (usize, usize)) -> (usize, usize) {
+    let mut pos = match next_pos(rows, pos) {
+        Some(p) => p,
+        None => return pos,
+    };
+
+    while class_at(rows, pos) == CharClass::Blank {
+        pos = match next_pos(rows, pos) {
+            Some(p) => p,
+            None => return pos,
+        };
+    }
+
+    let class = class_at(rows, pos);
+    while let Some(next) = next_pos(rows, pos).filter(|p| class_at(rows, *p) == class) {
+        pos = next;
+    }
+    pos
+}
+
+
+//start of the word before the cursor
+fn word_back(rows: &[Row], pos: (usize, usize)) -> (usize, usize) {
+    let mut pos = match prev_pos(rows, pos) {
+        Some(p) => p,
+        None => return pos,
+    };
+
+    while class_at(rows, pos) == CharClass::Blank && !is_empty_line(rows, pos) {
+        pos = match prev_pos(rows, pos) {
+            Some(p) => p,
+            None => return pos,
+        };
+    }
+    if is_empty_line(rows, pos) {
+        return pos;
+    }
+
+    let class = class_at(rows, pos);
+    while let Some(prev) = prev_pos(rows, pos).filter(|p| class_at(rows, *p) == class) {
+        pos = prev;
+    }
+    pos
+}
+
+
+//column of the first char which is not a space
+pub fn first_non_blank(rows: &[Row], y: usize) -> usize {
+    let len = line_len(rows, y);
+    (0..len).find(|x| class_at(rows, (*x, y)) != CharClass::Blank).unwrap_or(len)
+}
+
+
+//where motion moves the cursor at pos, count times. an operator also takes the end of the last line
+pub fn motion_target(rows: &[Row], (x, y): (usize, usize), motion: Motion, count: Option<usize>) -> (usize, usize) {
+    use Motion::*;
+
+    let n = count.unwrap_or(1);
+    let last = rows.len().saturating_sub(1);
+
+    match motion {
+        Left => (x.saturating_sub(n), y),
+        Right => (cmp::min(x + n, line_len(rows, y)), y),
+        Up => {
+            let y = y.saturating_sub(n);
+            (cmp::min(x, line_len(rows, y)), y)
+        }
+        Down => {
+            let y = cmp::min(y + n, last);
+            (cmp::min(x, line_len(rows, y)), y)
+        }
+        WordStart => (0..n).fold((x, y), |p, _| word_start(rows, p)),
+        WordEnd => (0..n).fold((x, y), |p, _| word_end(rows, p)),
+        WordBack => (0..n).fold((x, y), |p, _| word_back(rows, p)),
+        LineStart => (0, y),
+        LineEnd => {
+            let y = cmp::min(y + n - 1, last);
+            (line_len(rows, y).saturating_sub(1), y)
+        }
+        FirstLine | LastLine => {
+            let y = match count {
+                Some(n) => cmp::min(n.saturating_sub(1), last),
+                None if motion == FirstLine => 0,
+                None => last,
+            };
+            (first_non_blank(rows, y), y)
+        }
+    }
+}
+
+
+//start and exclusive end of the text an operator takes with a char motion from pos
+pub fn motion_range(rows: &[Row], pos: (usize, usize), motion: Motion, count: Option<usize>) -> ((usize, usize), (usize, usize)) {
+    let n = count.unwrap_or(1);
+
+    //dw on the last word of a line stops at the end of the line instead of the next word
+    let target = if motion == Motion::WordStart {
+        let from = motion_target(rows, pos, motion, Some(n - 1));
+        let target = word_start(rows, from);
+        if target.1 > from.1 && !is_empty_line(rows, from) {
+            (line_len(rows, from.1), from.1)
+        } else {
+            target
+        }
+    } else {
+        motion_target(rows, pos, motion, count)
+    };
+
+    let (start, end) = if (target.1, target.0) < (pos.1, pos.0) { (target, pos) } else { (pos, target) };
+
+    match motion.kind() {
+        MotionKind::Inclusive => (start, (cmp::min(end.0 + 1, line_len(rows, end.1)), end.1)),
+        _ => (start, end),
+    }
+}
+
+
+//cw changes to the end of the word under the cursor instead of up to the next one
+pub fn change_word_range(rows: &[Row], pos: (usize, usize), count: Option<usize>) -> ((usize, usize), (usize, usize)) {
+    let class = class_at(rows, pos);
+    if class == CharClass::Blank {
+        return motion_range(rows, pos, Motion::WordStart, count);
+    }
+
+    let mut end = pos;
+    while let Some(next) = next_pos(rows, end).filter(|p| p.1 == pos.1 && class_at(rows, *p) == class) {
+        end = next;
+    }
+    let end = (1..count.unwrap_or(1)).fold(end, |p, _| word_end(rows, p));
+    (pos, (cmp::min(end.0 + 1, line_len(rows, end.1)), end.1))
+}
+
+
+//the text an operator works on
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Span {
+    Chars((usize, usize), (usize, usize)), //start and exclusive end
+    Lines(usize, usize),                   //first and last line
+}
+
+
+//start and exclusive end of the chars of span
+pub fn span_range(rows: &[Row], span: Span) -> ((usize, usize), (usize, usize)) {
+    match span {
+        Span::Chars(start, end) => (start, end),
+        Span::Lines(y0, y1) => ((0, y0), (line_len(rows, y1), y1)),
+    }
+}
+
+
+//the text of obj at pos. a count takes that many words or paragraphs, or the count-th
+//enclosing pair. None when there is no such object
+pub fn object_span(rows: &[Row], pos: (usize, usize), obj: TextObject, count: Option<usize>) -> Option<Span> {
+    let n = count.unwrap_or(1);
+
+    match obj.kind {
+        ObjectKind::Word => Some(word_object(rows, pos, obj.around, n)),
+        ObjectKind::Pair(open, close) => pair_object(rows, pos, (open, close), obj.around, n),
+        ObjectKind::Quote(quote) => quote_object(rows, pos, quote, obj.around),
+        ObjectKind::Paragraph => paragraph_object(rows, pos.1, obj.around, n),
+        ObjectKind::Tag => tag_object(rows, pos, obj.around, n),
+    }
+}
+
+
+fn char_at(rows: &[Row], (x, y): (usize, usize)) -> Option<char> {
+    rows.get(y).filter(|r| x < r.len()).and_then(|r| r.char_at_checked(x))
+}
+
+
+//start and exclusive end of the chars of one class around x
+fn class_run(rows: &[Row], (x, y): (usize, usize)) -> (usize, usize) {
+    let len = line_len(rows, y);
+    let class = class_at(rows, (x, y));
+
+    let mut start = x;
+    while start > 0 && class_at(rows, (start - 1, y)) == class {
+        start -= 1;
+    }
+    let mut end = x;
+    while end < len && class_at(rows, (end, y)) == class {
+        end += 1;
+    }
+    (start, end)
+}
+
+
+//iw takes runs of word, punctuation or blank chars. aw takes a word with the blanks after it, or
+//before it when there are none after it
+fn word_object(rows: &[Row], (x, y): (usize, usize), around: bool, n: usize) -> Span {
+    let len = line_len(rows, y);
+    let x = cmp::min(x, len);
+    let on_blank = class_at(rows, (x, y)) == CharClass::Blank;
+
+    let (mut start, mut end) = class_run(rows, (x, y));
+    let runs = if around { 2 * n } else { n };
+    for _ in 1..runs {
+        if end < len {
+            end = class_run(rows, (end, y)).1;
+        }
+    }
+
+    //the indentation stays
+    let ends_with_word = end > 0 && class_at(rows, (end - 1, y)) != CharClass::Blank;
+    if around && !on_blank && ends_with_word && start > 0 {
+        let blanks = class_run(rows, (start - 1, y)).0;
+        if blanks > 0 {
+            start = blanks;
+        }
+    }
+    Span::Chars((start, y), (end, y))
+}
+
+
+//the string literal each char is in, numbered through the text. "strings" go on over lines,
+//'c' and 'strings' end on their line while the quote of a lifetime starts none
+fn string_ids(rows: &[Row]) -> Vec<Vec<Option<usize>>> {
+    let mut ids = Vec::with_capacity(rows.len());
+    let mut next = 0;
+    let mut current = None;
+
+    for row in rows {
+        let chars: Vec<char> = row.buffer().chars().collect();
+        let mut line = vec![None; chars.len()];
+        let mut x = 0;
+
+        while x < chars.len() {
+            match (current, chars[x]) {
+                (Some(_), '\\') => {
+                    line[x] = current;
+                    if x + 1 < chars.len() {
+                        line[x + 1] = current;
+                    }
+                    x += 1;
+                }
+                (Some(_), '"') => {
+                    line[x] = current;
+                    current = None;
+                }
+                (Some(_), _) => line[x] = current,
+                (None, '"') => {
+                    current = Some(next);
+                    next += 1;
+                    line[x] = current;
+                }
+                (None, '\'') => {
+                    let end = match chars.get(x + 1) {
+                        Some('\\') => (x + 3..chars.len()).find(|i| chars[*i] == '\''),
+                        Some(_) if chars.get(x + 2) == Some(&'\'') => Some(x + 2),
+                        Some(_) if opens_string(&chars, x) => closing_quote(&chars, x + 1),
+                        _ => None,
+                    };
+                    if let Some(end) = end {
+                        line[x..=end].iter_mut().for_each(|id| *id = Some(next));
+                        next += 1;
+                        x = end;
+                    }
+                }
+                (None, _) => {}
+            }
+            x += 1;
+        }
+        ids.push(line);
+    }
+    ids
+}
+
+
+//whether the ' at x starts a string. the one of a lifetime or a label like &'a, <'a, 'a: or 'a,
+//does not, neither does the one in a word like don't
+fn opens_string(chars: &[char], x: usize) -> bool {
+    let before = x.checked_sub(1).map(|i| chars[i]);
+    let ident = chars[x + 1..].iter().take_while(|c| c.is_alphanumeric() || **c == '_').count();
+    let after = chars.get(x + 1 + ident);
+
+    let lifetime = ident > 0 && (matches!(before, Some('&' | '<')) || matches!(after, None | Some('>' | ',' | ':' | '+' | ';')));
+    !lifetime && !before.is_some_and(|c| c.is_alphanumeric())
+}
+
+
+//the ' which ends a single quoted string from x, None when it does not end on the line
+fn closing_quote(chars: &[char], mut x: usize) -> Option<usize> {
+    while x < chars.len() {
+        match chars[x] {
+            '\\' => x += 2,
+            '\'' => return Some(x),
+            _ => x += 1,
+        }
+    }
+    None
+}
+
+
+//the open and close bracket around pos. only brackets in the same string as pos count, or the
+//ones outside of strings when pos is outside of them too
+fn find_pair(
+    rows: &[Row],
+    strings: &[Vec<Option<usize>>],
+    pos: (usize, usize),
+    (open, close): (char, char),
+    string: Option<usize>,
+    n: usize,
+) -> Option<((usize, usize), (usize, usize))> {
+    let string_at = |(x, y): (usize, usize)| strings.get(y).and_then(|l| l.get(x)).copied().flatten();
+    let bracket = |p: (usize, usize)| char_at(rows, p).filter(|_| string_at(p) == string);
+
+    //the cursor on a closing bracket is inside of it
+    let mut start = pos;
+    let mut from = pos;
+    for i in 0..n {
+        let mut depth = 0;
+        let mut p = from;
+        start = loop {
+            match bracket(p) {
+                Some(c) if c == open && depth == 0 => break p,
+                Some(c) if c == open => depth -= 1,
+                Some(c) if c == close && !(i == 0 && p == pos) => depth += 1,
+                _ => {}
+            }
+            p = prev_pos(rows, p)?;
+        };
+        if i + 1 < n {
+            from = prev_pos(rows, start)?;
+        }
+    }
+
+    let mut depth = 0;
+    let mut p = start;
+    let end = loop {
+        p = next_pos(rows, p)?;
+        match bracket(p) {
+            Some(c) if c == close && depth == 0 => break p,
+            Some(c) if c == close => depth -= 1,
+            Some(c) if c == open => depth += 1,
+            _ => {}
+        }
+    };
+    Some((start, end))
+}
+
+
+//a( takes the brackets too. i( on brackets which have lines of their own takes the lines between them
+fn pair_object(rows: &[Row], pos: (usize, usize), pair: (char, char), around: bool, n: usize) -> Option<Span> {
+    let strings = string_ids(rows);
+    let string = strings.get(pos.1).and_then(|l| l.get(pos.0)).copied().flatten();
+
+    //in a string without brackets the ones around the string are taken
+    let (start, end) = find_pair(rows, &strings, pos, pair, string, n)
+        .or_else(|| string.and_then(|_| find_pair(rows, &strings, pos, pair, None, n)))?;
+
+    if around {
+        return Some(Span::Chars(start, (end.0 + 1, end.1)));
+    }
+    if start.0 + 1 == line_len(rows, start.1) && end.1 > start.1 + 1 && end.0 <= first_non_blank(rows, end.1) {
+        return Some(Span::Lines(start.1 + 1, end.1 - 1));
+    }
+    Some(Span::Chars((start.0 + 1, start.1), end))
+}
+
+
+//the quoted text on the line of pos which pos is in, or else the next one. a" takes the blanks
+//after the quotes, or before them when there are none after
+fn quote_object(rows: &[Row], (x, y): (usize, usize), quote: char, around: bool) -> Option<Span> {
+    let chars: Vec<char> = rows.get(y)?.buffer().chars().collect();
+
+    let mut quotes = vec![];
+    let mut escaped = false;
+    for (i, c) in chars.iter().enumerate() {
+        if *c == quote && !escaped {
+            quotes.push(i);
+        }
+        escaped = *c == '\\' && !escaped;
+    }
+
+    let (start, end) = quotes.chunks_exact(2).map(|p| (p[0], p[1])).find(|(_, end)| x <= *end)?;
+    if !around {
+        return Some(Span::Chars((start + 1, y), (end, y)));
+    }
+
+    let blank = |i: usize| chars.get(i).is_some_and(|c| c.is_whitespace());
+    let mut to = end + 1;
+    while blank(to) {
+        to += 1;
+    }
+    let mut from = start;
+    if to == end + 1 {
+        while from > 0 && blank(from - 1) {
+            from -= 1;
+        }
+        //the indentation stays
+        if from == 0 {
+            from = start;
+        }
+    }
+    Some(Span::Chars((from, y), (to, y)))
+}
+
+
+//paragraphs are lines which are all empty or all not, as for move_cursor_paragraph. ap takes the
+//empty lines after the paragraph, or before it when there are none after
+fn paragraph_object(rows: &[Row], y: usize, around: bool, n: usize) -> Option<Span> {
+    if rows.is_empty() {
+        return None;
+    }
+
+    let y = cmp::min(y, rows.len() - 1);
+    let empty = |y: usize| line_len(rows, y) == 0;
+    let run_end = |y: usize| (y..rows.len()).take_while(|l| empty(*l) == empty(y)).last().unwrap_or(y);
+    let run_start = |y: usize| (0..=y).rev().take_while(|l| empty(*l) == empty(y)).last().unwrap_or(y);
+
+    let mut start = run_start(y);
+    let mut end = run_end(y);
+    let runs = if around { 2 * n } else { n };
+    for _ in 1..runs {
+        if end + 1 < rows.len() {
+            end = run_end(end + 1);
+        }
+    }
+
+    if around && !empty(y) && !empty(end) && start > 0 {
+        start = run_start(start - 1);
+    }
+    Some(Span::Lines(start, end))
+}
+
+
+//<name ...> or </name> with its start and exclusive end
+struct Tag {
+    name: String,
+    closing: bool,
+    start: (usize, usize),
+    end: (usize, usize),
+}
+
+
+//the tag which starts at pos, None for <!-- -->, self-closing tags and < which are no tag
+fn read_tag(rows: &[Row], start: (usize, usize)) -> Option<Tag> {
+    let mut p = next_pos(rows, start)?;
+    let closing = char_at(rows, p) == Some('/');
+    if closing {
+        p = next_pos(rows, p)?;
+    }
+
+    let mut name = String::new();
+    while let Some(c) = char_at(rows, p).filter(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | ':' | '.')) {
+        name.push(c);
+        p = next_pos(rows, p)?;
+    }
+    if name.is_empty() {
+        return None;
+    }
+
+    //attributes may go on over lines
+    let mut prev = None;
+    loop {
+        match char_at(rows, p) {
+            Some('>') => break,
+            Some('<') => return None,
+            c => prev = c,
+        }
+        p = next_pos(rows, p)?;
+    }
+    if prev == Some('/') {
+        return None;
+    }
+
+    Some(Tag { name, closing, start, end: (p.0 + 1, p.1) })
+}
+
+
+fn tags(rows: &[Row]) -> Vec<Tag> {
+    let mut tags = vec![];
+    let mut pos = Some((0, 0));
+
+    while let Some(p) = pos {
+        if let Some(tag) = Some(p).filter(|p| char_at(rows, *p) == Some('<')).and_then(|p| read_tag(rows, p)) {
+            pos = Some(tag.end);
+            tags.push(tag);
+        } else {
+            pos = next_pos(rows, p);
+        }
+    }
+    tags
+}
+
+
+//it takes what is between the tags around pos, at the tags too
+fn tag_object(rows: &[Row], (x, y): (usize, usize), around: bool, n: usize) -> Option<Span> {
+    let before = |(ax, ay): (usize, usize), (bx, by): (usize, usize)| (ay, ax) < (by, bx);
+    let tags = tags(rows);
+    let mut open: Vec<&Tag> = vec![];
+    let mut found = 0;
+
+    //pairs are complete at their closing tag, so the ones around pos come inner first
+    for tag in &tags {
+        if !tag.closing {
+            open.push(tag);
+            continue;
+        }
+
+        //tags opened after the last one of this name are never closed, like <br> or <li>
+        let i = match open.iter().rposition(|t| t.name == tag.name) {
+            Some(i) => i,
+            None => continue,
+        };
+        let start = open[i];
+        open.truncate(i);
+
+        if !before((x, y), start.start) && before((x, y), tag.end) {
+            found += 1;
+            if found == n {
+                return Some(if around {
+                    Span::Chars(start.start, tag.end)
+                } else {
+                    Span::Chars(start.end, tag.start)
+                });
+            }
+        }
+    }
+    None
+}
+
+
+//the unnamed register of yanked and deleted text
+#[derive(Default)]
+pub struct Register {
+    pub text: String,
+    pub lines: bool, //put below or above the line instead of inside it
+}
+
+
+//state of the vi keymap: the mode, the command being typed and what . repeats
+pub struct Vi {
+    pub mode: Mode,
+    pub keys: Vec<InputSeq>,           //of the command typed so far
+    pub register: Register,
+    pub last_change: Vec<InputSeq>,    //keys of the last change, for .
+    pub recording: Option<Vec<InputSeq>>, //keys of a change which goes on in insert mode
+    pub replaying: bool,
+}
+
+
+impl Vi {
+    pub fn new() -> Self {
+        Self {
+            mode: Mode::Normal,
+            keys: vec![],
+            register: Register::default(),
+            last_change: vec![],
+            recording: None,
+            replaying: false,
+        }
+    }
+
+    pub fn chars(&self) -> Vec<char> {
+        self.keys.iter().filter_map(key_char).collect()
+    }
+
+    //the last change with its count replaced by count, the one after an operator too: d2w then 3. deletes 3 words
+    pub fn repeat_keys(&self, count: Option<usize>) -> Vec<InputSeq> {
+        let keys = &self.last_change;
+        let count = match count {
+            Some(count) => count,
+            None => return keys.clone(),
+        };
+
+        let is_digit = |k: &InputSeq, first: bool| match k.key {
+            KeySeq::Key(b'0') => !first,
+            KeySeq::Key(b'1'..=b'9') => true,
+            _ => false,
+        };
+        let count_len = |from: usize| {
+            keys[from..].iter().enumerate().take_while(|(i, k)| is_digit(k, *i == 0)).count()
+        };
+
+        let op = count_len(0);
+        let mut inner = 0;
+        if keys.get(op).is_some_and(|k| matches!(k.key, KeySeq::Key(b'd' | b'c' | b'y'))) {
+            inner = count_len(op + 1);
+        }
+
+        let mut repeated: Vec<InputSeq> = count.to_string().bytes().map(|b| InputSeq::new(KeySeq::Key(b))).collect();
+        if let Some(k) = keys.get(op) {
+            repeated.push(k.clone());
+            repeated.extend(keys[op + 1 + inner..].iter().cloned());
+        }
+        repeated
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(s: &str) -> Vec<char> {
+        s.chars().collect()
+    }
+
+    fn seqs(s: &str) -> Vec<InputSeq> {
+        s.bytes().map(|b| InputSeq::new(KeySeq::Key(b))).collect()
+    }
+
+    fn lines(text: &[&str]) -> Vec<Row> {
+        text.iter().map(|l| Row::new(*l, 4).unwrap()).collect()
+    }
+
+    fn pair(c: char, around: bool) -> TextObject {
+        let kind = match c {
+            '(' => ObjectKind::Pair('(', ')'),
+            '{' => ObjectKind::Pair('{', '}'),
+            _ => ObjectKind::Quote(c),
+        };
+        TextObject { kind, around }
+    }
+
+    fn repeat(last_change: &str, count: Option<usize>) -> String {
+        let mut vi = Vi::new();
+        vi.last_change = seqs(last_change);
+        vi.repeat_keys(count).iter().filter_map(key_char).collect()
+    }
+
+    #[test]
+    fn parse_counts_multiply() {
+        let cmd = Command::Operate(Operator::Delete, Target::Motion(Motion::WordStart));
+        assert_eq!(parse(&chars("2d3w"), false), Parse::Done(Some(6), cmd));
+        assert_eq!(parse(&chars("d3w"), false), Parse::Done(Some(3), cmd));
+        assert_eq!(parse(&chars("dw"), false), Parse::Done(None, cmd));
+        assert_eq!(parse(&chars("10j"), false), Parse::Done(Some(10), Command::Move(Motion::Down)));
+    }
+
+    #[test]
+    fn parse_zero_is_a_motion() {
+        assert_eq!(parse(&chars("0"), false), Parse::Done(None, Command::Move(Motion::LineStart)));
+        assert_eq!(parse(&chars("d0"), false), Parse::Done(None, Command::Operate(Operator::Delete, Target::Motion(Motion::LineStart))));
+    }
+
+    #[test]
+    fn parse_lines_and_objects() {
+        assert_eq!(parse(&chars("dd"), false), Parse::Done(None, Command::Operate(Operator::Delete, Target::Lines)));
+        assert_eq!(parse(&chars("3yy"), false), Parse::Done(Some(3), Command::Operate(Operator::Yank, Target::Lines)));
+
+        let obj = TextObject { kind: ObjectKind::Pair('(', ')'), around: false };
+        assert_eq!(parse(&chars("ci("), false), Parse::Done(None, Command::Operate(Operator::Change, Target::Object(obj))));
+        assert_eq!(parse(&chars("ib"), true), Parse::Done(None, Command::SelectObject(obj)));
+    }
+
+    #[test]
+    fn parse_pending_and_invalid() {
+        assert_eq!(parse(&chars(""), false), Parse::Pending);
+        assert_eq!(parse(&chars("2"), false), Parse::Pending);
+        assert_eq!(parse(&chars("d"), false), Parse::Pending);
+        assert_eq!(parse(&chars("d2"), false), Parse::Pending);
+        assert_eq!(parse(&chars("g"), false), Parse::Pending);
+        assert_eq!(parse(&chars("di"), false), Parse::Pending);
+        assert_eq!(parse(&chars("dq"), false), Parse::Invalid);
+        assert_eq!(parse(&chars("gx"), false), Parse::Invalid);
+        assert_eq!(parse(&chars("diq"), false), Parse::Invalid);
+    }
+
+    #[test]
+    fn parse_visual() {
+        assert_eq!(parse(&chars("d"), true), Parse::Done(None, Command::Operate(Operator::Delete, Target::Selection)));
+        assert_eq!(parse(&chars("o"), true), Parse::Done(None, Command::SwapAnchor));
+        assert_eq!(parse(&chars("u"), false), Parse::Done(None, Command::Undo));
+    }
+
+    #[test]
+    fn repeat_replaces_the_count() {
+        assert_eq!(repeat("x", None), "x");
+        assert_eq!(repeat("x", Some(3)), "3x");
+        assert_eq!(repeat("2dd", None), "2dd");
+        assert_eq!(repeat("2dd", Some(5)), "5dd");
+        assert_eq!(repeat("d2w", Some(3)), "3dw");
+        assert_eq!(repeat("2d3w", Some(4)), "4dw");
+        assert_eq!(repeat("d0", Some(2)), "2d0");
+        assert_eq!(repeat("i12", Some(3)), "3i12");
+    }
+
+    #[test]
+    fn inner_and_around_pair() {
+        let rows = lines(&["f(a, (b), c)"]);
+        assert_eq!(object_span(&rows, (2, 0), pair('(', false), None), Some(Span::Chars((2, 0), (11, 0))));
+        assert_eq!(object_span(&rows, (2, 0), pair('(', true), None), Some(Span::Chars((1, 0), (12, 0))));
+        assert_eq!(object_span(&rows, (6, 0), pair('(', false), None), Some(Span::Chars((6, 0), (7, 0))));
+        assert_eq!(object_span(&rows, (6, 0), pair('(', false), Some(2)), Some(Span::Chars((2, 0), (11, 0))));
+        //on the closing bracket
+        assert_eq!(object_span(&rows, (7, 0), pair('(', false), None), Some(Span::Chars((6, 0), (7, 0))));
+        assert_eq!(object_span(&rows, (0, 0), pair('(', false), None), None);
+    }
+
+    #[test]
+    fn inner_empty_pair() {
+        let rows = lines(&["f()"]);
+        assert_eq!(object_span(&rows, (1, 0), pair('(', false), None), Some(Span::Chars((2, 0), (2, 0))));
+        assert_eq!(object_span(&rows, (2, 0), pair('(', true), None), Some(Span::Chars((1, 0), (3, 0))));
+    }
+
+    #[test]
+    fn inner_block_takes_lines() {
+        let rows = lines(&["fn f() {", "    a();", "    b();", "}"]);
+        assert_eq!(object_span(&rows, (4, 1), pair('{', false), None), Some(Span::Lines(1, 2)));
+        assert_eq!(object_span(&rows, (5, 1), pair('(', false), None), Some(Span::Chars((6, 1), (6, 1))));
+    }
+
+    #[test]
+    fn find_pair_skips_strings() {
+        let rows = lines(&[r#"f(")", '(', 'a(b', x)"#]);
+        let strings = string_ids(&rows);
+        assert_eq!(find_pair(&rows, &strings, (19, 0), ('(', ')'), None, 1), Some(((1, 0), (20, 0))));
+
+        //inside a string only the brackets of the string count
+        let rows = lines(&[r#"f("a(b)c")"#]);
+        let strings = string_ids(&rows);
+        let string = strings[0][5];
+        assert!(string.is_some());
+        assert_eq!(find_pair(&rows, &strings, (5, 0), ('(', ')'), string, 1), Some(((4, 0), (6, 0))));
+        assert_eq!(find_pair(&rows, &strings, (5, 0), ('(', ')'), None, 1), Some(((1, 0), (9, 0))));
+    }
+
+    #[test]
+    fn string_ids_of_single_quotes() {
+        let ids = string_ids(&lines(&["x = 'a(b' + 'c'"]));
+        assert_eq!(ids[0][4], ids[0][8]);
+        assert!(ids[0][6].is_some());
+        assert_eq!(ids[0][12], ids[0][14]);
+        assert_ne!(ids[0][4], ids[0][12]);
+        assert_eq!(ids[0][10], None);
+
+        //lifetimes, labels and apostrophes start no string
+        let ids = string_ids(&lines(&["fn f<'a>(x: &'a str) { 'l: loop { break 'l; } } // don't (x)"]));
+        assert!(ids[0].iter().all(|id| id.is_none()));
+    }
+}
@@ -0,0 +1,759 @@
+use std::cmp;
+
+use unicode_width::UnicodeWidthChar;
+
+use crate::config::Settings;
+use crate::row::Row;
+use crate::status::{CursorDir, Status, TextBuffer};
+
+
+//smallest window we allow: one text line plus its status line
+const MIN_HEIGHT: usize = 2;
+const MIN_WIDTH: usize = 8;
+
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum SplitDir {
+    Horizontal, //windows stacked on top of each other
+    Vertical,   //windows side by side, with a separator column between
+}
+
+
+#[derive(Copy, Clone, PartialEq, Default, Debug)]
+pub struct Rect {
+    pub top: usize,
+    pub left: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+
+impl Rect {
+    fn bottom(&self) -> usize {
+        self.top + self.height
+    }
+
+    fn right(&self) -> usize {
+        self.left + self.width
+    }
+}
+
+
+//a view into one of the text buffers, with its own cursor, scroll and status line
+pub struct Window {
+    pub buf_idx: usize,
+    pub row_off: usize,
+    pub col_off: usize,
+    pub rx: usize,
+    pub rect: Rect,
+    pub status_bar: Status,
+    cursor: (usize, usize), //cursor of the buffer while the window is not focused
+    dirty_start: Option<usize>,
+    screen_cursor: (usize, usize), //row and column of the cursor inside the window
+    wrap: bool,
+    gutter: usize, //columns taken by line numbers
+    scroll_margin: usize,
+}
+
+
+impl Window {
+    pub fn new(buf: &TextBuffer, buf_idx: usize, buf_pos: (usize, usize)) -> Self {
+        Self {
+            buf_idx,
+            row_off: 0,
+            col_off: 0,
+            rx: 0,
+            rect: Rect::default(),
+            status_bar: Status::from_buffer(buf, buf_pos),
+            cursor: buf.cursor(),
+            dirty_start: Some(0),
+            screen_cursor: (0, 0),
+            wrap: false,
+            gutter: 0,
+            scroll_margin: 0,
+        }
+    }
+
+    //follow the display settings of the buffer shown, num_rows sizes the line number gutter
+    pub fn configure(&mut self, settings: &Settings, num_rows: usize) {
+        let gutter = if settings.line_numbers {
+            cmp::max(num_rows.to_string().len(), 3) + 1
+        } else {
+            0
+        };
+
+        if gutter != self.gutter || settings.soft_wrap != self.wrap {
+            self.gutter = gutter;
+            self.wrap = settings.soft_wrap;
+            self.col_off = 0;
+            self.set_dirty_start(self.row_off);
+        }
+
+        self.scroll_margin = settings.scroll_margin;
+    }
+
+    pub fn wraps(&self) -> bool {
+        self.wrap
+    }
+
+    pub fn gutter(&self) -> usize {
+        self.gutter
+    }
+
+    //columns left for the text
+    pub fn text_cols(&self) -> usize {
+        self.cols().saturating_sub(self.gutter)
+    }
+
+    pub fn screen_cursor(&self) -> (usize, usize) {
+        self.screen_cursor
+    }
+
+    //screen lines the row takes, always one unless soft wrap is on
+    pub fn row_height(&self, row: &Row) -> usize {
+        if self.wrap {
+            wrap_pos(row, self.text_cols(), usize::MAX).0 + 1
+        } else {
+            1
+        }
+    }
+
+    //number of text rows, the last line of the window is the status bar
+    pub fn rows(&self) -> usize {
+        self.rect.height.saturating_sub(1)
+    }
+
+    pub fn cols(&self) -> usize {
+        self.rect.width
+    }
+
+    pub fn cursor(&self) -> (usize, usize) {
+        self.cursor
+    }
+
+    pub fn set_cursor(&mut self, cursor: (usize, usize)) {
+        self.cursor = cursor;
+    }
+
+    pub fn dirty_start(&self) -> Option<usize> {
+        self.dirty_start
+    }
+
+    pub fn set_dirty_start(&mut self, start: usize) {
+        if let Some(x) = self.dirty_start {
+            if x < start {
+                return
+            }
+        }
+
+        self.dirty_start = Some(start);
+    }
+
+    pub fn redraw_all(&mut self) {
+        self.set_dirty_start(self.row_off);
+        self.status_bar.redraw = true;
+    }
+
+    pub fn reset_scroll(&mut self) {
+        self.set_dirty_start(0);
+        self.row_off = 0;
+        self.col_off = 0;
+    }
+
+    pub fn after_render(&mut self) {
+        self.dirty_start = None;
+        self.status_bar.redraw = false;
+    }
+
+    pub fn do_scroll(&mut self, rows: &[Row], (cx, cy): (usize, usize)) {
+        let prev_rowoff = self.row_off;
+        let prev_coloff = self.col_off;
+
+        if cy < rows.len() {
+            self.rx = rows[cy].rx_from_cx(cx);
+        } else {
+            self.rx = 0;
+        }
+
+        let margin = cmp::min(self.scroll_margin, self.rows().saturating_sub(1) / 2);
+
+        if cy < self.row_off + margin {
+            self.row_off = cy.saturating_sub(margin);
+        }
+
+        if self.wrap {
+            self.col_off = 0;
+            //scroll down until the lines from the top to the cursor, and the margin below it, fit
+            while self.row_off < cy && self.lines_until(rows, cy) + margin > self.rows() {
+                self.row_off += 1;
+            }
+        } else {
+            if cy + margin >= self.row_off + self.rows() {
+                self.row_off = cy + margin + 1 - self.rows();
+            }
+
+            if self.rx < self.col_off {
+                self.col_off = self.rx;
+            }
+
+            if self.rx >= self.col_off + self.text_cols() {
+                self.col_off = next_coloff(self.rx + 1 - self.text_cols(), &rows[cy]);
+            }
+        }
+
+        self.screen_cursor = if self.wrap {
+            let col = rows.get(cy).map(|r| wrap_pos(r, self.text_cols(), self.rx).1).unwrap_or(0);
+            (self.lines_until(rows, cy) - 1, self.gutter + col)
+        } else {
+            (cy - self.row_off, self.gutter + self.rx - self.col_off)
+        };
+
+        if prev_rowoff != self.row_off || prev_coloff != self.col_off {
+            self.set_dirty_start(self.row_off);
+        }
+    }
+
+    //buffer position (cx, cy) of the cell at line y and column x of the window. cells after the end
+    //of a line or below the last one give the nearest position
+    pub fn text_pos(&self, rows: &[Row], y: usize, x: usize) -> (usize, usize) {
+        let x = x.saturating_sub(self.gutter);
+        if rows.is_empty() {
+            return (0, 0);
+        }
+
+        if !self.wrap {
+            let cy = cmp::min(self.row_off + y, rows.len() - 1);
+            return (rows[cy].cx_from_rx(self.col_off + x), cy);
+        }
+
+        let mut line = 0;
+        for (cy, row) in rows.iter().enumerate().skip(self.row_off) {
+            let height = self.row_height(row);
+            if y < line + height {
+                return (row.cx_from_rx(wrap_rx(row, self.text_cols(), y - line, x)), cy);
+            }
+            line += height;
+        }
+
+        let last = rows.len() - 1;
+        (rows[last].len(), last)
+    }
+
+    //screen lines from the top of the window down to the line of the cursor in row cy
+    fn lines_until(&self, rows: &[Row], cy: usize) -> usize {
+        let above: usize = rows[self.row_off.min(rows.len())..cy.min(rows.len())]
+            .iter()
+            .map(|r| self.row_height(r))
+            .sum();
+        let line = rows.get(cy).map(|r| wrap_pos(r, self.text_cols(), self.rx).0).unwrap_or(0);
+        above + line + 1
+    }
+}
+
+
+//screen line and column where render column rx of the row is drawn when it wraps at width.
+//A cursor after a row filling the last line exactly goes to the start of one more line
+pub fn wrap_pos(row: &Row, width: usize, rx: usize) -> (usize, usize) {
+    let (mut line, mut col, mut at) = (0, 0, 0);
+
+    for c in row.render_text().chars() {
+        let w = c.width_cjk().unwrap_or(1);
+        if col + w > width && col > 0 {
+            line += 1;
+            col = 0;
+        }
+
+        if at >= rx {
+            return (line, col);
+        }
+
+        col += w;
+        at += w;
+    }
+
+    if col >= width {
+        (line + 1, 0)
+    } else {
+        (line, col)
+    }
+}
+
+
+//render column drawn at line and col of the row wrapping at width, the inverse of wrap_pos. past the
+//end of a line it is the last char of that line
+fn wrap_rx(row: &Row, width: usize, line: usize, col: usize) -> usize {
+    let (mut l, mut c, mut at, mut prev) = (0, 0, 0, 0);
+
+    for ch in row.render_text().chars() {
+        let w = ch.width_cjk().unwrap_or(1);
+        if c + w > width && c > 0 {
+            if l == line {
+                return prev;
+            }
+            l += 1;
+            c = 0;
+        }
+
+        if l == line && col < c + w {
+            return at;
+        }
+
+        prev = at;
+        c += w;
+        at += w;
+    }
+
+    at
+}
+
+
+fn next_coloff(stop: usize, row: &Row) -> usize {
+    let mut col_off = 0;
+
+    for x in row.render_text().chars() {
+        col_off += x.width_cjk().unwrap_or(1);
+        if col_off >= stop {
+            break;
+        }
+    }
+
+    col_off
+}
+
+
+enum Layout {
+    Leaf(usize),
+    Split {
+        dir: SplitDir,
+        first: usize, //size of the first child along the split direction
+        children: Box<(Layout, Layout)>,
+    },
+}
+
+
+impl Layout {
+    fn arrange(&mut self, rect: Rect, wins: &mut [Window], seps: &mut Vec<Rect>) {
+        match self {
+            Layout::Leaf(idx) => wins[*idx].rect = rect,
+            Layout::Split { dir: SplitDir::Horizontal, first, children } => {
+                *first = clamp_size(*first, rect.height, MIN_HEIGHT, 0);
+                let top = Rect { height: *first, ..rect };
+                let bottom = Rect { top: rect.top + *first, height: rect.height.saturating_sub(*first), ..rect };
+                children.0.arrange(top, wins, seps);
+                children.1.arrange(bottom, wins, seps);
+            }
+            Layout::Split { dir: SplitDir::Vertical, first, children } => {
+                *first = clamp_size(*first, rect.width, MIN_WIDTH, 1);
+                let left = Rect { width: *first, ..rect };
+                let right = Rect {
+                    left: rect.left + *first + 1,
+                    width: rect.width.saturating_sub(*first + 1),
+                    ..rect
+                };
+                seps.push(Rect { left: rect.left + *first, width: 1, ..rect });
+                children.0.arrange(left, wins, seps);
+                children.1.arrange(right, wins, seps);
+            }
+        }
+    }
+
+    fn first_leaf(&self) -> usize {
+        match self {
+            Layout::Leaf(idx) => *idx,
+            Layout::Split { children, .. } => children.0.first_leaf(),
+        }
+    }
+
+    fn contains(&self, target: usize) -> bool {
+        match self {
+            Layout::Leaf(idx) => *idx == target,
+            Layout::Split { children, .. } => children.0.contains(target) || children.1.contains(target),
+        }
+    }
+
+    fn split(&mut self, target: usize, new: usize, dir: SplitDir, size: usize) -> bool {
+        match self {
+            Layout::Leaf(idx) if *idx == target => {
+                *self = Layout::Split {
+                    dir,
+                    first: size,
+                    children: Box::new((Layout::Leaf(target), Layout::Leaf(new))),
+                };
+                true
+            }
+            Layout::Leaf(_) => false,
+            Layout::Split { children, .. } => {
+                children.0.split(target, new, dir, size) || children.1.split(target, new, dir, size)
+            }
+        }
+    }
+
+    //replace the parent of target by target's sibling, returns the sibling's first window
+    fn remove(&mut self, target: usize) -> Option<usize> {
+        let sibling = match self {
+            Layout::Leaf(_) => return None,
+            Layout::Split { children, .. } => match (&children.0, &children.1) {
+                (Layout::Leaf(idx), _) if *idx == target => 1,
+                (_, Layout::Leaf(idx)) if *idx == target => 0,
+                _ => return children.0.remove(target).or_else(|| children.1.remove(target)),
+            },
+        };
+
+        let placeholder = Layout::Leaf(target);
+        if let Layout::Split { children, .. } = std::mem::replace(self, placeholder) {
+            let (first, second) = *children;
+            *self = if sibling == 0 { first } else { second };
+        }
+
+        Some(self.first_leaf())
+    }
+
+    //window indices above removed shift down by one
+    fn renumber(&mut self, removed: usize) {
+        match self {
+            Layout::Leaf(idx) => {
+                if *idx > removed {
+                    *idx -= 1;
+                }
+            }
+            Layout::Split { children, .. } => {
+                children.0.renumber(removed);
+                children.1.renumber(removed);
+            }
+        }
+    }
+
+    //grow (or shrink with negative delta) the innermost split along dir which holds target
+    fn resize(&mut self, target: usize, along: SplitDir, delta: isize) -> bool {
+        match self {
+            Layout::Leaf(_) => false,
+            Layout::Split { dir, first, children } => {
+                let in_first = children.0.contains(target);
+                let inner = if in_first {
+                    children.0.resize(target, along, delta)
+                } else if children.1.contains(target) {
+                    children.1.resize(target, along, delta)
+                } else {
+                    return false;
+                };
+
+                if inner || *dir != along {
+                    return inner;
+                }
+
+                let delta = if in_first { delta } else { -delta };
+                *first = if delta < 0 {
+                    first.saturating_sub(delta.unsigned_abs())
+                } else {
+                    *first + delta as usize
+                };
+                true
+            }
+        }
+    }
+}
+
+
+fn clamp_size(size: usize, total: usize, min: usize, sep: usize) -> usize {
+    if total < min * 2 + sep {
+        return cmp::max(total.saturating_sub(sep) / 2, 1);
+    }
+
+    cmp::min(cmp::max(size, min), total - min - sep)
+}
+
+
+//all the windows on the screen and how they are laid out
+pub struct Windows {
+    wins: Vec<Window>,
+    layout: Layout,
+    focus: usize,
+    separators: Vec<Rect>,
+}
+
+
+impl Windows {
+    pub fn new(win: Window) -> Self {
+        Self {
+            wins: vec![win],
+            layout: Layout::Leaf(0),
+            focus: 0,
+            separators: vec![],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.wins.len()
+    }
+
+    pub fn focus(&self) -> usize {
+        self.focus
+    }
+
+    pub fn focused(&self) -> &Window {
+        &self.wins[self.focus]
+    }
+
+    pub fn focused_mut(&mut self) -> &mut Window {
+        &mut self.wins[self.focus]
+    }
+
+    //the window at a cell of the screen, with the line and column of the cell inside it
+    pub fn at(&self, row: usize, col: usize) -> Option<(usize, usize, usize)> {
+        self.wins.iter().enumerate().find_map(|(i, w)| {
+            let r = w.rect;
+            if r.top <= row && row < r.bottom() && r.left <= col && col < r.right() {
+                Some((i, row - r.top, col - r.left))
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Window> {
+        self.wins.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Window> {
+        self.wins.iter_mut()
+    }
+
+    pub fn separators(&self) -> &[Rect] {
+        &self.separators
+    }
+
+    //lay the windows out inside area, returns true when any window moved or was resized
+    pub fn arrange(&mut self, area: Rect) -> bool {
+        let before: Vec<Rect> = self.wins.iter().map(|w| w.rect).collect();
+
+        self.separators.clear();
+        self.layout.arrange(area, &mut self.wins, &mut self.separators);
+
+        let changed = self.wins.iter().zip(before.iter()).any(|(w, r)| w.rect != *r);
+        if changed {
+            self.redraw_all();
+        }
+
+        changed
+    }
+
+    pub fn redraw_all(&mut self) {
+        for win in self.wins.iter_mut() {
+            win.redraw_all();
+        }
+    }
+
+    //mark the lines from start of every window showing buffer buf_idx for redraw
+    pub fn set_dirty_start(&mut self, buf_idx: usize, start: usize) {
+        for win in self.wins.iter_mut().filter(|w| w.buf_idx == buf_idx) {
+            win.set_dirty_start(start);
+        }
+    }
+
+    pub fn split(&mut self, dir: SplitDir, buf: &TextBuffer, buf_pos: (usize, usize)) -> bool {
+        let rect = self.focused().rect;
+        let (size, min, sep) = match dir {
+            SplitDir::Horizontal => (rect.height, MIN_HEIGHT, 0),
+            SplitDir::Vertical => (rect.width, MIN_WIDTH, 1),
+        };
+
+        if size < min * 2 + sep {
+            return false;
+        }
+
+        let focused = self.focused();
+        let mut win = Window::new(buf, focused.buf_idx, buf_pos);
+        win.row_off = focused.row_off;
+        win.col_off = focused.col_off;
+
+        let new = self.wins.len();
+        self.wins.push(win);
+        self.layout.split(self.focus, new, dir, (size - sep) / 2);
+        true
+    }
+
+    //close the focused window, returns false when it is the only one
+    pub fn close(&mut self) -> bool {
+        if self.wins.len() == 1 {
+            return false;
+        }
+
+        let removed = self.focus;
+        let next = self.layout.remove(removed).unwrap();
+        self.wins.remove(removed);
+        self.layout.renumber(removed);
+        self.focus = if next > removed { next - 1 } else { next };
+        true
+    }
+
+    //close every window but the focused one
+    pub fn only(&mut self) {
+        let win = self.wins.swap_remove(self.focus);
+        self.wins = vec![win];
+        self.layout = Layout::Leaf(0);
+        self.focus = 0;
+    }
+
+    pub fn resize(&mut self, dir: SplitDir, delta: isize) -> bool {
+        self.layout.resize(self.focus, dir, delta)
+    }
+
+    pub fn set_focus(&mut self, idx: usize) {
+        if idx != self.focus {
+            self.wins[self.focus].status_bar.redraw = true;
+            self.wins[idx].status_bar.redraw = true;
+            self.focus = idx;
+        }
+    }
+
+    pub fn next_idx(&self) -> usize {
+        (self.focus + 1) % self.wins.len()
+    }
+
+    //the window next to the focused one in dir, at the screen position (row, col) of its cursor
+    pub fn neighbor(&self, dir: CursorDir, (row, col): (usize, usize)) -> Option<usize> {
+        let cur = self.focused().rect;
+
+        let candidates = self.wins.iter().enumerate().filter(|(i, w)| {
+            let r = w.rect;
+            let adjacent = match dir {
+                CursorDir::Left => r.right() + 1 == cur.left,
+                CursorDir::Right => cur.right() + 1 == r.left,
+                CursorDir::Up => r.bottom() == cur.top,
+                CursorDir::Down => cur.bottom() == r.top,
+            };
+            let overlaps = match dir {
+                CursorDir::Left | CursorDir::Right => r.top < cur.bottom() && cur.top < r.bottom(),
+                CursorDir::Up | CursorDir::Down => r.left < cur.right() && cur.left < r.right(),
+            };
+            *i != self.focus && adjacent && overlaps
+        });
+
+        let mut found = None;
+        for (i, w) in candidates {
+            let r = w.rect;
+            let hit = match dir {
+                CursorDir::Left | CursorDir::Right => r.top <= row && row < r.bottom(),
+                CursorDir::Up | CursorDir::Down => r.left <= col && col < r.right(),
+            };
+
+            if hit {
+                return Some(i);
+            }
+
+            found = found.or(Some(i));
+        }
+
+        found
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AREA: Rect = Rect { top: 0, left: 0, width: 80, height: 24 };
+
+    fn windows() -> Windows {
+        let buf = TextBuffer::empty();
+        let mut wins = Windows::new(Window::new(&buf, 0, (1, 1)));
+        wins.arrange(AREA);
+        wins
+    }
+
+    fn split(wins: &mut Windows, dir: SplitDir) {
+        assert!(wins.split(dir, &TextBuffer::empty(), (1, 1)));
+        wins.arrange(AREA);
+    }
+
+    fn rects(wins: &Windows) -> Vec<Rect> {
+        wins.iter().map(|w| w.rect).collect()
+    }
+
+    fn rect(top: usize, left: usize, width: usize, height: usize) -> Rect {
+        Rect { top, left, width, height }
+    }
+
+    #[test]
+    fn split_halves_the_focused_window() {
+        let mut wins = windows();
+        split(&mut wins, SplitDir::Horizontal);
+        assert_eq!(rects(&wins), [rect(0, 0, 80, 12), rect(12, 0, 80, 12)]);
+        assert_eq!(wins.focus(), 0);
+
+        //the separator column is taken from the right window
+        wins.set_focus(1);
+        split(&mut wins, SplitDir::Vertical);
+        assert_eq!(rects(&wins), [rect(0, 0, 80, 12), rect(12, 0, 39, 12), rect(12, 40, 40, 12)]);
+        assert_eq!(wins.separators(), [rect(12, 39, 1, 12)]);
+        assert_eq!(wins.focus(), 1);
+    }
+
+    #[test]
+    fn split_refuses_windows_too_small() {
+        let mut wins = windows();
+        wins.arrange(rect(0, 0, 80, 3));
+        assert!(!wins.split(SplitDir::Horizontal, &TextBuffer::empty(), (1, 1)));
+        assert_eq!(wins.len(), 1);
+    }
+
+    #[test]
+    fn close_gives_the_space_to_the_sibling() {
+        let mut wins = windows();
+        split(&mut wins, SplitDir::Horizontal);
+        wins.set_focus(1);
+        split(&mut wins, SplitDir::Vertical);
+
+        wins.set_focus(2);
+        assert!(wins.close());
+        wins.arrange(AREA);
+        assert_eq!(rects(&wins), [rect(0, 0, 80, 12), rect(12, 0, 80, 12)]);
+        assert_eq!(wins.focus(), 1);
+
+        //closing the first window of a split renumbers the rest
+        wins.set_focus(0);
+        assert!(wins.close());
+        wins.arrange(AREA);
+        assert_eq!(rects(&wins), [AREA]);
+        assert_eq!(wins.focus(), 0);
+
+        assert!(!wins.close());
+    }
+
+    #[test]
+    fn resize_moves_the_split_within_bounds() {
+        let mut wins = windows();
+        split(&mut wins, SplitDir::Horizontal);
+
+        assert!(wins.resize(SplitDir::Horizontal, 3));
+        wins.arrange(AREA);
+        assert_eq!(rects(&wins), [rect(0, 0, 80, 15), rect(15, 0, 80, 9)]);
+
+        //the other window keeps its smallest height
+        wins.resize(SplitDir::Horizontal, 100);
+        wins.arrange(AREA);
+        assert_eq!(rects(&wins), [rect(0, 0, 80, 22), rect(22, 0, 80, 2)]);
+
+        //no vertical split holds the window
+        assert!(!wins.resize(SplitDir::Vertical, 3));
+    }
+
+    #[test]
+    fn neighbor_prefers_the_window_at_the_cursor() {
+        let mut wins = windows();
+        split(&mut wins, SplitDir::Horizontal);
+        wins.set_focus(1);
+        split(&mut wins, SplitDir::Vertical);
+
+        wins.set_focus(0);
+        assert_eq!(wins.neighbor(CursorDir::Down, (5, 10)), Some(1));
+        assert_eq!(wins.neighbor(CursorDir::Down, (5, 50)), Some(2));
+        assert_eq!(wins.neighbor(CursorDir::Up, (5, 10)), None);
+
+        wins.set_focus(2);
+        assert_eq!(wins.neighbor(CursorDir::Left, (15, 50)), Some(1));
+        assert_eq!(wins.neighbor(CursorDir::Up, (15, 50)), Some(0));
+        assert_eq!(wins.neighbor(CursorDir::Right, (15, 50)), None);
+    }
+}